@@ -1,38 +1,413 @@
 // src/database.rs
 
-use mysql::*;
-use mysql::prelude::*;
+use std::collections::HashMap;
 use std::env;
+use std::sync::{Mutex, OnceLock};
+use std::sync::atomic::{AtomicBool, Ordering};
 use dotenv::dotenv;
-use anyhow::Result;
-use bcrypt::{hash, verify, DEFAULT_COST};
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc, Weekday};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
 use serde::{Serialize, Deserialize};
 use thiserror::Error;
 
+use crate::lockout::{self, ThrottleScope};
+use crate::offline_cache::OfflineCache;
+use crate::pay_period::{PayPeriod, PayPeriodConfig};
+use crate::password::PasswordHasher;
+use crate::permissions::Role;
+use crate::punch_queue::PunchQueue;
+use crate::qr::{QrClaim, QrCode, QrSigner};
+use crate::rules::{AttendanceRules, AttendanceStatus, DayAttendance, PUNCH_MATCH_HOURS};
+use crate::storage::{self, Storage, StorageError};
+use crate::timesheet::{Timesheet, TimesheetEvent, TimesheetRules};
+use crate::validation::{FieldError, Validator};
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct CreateUserRequest {
     pub username: String,
     pub password: String,
     pub email: Option<String>,
     pub full_name: Option<String>,
+    pub role: Option<Role>,
+    pub supervisor_id: Option<i32>,
 }
 
 #[derive(Error, Debug)]
 pub enum AuthError {
     #[error("Database error: {0}")]
-    Database(#[from] mysql::Error),
+    Database(#[from] StorageError),
     #[error("Invalid credentials")]
     InvalidCredentials,
     #[error("Username already exists")]
     UsernameTaken,
+    #[error("Badge number is already assigned to another user")]
+    BadgeTaken,
     #[error("Password hashing failed")]
     HashingError,
+    #[error("Session is invalid or has expired")]
+    InvalidSession,
+    #[error("You do not have permission to perform this action")]
+    Forbidden,
+    #[error("User not found")]
+    UserNotFound,
+    #[error("This account has been deactivated")]
+    AccountDeactivated,
+    #[error("You must change your password before logging in")]
+    PasswordChangeRequired,
+    #[error("User has attendance history; deactivate the account instead")]
+    UserHasHistory,
+    #[error("You cannot change the role or status of your own account")]
+    SelfModification,
+    #[error("Enter your PIN to finish punching")]
+    PinRequired,
+    #[error("QR code is not valid")]
+    InvalidQrCode,
+    #[error("QR code has expired; show the current code")]
+    QrCodeExpired,
+    #[error("QR code has already been used")]
+    QrCodeUsed,
+    #[error("Too many failed login attempts; try again after {until} UTC")]
+    AccountLocked { until: NaiveDateTime },
+    #[error("{}", .0.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join("; "))]
+    Validation(Vec<FieldError>),
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Error, Debug)]
+pub enum AttendanceError {
+    #[error("Database error: {0}")]
+    Database(#[from] StorageError),
+    #[error("User not found")]
+    UserNotFound,
+    #[error("Already clocked in")]
+    AlreadyClockedIn,
+    #[error("Not clocked in")]
+    NotClockedIn,
+    #[error("On a break; end the break first")]
+    OnBreak,
+    #[error("Not on a break")]
+    NotOnBreak,
+    #[error("Pay period {starts_on} to {ends_on} is locked")]
+    PeriodLocked { starts_on: NaiveDate, ends_on: NaiveDate },
+}
+
+#[derive(Error, Debug)]
+pub enum ScheduleError {
+    #[error("Database error: {0}")]
+    Database(#[from] StorageError),
+    #[error("Shift not found")]
+    ShiftNotFound,
+    #[error("Schedule assignment not found")]
+    AssignmentNotFound,
+    #[error("User not found")]
+    UserNotFound,
+    #[error("A shift with this name already exists")]
+    ShiftNameTaken,
+    #[error("Shift is still assigned to users; remove those assignments first")]
+    ShiftInUse,
+    #[error("Attendance exception not found")]
+    ExceptionNotFound,
+    #[error("{}", .0.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join("; "))]
+    Validation(Vec<FieldError>),
+}
+
+#[derive(Error, Debug)]
+pub enum CorrectionError {
+    #[error("Database error: {0}")]
+    Database(#[from] StorageError),
+    #[error("User not found")]
+    UserNotFound,
+    #[error("Punch not found")]
+    EventNotFound,
+    #[error("Correction request not found")]
+    CorrectionNotFound,
+    #[error("Correction request has already been decided")]
+    AlreadyDecided,
+    #[error("This punch has already been corrected")]
+    AlreadyCorrected,
+    #[error("Approving would leave the punches out of order: {0}")]
+    InvalidSequence(AttendanceError),
+    #[error("Pay period {starts_on} to {ends_on} is locked")]
+    PeriodLocked { starts_on: NaiveDate, ends_on: NaiveDate },
+    #[error("{}", .0.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join("; "))]
+    Validation(Vec<FieldError>),
+}
+
+#[derive(Error, Debug)]
+pub enum LeaveError {
+    #[error("Database error: {0}")]
+    Database(#[from] StorageError),
+    #[error("User not found")]
+    UserNotFound,
+    #[error("Leave request not found")]
+    LeaveNotFound,
+    #[error("Leave request has already been decided")]
+    AlreadyDecided,
+    #[error("Overlaps leave already requested for {starts_on} to {ends_on}")]
+    Overlaps { starts_on: NaiveDate, ends_on: NaiveDate },
+    #[error("Not enough {} leave: {available} days available, {requested} requested", .leave_type.as_str())]
+    InsufficientBalance { leave_type: LeaveType, available: f64, requested: f64 },
+    #[error("{}", .0.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join("; "))]
+    Validation(Vec<FieldError>),
+}
+
+#[derive(Error, Debug)]
+pub enum PayPeriodError {
+    #[error("Database error: {0}")]
+    Database(#[from] StorageError),
+    #[error("Pay period lock not found")]
+    LockNotFound,
+    #[error("Pay period {starts_on} to {ends_on} is already locked")]
+    AlreadyLocked { starts_on: NaiveDate, ends_on: NaiveDate },
+    #[error("Pay period is not locked")]
+    NotLocked,
+    #[error("{}", .0.iter().map(|e| e.message.as_str()).collect::<Vec<_>>().join("; "))]
+    Validation(Vec<FieldError>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum AttendanceEventType {
+    In,
+    Out,
+    BreakStart,
+    BreakEnd,
+}
+
+impl AttendanceEventType {
+    // The punch that follows this one when a kiosk toggles. A toggle during
+    // a break ends the break rather than the shift.
+    pub fn toggled(last: Option<Self>) -> Self {
+        match last {
+            None | Some(AttendanceEventType::Out) => AttendanceEventType::In,
+            Some(AttendanceEventType::In) | Some(AttendanceEventType::BreakEnd) => AttendanceEventType::Out,
+            Some(AttendanceEventType::BreakStart) => AttendanceEventType::BreakEnd,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttendanceEventType::In => "IN",
+            AttendanceEventType::Out => "OUT",
+            AttendanceEventType::BreakStart => "BREAK_START",
+            AttendanceEventType::BreakEnd => "BREAK_END",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "IN" => Some(AttendanceEventType::In),
+            "OUT" => Some(AttendanceEventType::Out),
+            "BREAK_START" => Some(AttendanceEventType::BreakStart),
+            "BREAK_END" => Some(AttendanceEventType::BreakEnd),
+            _ => None,
+        }
+    }
+}
+
+// Shared by every storage backend so punch rules cannot drift between them
+pub fn check_attendance_transition(
+    last_event: Option<AttendanceEventType>,
+    event_type: AttendanceEventType,
+) -> Result<(), AttendanceError> {
+    // A shift is open from a clock-in until the clock-out, breaks included
+    let shift_open = matches!(
+        last_event,
+        Some(AttendanceEventType::In | AttendanceEventType::BreakStart | AttendanceEventType::BreakEnd)
+    );
+    let on_break = last_event == Some(AttendanceEventType::BreakStart);
+
+    match event_type {
+        AttendanceEventType::In if shift_open => Err(AttendanceError::AlreadyClockedIn),
+        AttendanceEventType::Out | AttendanceEventType::BreakStart if !shift_open => {
+            Err(AttendanceError::NotClockedIn)
+        }
+        AttendanceEventType::Out | AttendanceEventType::BreakStart if on_break => Err(AttendanceError::OnBreak),
+        AttendanceEventType::BreakEnd if !on_break => Err(AttendanceError::NotOnBreak),
+        _ => Ok(()),
+    }
+}
+
+// Checks `events`, oldest first, as they would follow `last_event`
+pub fn check_attendance_sequence(
+    last_event: Option<AttendanceEventType>,
+    events: impl IntoIterator<Item = AttendanceEventType>,
+) -> Result<(), AttendanceError> {
+    let mut last_event = last_event;
+    for event_type in events {
+        check_attendance_transition(last_event, event_type)?;
+        last_event = Some(event_type);
+    }
+    
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttendanceEvent {
+    pub id: u64,
+    pub user_id: i32,
+    pub event_type: AttendanceEventType,
+    pub event_time: NaiveDateTime,
+    pub idempotency_key: Option<String>,
+    // Set on events written by an approved correction: the punch it
+    // replaces, if any, and the correction itself. Replaced punches stay
+    // stored but are left out of attendance reads.
+    pub corrects_event_id: Option<u64>,
+    pub correction_id: Option<i32>,
+    // True while the punch waits in the offline queue; `id` is only
+    // assigned once the server has accepted it
+    pub queued: bool,
+}
+
+// A punch on its way to storage. The idempotency key is generated once at
+// the terminal so replaying it from the offline queue never double-counts.
+#[derive(Debug, Clone)]
+pub struct Punch {
+    pub idempotency_key: String,
+    pub user_id: i32,
+    pub event_type: AttendanceEventType,
+    pub event_time: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DatabaseStatus {
+    pub available: bool,
+    pub backend: Option<String>,
+    pub error: Option<String>,
+    pub queued_punches: usize,
+}
+
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct SyncReport {
+    pub synced: usize,
+    pub rejected: usize,
+    pub remaining: usize,
+}
+
+#[derive(Debug)]
+pub struct NewUser {
+    pub username: String,
+    pub password_hash: String,
+    pub email: Option<String>,
+    pub full_name: Option<String>,
+    pub role: Role,
+    pub supervisor_id: Option<i32>,
+}
+
+// Inactive accounts cannot log in but keep their attendance history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    Active,
+    Inactive,
+}
+
+impl UserStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UserStatus::Active => "active",
+            UserStatus::Inactive => "inactive",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "active" => Some(UserStatus::Active),
+            "inactive" => Some(UserStatus::Inactive),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub id: i32,
     pub username: String,
+    pub email: Option<String>,
+    pub full_name: Option<String>,
+    pub role: Role,
+    pub status: UserStatus,
+    // Set by an admin password reset until the user picks a new password
+    pub must_change_password: bool,
+    pub created_at: Option<NaiveDateTime>,
+}
+
+// Columns the user list may be sorted by; anything else is rejected when
+// the request is deserialized, so no caller text reaches ORDER BY
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UserSortColumn {
+    Username,
+    FullName,
+    Email,
+    Role,
+    Status,
+    #[default]
+    CreatedAt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortDirection {
+    Asc,
+    #[default]
+    Desc,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ListUsersRequest {
+    // 1-based
+    pub page: Option<u32>,
+    pub page_size: Option<u32>,
+    // Matched against username, full name and email
+    pub search: Option<String>,
+    pub sort_by: Option<UserSortColumn>,
+    pub sort_direction: Option<SortDirection>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UserPage {
+    pub items: Vec<User>,
+    pub total: u64,
+    pub page: u32,
+    pub page_size: u32,
+}
+
+// A normalized `ListUsersRequest` as handed to storage
+#[derive(Debug)]
+pub struct UserQuery {
+    // A LIKE pattern using `!` as its escape character
+    pub search_pattern: Option<String>,
+    pub sort_by: UserSortColumn,
+    pub sort_direction: SortDirection,
+    pub limit: u32,
+    pub offset: u64,
+}
+
+// Replaces the editable profile fields; omitted optional fields are cleared
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UpdateUserRequest {
+    pub email: Option<String>,
+    pub full_name: Option<String>,
+    pub role: Role,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Session {
+    pub token: String,
+    pub expires_at: NaiveDateTime,
+    pub user: User,
+}
+
+// A QR code that identified its holder. Codes checked while the server is
+// unreachable cannot be redeemed yet, so the claim travels with the queued
+// punch and is redeemed when that syncs.
+#[derive(Debug)]
+pub struct QrPass {
+    pub user: User,
+    unredeemed: Option<QrClaim>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -47,151 +422,3227 @@ pub struct LoginRequest {
     pub password: String,
 }
 
-pub struct Database {
-    pool: Pool,
+// Proves the current password instead of a session, so it also serves
+// users whose login is blocked until they replace a temporary password
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub username: String,
+    pub current_password: String,
+    pub new_password: String,
 }
 
-impl Database {
-    pub fn create_user(&self, req: CreateUserRequest) -> Result<User, AuthError> {
-        let mut conn = self.pool.get_conn()?;
-        
-        // Check if username exists
-        let exists: Option<i32> = conn
-            .exec_first(
-                "SELECT id FROM users WHERE username = :username",
-                params! {
-                    "username" => &req.username,
-                }
-            )?;
-            
-        if exists.is_some() {
-            return Err(AuthError::UsernameTaken);
+// Replaces both kiosk credentials; an omitted field is cleared
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KioskCredentialsRequest {
+    pub badge_number: Option<String>,
+    pub pin: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BadgePunchRequest {
+    pub badge_number: String,
+    // Omitted for a badge scan; refused with PinRequired when the user has
+    // a PIN, so the kiosk can ask for it
+    pub pin: Option<String>,
+    // Omitted to toggle between clock-in and clock-out
+    pub event_type: Option<AttendanceEventType>,
+}
+
+// What a kiosk shows after a punch: who was recognised and what was recorded
+#[derive(Debug, Serialize, Deserialize)]
+pub struct KioskPunch {
+    pub user: User,
+    pub event: AttendanceEvent,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordReset {
+    pub user: User,
+    // Shown once to the admin; only its hash is stored
+    pub temporary_password: String,
+}
+
+// A named block of working time. Times are wall-clock times where the
+// terminal runs, and a segment that does not end after it starts runs past
+// midnight. Split shifts add a second segment later the same working day.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Shift {
+    pub id: i32,
+    pub name: String,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    pub split_start_time: Option<NaiveTime>,
+    pub split_end_time: Option<NaiveTime>,
+}
+
+impl Shift {
+    // Local start and end of each segment when the shift is worked on `date`
+    pub fn segments_on(&self, date: NaiveDate) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+        shift_segments(date, self.start_time, self.end_time, self.split_start_time.zip(self.split_end_time))
+    }
+}
+
+fn shift_segments(
+    date: NaiveDate,
+    start_time: NaiveTime,
+    end_time: NaiveTime,
+    split: Option<(NaiveTime, NaiveTime)>,
+) -> Vec<(NaiveDateTime, NaiveDateTime)> {
+    let segment = |starts_at: NaiveDateTime, end_time: NaiveTime| {
+        let mut ends_at = starts_at.date().and_time(end_time);
+        if ends_at <= starts_at {
+            ends_at += Duration::days(1);
         }
-        
-        // Hash password
-        let password_hash = hash(req.password.as_bytes(), DEFAULT_COST)
-            .map_err(|_| AuthError::HashingError)?;
-            
-        // Insert user with optional fields
-        let query = "INSERT INTO users 
-            (username, password_hash, email, full_name) 
-            VALUES (:username, :password_hash, :email, :full_name)";
-        
-        conn.exec_drop(
-            query,
-            params! {
-                "username" => &req.username,
-                "password_hash" => &password_hash,
-                "email" => &req.email,
-                "full_name" => &req.full_name,
+        (starts_at, ends_at)
+    };
+    
+    let first = segment(date.and_time(start_time), end_time);
+    let mut segments = vec![first];
+    
+    if let Some((split_start, split_end)) = split {
+        // The second segment follows the first, past midnight if need be
+        let mut starts_at = date.and_time(split_start);
+        while starts_at < first.1 {
+            starts_at += Duration::days(1);
+        }
+        segments.push(segment(starts_at, split_end));
+    }
+    
+    segments
+}
+
+// Both split times are given or neither
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ShiftRequest {
+    pub name: String,
+    pub start_time: NaiveTime,
+    pub end_time: NaiveTime,
+    pub split_start_time: Option<NaiveTime>,
+    pub split_end_time: Option<NaiveTime>,
+}
+
+// Which days an assignment's shift is worked
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SchedulePattern {
+    // The same weekdays every week
+    Weekly { weekdays: Vec<Weekday> },
+    // `days_on` working days, then `days_off` rest days, repeating from the
+    // assignment's first day
+    Rotating { days_on: u32, days_off: u32 },
+}
+
+impl SchedulePattern {
+    pub fn works_on(&self, anchor: NaiveDate, date: NaiveDate) -> bool {
+        match self {
+            SchedulePattern::Weekly { weekdays } => weekdays.contains(&date.weekday()),
+            SchedulePattern::Rotating { days_on, days_off } => {
+                let cycle = i64::from(days_on + days_off);
+                cycle > 0 && (date - anchor).num_days().rem_euclid(cycle) < i64::from(*days_on)
             }
-        )?;
-        
-        let id = conn.last_insert_id() as i32;
-        
-        Ok(User {
-            id,
-            username: req.username,
-        })
+        }
     }
+}
 
-    pub fn get_all_users(&self) -> Result<Vec<User>, AuthError> {
-        let mut conn = self.pool.get_conn()?;
-        
-        let users: Vec<User> = conn
-            .query("SELECT id, username FROM users ORDER BY created_at DESC")?
-            .into_iter()
-            .map(|row| {
-                let (id, username): (i32, String) = mysql::from_row(row);
-                User { id, username }
-            })
-            .collect();
-        
-        Ok(users)
+// Puts a user on a shift from `starts_on` until `ends_on` (inclusive; open
+// ended when None). A user may hold several assignments at once.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleAssignment {
+    pub id: i32,
+    pub user_id: i32,
+    pub shift_id: i32,
+    pub pattern: SchedulePattern,
+    pub starts_on: NaiveDate,
+    pub ends_on: Option<NaiveDate>,
+}
+
+impl ScheduleAssignment {
+    pub fn works_on(&self, date: NaiveDate) -> bool {
+        date >= self.starts_on
+            && self.ends_on.is_none_or(|ends_on| date <= ends_on)
+            && self.pattern.works_on(self.starts_on, date)
     }
+}
 
-    pub fn new() -> Result<Self> {
-        dotenv().ok();
-        
-        let url = env::var("DATABASE_URL")
-            .expect("DATABASE_URL must be set");
-            
-        let pool = Pool::new(url.as_str())?;
-        
-        Ok(Database { pool })
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ScheduleAssignmentRequest {
+    pub user_id: i32,
+    pub shift_id: i32,
+    pub pattern: SchedulePattern,
+    pub starts_on: NaiveDate,
+    pub ends_on: Option<NaiveDate>,
+}
+
+// UTC bounds of one worked segment
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShiftSegment {
+    pub starts_at: NaiveDateTime,
+    pub ends_at: NaiveDateTime,
+}
+
+// One occurrence of an assigned shift. `date` is the local working day it
+// starts on; the segments may run into the next day.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledShift {
+    pub assignment_id: i32,
+    pub user_id: i32,
+    pub shift: Shift,
+    pub date: NaiveDate,
+    pub segments: Vec<ShiftSegment>,
+}
+
+// Someone whose schedule has them working at the requested moment
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OnSiteWorker {
+    pub user: User,
+    pub shift: Shift,
+    pub segment: ShiftSegment,
+}
+
+// A late arrival, early leave, absence or unscheduled day kept for a
+// supervisor to review. Recomputing attendance replaces unreviewed
+// exceptions; reviewed ones stay as they were.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttendanceException {
+    pub id: i32,
+    pub user_id: i32,
+    pub work_date: NaiveDate,
+    pub kind: AttendanceStatus,
+    // How late or how early, for those kinds
+    pub minutes: Option<i64>,
+    pub detected_at: NaiveDateTime,
+    pub reviewed_by: Option<i32>,
+    pub reviewed_at: Option<NaiveDateTime>,
+    pub review_note: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct NewAttendanceException {
+    pub work_date: NaiveDate,
+    pub kind: AttendanceStatus,
+    pub minutes: Option<i64>,
+}
+
+// A pay period frozen by payroll. Unlocking keeps the row, so who locked
+// and who reopened a period, and why, stays on record.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeriodLock {
+    pub id: i32,
+    pub starts_on: NaiveDate,
+    pub ends_on: NaiveDate,
+    pub locked_by: i32,
+    pub locked_at: NaiveDateTime,
+    pub unlocked_by: Option<i32>,
+    pub unlocked_at: Option<NaiveDateTime>,
+    pub unlock_reason: Option<String>,
+}
+
+impl PeriodLock {
+    pub fn is_active(&self) -> bool {
+        self.unlocked_at.is_none()
     }
-    
-    pub fn init(&self) -> Result<()> {
-        let mut conn = self.pool.get_conn()?;
-        
-        conn.query_drop(
-            r"CREATE TABLE IF NOT EXISTS users (
-                id INT PRIMARY KEY AUTO_INCREMENT,
-                username VARCHAR(255) UNIQUE NOT NULL,
-                password_hash VARCHAR(255) NOT NULL,
-                email VARCHAR(255),
-                full_name VARCHAR(255),
-                created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-            )"
-        )?;
-        
-        Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CorrectionKind {
+    // Adds a punch that was never taken
+    MissingPunch,
+    // Moves an existing punch to another time
+    WrongTime,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CorrectionStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl CorrectionStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CorrectionStatus::Pending => "pending",
+            CorrectionStatus::Approved => "approved",
+            CorrectionStatus::Rejected => "rejected",
+        }
     }
 
-    pub fn register_user(&self, req: RegisterRequest) -> Result<User, AuthError> {
-        let mut conn = self.pool.get_conn()?;
-        
-        // Check if username exists
-        let exists: Option<i32> = conn
-            .exec_first(
-                "SELECT id FROM users WHERE username = :username",
-                params! {
-                    "username" => &req.username,
-                }
-            )?;
-            
-        if exists.is_some() {
-            return Err(AuthError::UsernameTaken);
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(CorrectionStatus::Pending),
+            "approved" => Some(CorrectionStatus::Approved),
+            "rejected" => Some(CorrectionStatus::Rejected),
+            _ => None,
         }
-        
-        // Hash password
-        let password_hash = hash(req.password.as_bytes(), DEFAULT_COST)
-            .map_err(|_| AuthError::HashingError)?;
-            
-        // Insert user
-        conn.exec_drop(
-            "INSERT INTO users (username, password_hash) VALUES (:username, :password_hash)",
-            params! {
-                "username" => &req.username,
-                "password_hash" => &password_hash,
-            }
-        )?;
-        
-        let id = conn.last_insert_id() as i32;
-        
-        Ok(User {
-            id,
-            username: req.username,
-        })
     }
-    
-    pub fn login_user(&self, req: LoginRequest) -> Result<User, AuthError> {
-        let mut conn = self.pool.get_conn()?;
-        
-        let (id, username, password_hash): (i32, String, String) = conn
-            .exec_first(
-                "SELECT id, username, password_hash FROM users WHERE username = :username",
-                params! {
-                    "username" => &req.username,
-                }
-            )?
-            .ok_or(AuthError::InvalidCredentials)?;
-            
-        if !verify(req.password.as_bytes(), &password_hash)
-            .map_err(|_| AuthError::HashingError)? {
-            return Err(AuthError::InvalidCredentials);
+}
+
+// What an employee asks to have fixed. Times are UTC, like punches.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CorrectionRequest {
+    pub kind: CorrectionKind,
+    // The punch to move, for a wrong time
+    pub event_id: Option<u64>,
+    // Required for a missing punch; a moved punch keeps its type
+    pub event_type: Option<AttendanceEventType>,
+    pub event_time: NaiveDateTime,
+    pub reason: String,
+}
+
+#[derive(Debug)]
+pub struct NewAttendanceCorrection {
+    pub event_id: Option<u64>,
+    pub event_type: AttendanceEventType,
+    pub event_time: NaiveDateTime,
+    pub reason: String,
+}
+
+// An approved correction points at the event it wrote; the punch it
+// corrects is never changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttendanceCorrection {
+    pub id: i32,
+    pub user_id: i32,
+    pub kind: CorrectionKind,
+    pub event_id: Option<u64>,
+    pub event_type: AttendanceEventType,
+    pub event_time: NaiveDateTime,
+    pub reason: String,
+    pub status: CorrectionStatus,
+    pub requested_at: NaiveDateTime,
+    pub decided_by: Option<i32>,
+    pub decided_at: Option<NaiveDateTime>,
+    pub decision_note: Option<String>,
+    pub adjusted_event_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaveType {
+    Vacation,
+    Sick,
+    Unpaid,
+}
+
+impl LeaveType {
+    // The types drawn from an accrued balance, in display order
+    pub const ACCRUED: [LeaveType; 2] = [LeaveType::Vacation, LeaveType::Sick];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LeaveType::Vacation => "vacation",
+            LeaveType::Sick => "sick",
+            LeaveType::Unpaid => "unpaid",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "vacation" => Some(LeaveType::Vacation),
+            "sick" => Some(LeaveType::Sick),
+            "unpaid" => Some(LeaveType::Unpaid),
+            _ => None,
+        }
+    }
+
+    pub fn is_accrued(&self) -> bool {
+        Self::ACCRUED.contains(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LeaveStatus {
+    Pending,
+    Approved,
+    Rejected,
+}
+
+impl LeaveStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LeaveStatus::Pending => "pending",
+            LeaveStatus::Approved => "approved",
+            LeaveStatus::Rejected => "rejected",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "pending" => Some(LeaveStatus::Pending),
+            "approved" => Some(LeaveStatus::Approved),
+            "rejected" => Some(LeaveStatus::Rejected),
+            _ => None,
+        }
+    }
+}
+
+// Days credited to (or, when negative, taken from) a user's balance
+#[derive(Debug, Clone, Deserialize)]
+pub struct LeaveAccrualRequest {
+    pub leave_type: LeaveType,
+    pub days: f64,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LeaveAccrual {
+    pub id: i32,
+    pub user_id: i32,
+    pub leave_type: LeaveType,
+    pub days: f64,
+    pub note: Option<String>,
+    pub created_by: i32,
+    pub created_at: NaiveDateTime,
+}
+
+// Pending requests already count against what is available, so a balance
+// cannot be promised twice
+#[derive(Debug, Clone, Serialize)]
+pub struct LeaveBalance {
+    pub leave_type: LeaveType,
+    pub accrued: f64,
+    pub used: f64,
+    pub pending: f64,
+    pub available: f64,
+}
+
+// Whole local days, both ends included
+#[derive(Debug, Clone, Deserialize)]
+pub struct LeaveRequest {
+    pub leave_type: LeaveType,
+    pub starts_on: NaiveDate,
+    pub ends_on: NaiveDate,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct NewLeave {
+    pub leave_type: LeaveType,
+    pub starts_on: NaiveDate,
+    pub ends_on: NaiveDate,
+    pub days: f64,
+    pub reason: Option<String>,
+}
+
+// `days` counts the scheduled working days the leave covered when it was
+// requested; later schedule changes do not alter it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Leave {
+    pub id: i32,
+    pub user_id: i32,
+    pub leave_type: LeaveType,
+    pub starts_on: NaiveDate,
+    pub ends_on: NaiveDate,
+    pub days: f64,
+    pub reason: Option<String>,
+    pub status: LeaveStatus,
+    pub requested_at: NaiveDateTime,
+    pub decided_by: Option<i32>,
+    pub decided_at: Option<NaiveDateTime>,
+    pub decision_note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PayPeriodStatus {
+    pub starts_on: NaiveDate,
+    pub ends_on: NaiveDate,
+    // The lock in force, if any
+    pub lock: Option<PeriodLock>,
+}
+
+// The configured storage, opened once DATABASE_URL resolves
+struct Backend {
+    storage: Box<dyn Storage>,
+    // Only present for server backends, which can become unreachable
+    queue: Option<PunchQueue>,
+    cache: Option<OfflineCache>,
+}
+
+impl Backend {
+    fn open(url: &str) -> Result<Self> {
+        let storage = storage::connect(url)?;
+        
+        if storage.is_embedded() {
+            return Ok(Backend { storage, queue: None, cache: None });
         }
         
-        Ok(User { id, username })
+        let queue_path = env::var("PUNCH_QUEUE_PATH")
+            .unwrap_or_else(|_| DEFAULT_PUNCH_QUEUE_PATH.to_string());
+        let cache_path = env::var("OFFLINE_CACHE_PATH")
+            .unwrap_or_else(|_| DEFAULT_OFFLINE_CACHE_PATH.to_string());
+            
+        Backend::with_local_state(storage, &queue_path, &cache_path)
     }
-}
\ No newline at end of file
+
+    fn with_local_state(storage: Box<dyn Storage>, queue_path: &str, cache_path: &str) -> Result<Self> {
+        Ok(Backend {
+            storage,
+            queue: Some(PunchQueue::open(queue_path)?),
+            cache: Some(OfflineCache::open(cache_path)?),
+        })
+    }
+}
+
+// Starts in a degraded state when the database cannot be reached; commands
+// report the reason until a background retry of `init` succeeds.
+pub struct Database {
+    backend: OnceLock<Backend>,
+    initialized: AtomicBool,
+    last_error: Mutex<Option<String>>,
+    // Keys this terminal's failed-login counter
+    device_id: String,
+    hasher: PasswordHasher,
+    qr_signer: QrSigner,
+    attendance_rules: AttendanceRules,
+    timesheet_rules: TimesheetRules,
+    pay_periods: PayPeriodConfig,
+}
+
+const SESSION_TTL_HOURS: i64 = 12;
+const TEMPORARY_PASSWORD_LEN: usize = 12;
+// No look-alike characters, since temporary passwords are read aloud or copied by hand
+const TEMPORARY_PASSWORD_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyzABCDEFGHJKMNPQRSTUVWXYZ23456789";
+const DEFAULT_USER_PAGE_SIZE: u32 = 50;
+const MAX_USER_PAGE_SIZE: u32 = 200;
+const DEFAULT_PUNCH_QUEUE_PATH: &str = "punch_queue.db";
+const DEFAULT_OFFLINE_CACHE_PATH: &str = "offline_cache.db";
+// Redemptions are kept long after the codes expire, so a code used on a
+// kiosk that was offline can still be caught as a replay when it syncs
+const QR_REDEMPTION_RETENTION_DAYS: i64 = 7;
+
+// Shift times are local to the terminal, while events are stored as UTC.
+// A time skipped by a DST change is taken to mean the hour after it.
+fn local_to_utc(local: NaiveDateTime) -> NaiveDateTime {
+    Local
+        .from_local_datetime(&local)
+        .earliest()
+        .or_else(|| Local.from_local_datetime(&(local + Duration::hours(1))).earliest())
+        .map_or(local, |local| local.naive_utc())
+}
+
+fn utc_to_local(utc: NaiveDateTime) -> NaiveDateTime {
+    Local.from_utc_datetime(&utc).naive_local()
+}
+
+// Timestamps are stored as UTC at second precision so values returned to
+// callers match the stored rows exactly
+pub fn now_utc() -> NaiveDateTime {
+    Utc::now().naive_utc().with_nanosecond(0).unwrap_or_default()
+}
+
+// Badge numbers must be found by value, so they get an unsalted digest
+// rather than a password hash; PINs are salted and hashed like passwords
+fn hash_badge(badge_number: &str) -> String {
+    hex::encode(Sha256::digest(badge_number.trim().to_uppercase().as_bytes()))
+}
+
+// Only a digest of the token is stored, so a leaked sessions table cannot
+// be replayed against the app
+fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+// Matches `text` anywhere, with LIKE wildcards in it taken literally
+fn like_contains_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len() + 2);
+    pattern.push('%');
+    for c in text.chars() {
+        if matches!(c, '!' | '%' | '_') {
+            pattern.push('!');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+fn temporary_password() -> String {
+    (0..TEMPORARY_PASSWORD_LEN)
+        .map(|_| {
+            let index = OsRng.next_u32() as usize % TEMPORARY_PASSWORD_ALPHABET.len();
+            TEMPORARY_PASSWORD_ALPHABET[index] as char
+        })
+        .collect()
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    OsRng.fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+impl Database {
+    pub fn create_user(&self, req: CreateUserRequest) -> Result<User, AuthError> {
+        let role = req.role.unwrap_or(Role::Employee);
+        self.insert_validated_user(req, role)
+    }
+
+    // The single path through which accounts are created, whether by an
+    // admin or through self-registration
+    fn insert_validated_user(&self, req: CreateUserRequest, role: Role) -> Result<User, AuthError> {
+        let username = req.username.trim().to_string();
+        // Blank optional fields from the form mean "not provided"
+        let email = req.email.map(|e| e.trim().to_string()).filter(|e| !e.is_empty());
+        let full_name = req.full_name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty());
+        
+        Validator::default()
+            .username(&username)
+            .email(email.as_deref())
+            .full_name(full_name.as_deref())
+            .password("password", &req.password, &username)
+            .finish()
+            .map_err(AuthError::Validation)?;
+            
+        let password_hash = self.hasher.hash(&req.password)?;
+            
+        let new_user = NewUser {
+            username,
+            password_hash,
+            email,
+            full_name,
+            role,
+            supervisor_id: req.supervisor_id,
+        };
+        
+        let id = match self.storage()?.insert_user(&new_user) {
+            Err(StorageError::Duplicate(_)) => return Err(AuthError::UsernameTaken),
+            result => result?,
+        };
+        
+        self.get_user(id)
+    }
+
+    pub fn get_all_users(&self) -> Result<Vec<User>, AuthError> {
+        Ok(self.storage()?.list_users()?)
+    }
+
+    pub fn list_users(&self, req: ListUsersRequest) -> Result<UserPage, AuthError> {
+        let page = req.page.unwrap_or(1).max(1);
+        let page_size = req
+            .page_size
+            .unwrap_or(DEFAULT_USER_PAGE_SIZE)
+            .clamp(1, MAX_USER_PAGE_SIZE);
+            
+        let query = UserQuery {
+            search_pattern: req
+                .search
+                .as_deref()
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(like_contains_pattern),
+            sort_by: req.sort_by.unwrap_or_default(),
+            sort_direction: req.sort_direction.unwrap_or_default(),
+            limit: page_size,
+            offset: u64::from(page - 1) * u64::from(page_size),
+        };
+        
+        let (items, total) = self.storage()?.query_users(&query)?;
+        
+        Ok(UserPage {
+            items,
+            total,
+            page,
+            page_size,
+        })
+    }
+
+    pub fn get_user(&self, user_id: i32) -> Result<User, AuthError> {
+        self.storage()?
+            .find_user(user_id)?
+            .ok_or(AuthError::UserNotFound)
+    }
+
+    pub fn update_user(&self, user_id: i32, req: UpdateUserRequest) -> Result<User, AuthError> {
+        let req = UpdateUserRequest {
+            email: req.email.map(|e| e.trim().to_string()).filter(|e| !e.is_empty()),
+            full_name: req.full_name.map(|n| n.trim().to_string()).filter(|n| !n.is_empty()),
+            role: req.role,
+        };
+        
+        Validator::default()
+            .email(req.email.as_deref())
+            .full_name(req.full_name.as_deref())
+            .finish()
+            .map_err(AuthError::Validation)?;
+            
+        if !self.storage()?.update_user(user_id, &req)? {
+            return Err(AuthError::UserNotFound);
+        }
+        
+        self.get_user(user_id)
+    }
+
+    // Soft delete: the account can no longer log in and its sessions end,
+    // but its attendance history stays intact
+    pub fn deactivate_user(&self, user_id: i32) -> Result<User, AuthError> {
+        let storage = self.storage()?;
+        
+        if !storage.set_user_status(user_id, UserStatus::Inactive)? {
+            return Err(AuthError::UserNotFound);
+        }
+        
+        storage.revoke_user_sessions(user_id, now_utc())?;
+        self.forget_cached_user(user_id);
+        
+        self.get_user(user_id)
+    }
+
+    // Only for accounts without attendance history, e.g. ones created by
+    // mistake; returns the record as it was before deletion
+    pub fn delete_user(&self, user_id: i32) -> Result<User, AuthError> {
+        let user = self.get_user(user_id)?;
+        let storage = self.storage()?;
+        
+        match storage.delete_user(user_id) {
+            Ok(true) => {}
+            Ok(false) => return Err(AuthError::UserNotFound),
+            Err(StorageError::Referenced(_)) => return Err(AuthError::UserHasHistory),
+            Err(e) => return Err(e.into()),
+        }
+        
+        storage.clear_login_failures(ThrottleScope::Username, &lockout::username_subject(&user.username))?;
+        self.forget_cached_user(user_id);
+        
+        Ok(user)
+    }
+
+    // The server has already changed by the time these run, so a failing
+    // local cache is only reported
+    fn forget_cached_sessions(&self, user_id: i32) {
+        if let Some(Err(e)) = self.offline_cache().map(|cache| cache.forget_sessions(user_id)) {
+            eprintln!("Failed to forget cached sessions of user {}: {}", user_id, e);
+        }
+    }
+
+    fn forget_cached_user(&self, user_id: i32) {
+        if let Some(Err(e)) = self.offline_cache().map(|cache| cache.forget_user(user_id)) {
+            eprintln!("Failed to forget cached user {}: {}", user_id, e);
+        }
+    }
+
+    pub fn new() -> Self {
+        dotenv().ok();
+        
+        let database = Database::new_unconnected();
+        
+        if let Err(e) = database.connect() {
+            database.set_last_error(Some(e.to_string()));
+        }
+        
+        database
+    }
+
+    fn new_unconnected() -> Self {
+        Database {
+            backend: OnceLock::new(),
+            initialized: AtomicBool::new(false),
+            last_error: Mutex::new(None),
+            device_id: lockout::device_id(),
+            hasher: PasswordHasher::from_env(),
+            qr_signer: QrSigner::from_env(),
+            attendance_rules: AttendanceRules::from_env(),
+            timesheet_rules: TimesheetRules::from_env(),
+            pay_periods: PayPeriodConfig::from_env(),
+        }
+    }
+
+    // Connects to `url` instead of DATABASE_URL and runs migrations
+    #[cfg(test)]
+    pub fn open(url: &str) -> Result<Self> {
+        let database = Database::new_unconnected();
+        let _ = database.backend.set(Backend::open(url)?);
+        database.init()?;
+        Ok(database)
+    }
+
+    // Opens `url` the way a kiosk opens a server backend, keeping the punch
+    // queue and offline cache in `local_dir`. Unless `online`, migrations are
+    // not run and every storage call fails as if the server were unreachable.
+    #[cfg(test)]
+    pub fn open_kiosk(url: &str, local_dir: &std::path::Path, online: bool) -> Result<Self> {
+        let storage = storage::connect(url)?;
+        let queue_path = local_dir.join("punch_queue.db");
+        let cache_path = local_dir.join("offline_cache.db");
+        let backend = Backend::with_local_state(
+            storage,
+            &queue_path.to_string_lossy(),
+            &cache_path.to_string_lossy(),
+        )?;
+        
+        let database = Database::new_unconnected();
+        let _ = database.backend.set(backend);
+        if online {
+            database.init()?;
+        } else {
+            database.set_last_error(Some("Server unreachable".to_string()));
+        }
+        Ok(database)
+    }
+
+    // Resolves DATABASE_URL and opens the backend; safe to call repeatedly
+    // so a missing or fixed .env is picked up by later retries
+    fn connect(&self) -> Result<&Backend> {
+        if let Some(backend) = self.backend.get() {
+            return Ok(backend);
+        }
+        
+        dotenv().ok();
+        
+        let url = env::var("DATABASE_URL")
+            .map_err(|_| anyhow!("DATABASE_URL is not set; add it to the .env file"))?;
+            
+        let backend = Backend::open(&url)?;
+        
+        Ok(self.backend.get_or_init(|| backend))
+    }
+    
+    pub fn init(&self) -> Result<()> {
+        let result = self
+            .connect()
+            .and_then(|backend| Ok(backend.storage.migrate()?));
+            
+        let applied = match result {
+            Ok(applied) => applied,
+            Err(e) => {
+                self.set_last_error(Some(e.to_string()));
+                return Err(e);
+            }
+        };
+        
+        if !applied.is_empty() {
+            println!("✓ Applied database migrations: {:?}", applied);
+        }
+        
+        self.set_last_error(None);
+        self.initialized.store(true, Ordering::SeqCst);
+        
+        Ok(())
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.initialized.load(Ordering::SeqCst)
+    }
+
+    // Embedded databases need no Docker-hosted server
+    pub fn is_embedded(&self) -> bool {
+        self.backend
+            .get()
+            .is_some_and(|backend| backend.storage.is_embedded())
+    }
+
+    pub fn status(&self) -> DatabaseStatus {
+        let backend = self.backend.get();
+        
+        DatabaseStatus {
+            available: self.is_initialized(),
+            backend: backend.map(|b| b.storage.name().to_string()),
+            error: self.last_error(),
+            queued_punches: backend
+                .and_then(|b| b.queue.as_ref())
+                .and_then(|queue| queue.pending_count().ok())
+                .unwrap_or(0),
+        }
+    }
+
+    fn last_error(&self) -> Option<String> {
+        self.last_error.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    fn set_last_error(&self, error: Option<String>) {
+        *self.last_error.lock().unwrap_or_else(|e| e.into_inner()) = error;
+    }
+
+    // Storage is only handed out once migrations have run against it
+    fn storage(&self) -> Result<&dyn Storage, StorageError> {
+        match self.backend.get() {
+            Some(backend) if self.is_initialized() => Ok(backend.storage.as_ref()),
+            _ => Err(StorageError::Unavailable(
+                self.last_error().unwrap_or_else(|| "Database is not ready".to_string()),
+            )),
+        }
+    }
+
+    fn queue(&self) -> Option<&PunchQueue> {
+        self.backend.get().and_then(|backend| backend.queue.as_ref())
+    }
+
+    fn offline_cache(&self) -> Option<&OfflineCache> {
+        self.backend.get().and_then(|backend| backend.cache.as_ref())
+    }
+
+    pub fn register_user(&self, req: RegisterRequest) -> Result<User, AuthError> {
+        // The very first account bootstraps the system as its administrator
+        let role = if self.storage()?.count_users()? == 0 {
+            Role::Admin
+        } else {
+            Role::Employee
+        };
+        
+        let req = CreateUserRequest {
+            username: req.username,
+            password: req.password,
+            email: None,
+            full_name: None,
+            role: None,
+            supervisor_id: None,
+        };
+        
+        self.insert_validated_user(req, role)
+    }
+    
+    pub fn login_user(&self, req: LoginRequest) -> Result<Session, AuthError> {
+        let user = self.authenticate(&req.username, &req.password)?;
+        
+        if user.must_change_password {
+            return Err(AuthError::PasswordChangeRequired);
+        }
+        
+        // Issue an opaque session token for subsequent commands
+        let token = random_hex(32);
+        
+        let created_at = now_utc();
+        let expires_at = created_at + Duration::hours(SESSION_TTL_HOURS);
+        
+        self.storage()?.insert_session(&hash_token(&token), user.id, created_at, expires_at)?;
+        
+        Ok(Session {
+            token,
+            expires_at,
+            user,
+        })
+    }
+
+    pub fn change_password(&self, req: ChangePasswordRequest) -> Result<User, AuthError> {
+        let user = self.authenticate(&req.username, &req.current_password)?;
+        
+        let mut validator = Validator::default();
+        validator.password("new_password", &req.new_password, &user.username);
+        if req.new_password == req.current_password {
+            validator.push("new_password", "New password must differ from the current one");
+        }
+        validator.finish().map_err(AuthError::Validation)?;
+        
+        let password_hash = self.hasher.hash(&req.new_password)?;
+        self.replace_password(user.id, &password_hash, false)
+    }
+
+    // Sets a generated password the user must replace at their next login
+    pub fn reset_password(&self, user_id: i32) -> Result<PasswordReset, AuthError> {
+        let temporary_password = temporary_password();
+        let password_hash = self.hasher.hash(&temporary_password)?;
+        let user = self.replace_password(user_id, &password_hash, true)?;
+        
+        Ok(PasswordReset {
+            user,
+            temporary_password,
+        })
+    }
+
+    // Every session of the user ends, so a stolen session cannot outlive
+    // the password it was opened with
+    fn replace_password(&self, user_id: i32, password_hash: &str, must_change: bool) -> Result<User, AuthError> {
+        let storage = self.storage()?;
+        
+        if !storage.set_password(user_id, password_hash, must_change)? {
+            return Err(AuthError::UserNotFound);
+        }
+        
+        storage.revoke_user_sessions(user_id, now_utc())?;
+        self.forget_cached_sessions(user_id);
+        
+        self.get_user(user_id)
+    }
+
+    // Checks a username and password under the login throttles and returns
+    // the active user they belong to
+    fn authenticate(&self, username: &str, password: &str) -> Result<User, AuthError> {
+        let storage = self.storage()?;
+        let throttles = [
+            (ThrottleScope::Username, lockout::username_subject(username)),
+            (ThrottleScope::Device, self.device_id.clone()),
+        ];
+        
+        // A locked account stays locked even for the right password
+        self.check_login_locks(&throttles)?;
+        
+        let credentials = storage.find_user_credentials(username)?;
+        let verified = match &credentials {
+            Some((_, password_hash)) => self.hasher.verify(password, password_hash)?,
+            None => self.hasher.verify_dummy(password),
+        };
+        
+        let (user, password_hash) = match credentials {
+            Some(credentials) if verified => credentials,
+            _ => return Err(self.record_login_failure(&throttles)?),
+        };
+        
+        self.record_login_success(&throttles)?;
+        
+        // Only reported once the password is proven, so it cannot be probed
+        if user.status == UserStatus::Inactive {
+            return Err(AuthError::AccountDeactivated);
+        }
+        
+        // The plaintext is only available now, so upgrade outdated hashes
+        // while we have it; a failed upgrade must not block the login
+        if self.hasher.needs_rehash(&password_hash) {
+            let rehashed = self
+                .hasher
+                .hash(password)
+                .and_then(|hash| Ok(storage.update_password_hash(user.id, &hash)?));
+            if let Err(e) = rehashed {
+                eprintln!("Failed to upgrade password hash for user {}: {}", user.id, e);
+            }
+        }
+        
+        Ok(user)
+    }
+
+    pub fn set_kiosk_credentials(&self, user_id: i32, req: KioskCredentialsRequest) -> Result<User, AuthError> {
+        let badge_number = req.badge_number.map(|b| b.trim().to_string()).filter(|b| !b.is_empty());
+        let pin = req.pin.filter(|p| !p.is_empty());
+        
+        Validator::default()
+            .badge_number(badge_number.as_deref())
+            .pin(pin.as_deref())
+            .finish()
+            .map_err(AuthError::Validation)?;
+            
+        let badge_hash = badge_number.as_deref().map(hash_badge);
+        let pin_hash = pin.as_deref().map(|pin| self.hasher.hash(pin)).transpose()?;
+        
+        match self.storage()?.set_kiosk_credentials(user_id, badge_hash.as_deref(), pin_hash.as_deref()) {
+            Ok(true) => {
+                if let Some(Err(e)) = self
+                    .offline_cache()
+                    .map(|cache| cache.set_kiosk_credentials(user_id, badge_hash.as_deref(), pin_hash.as_deref()))
+                {
+                    eprintln!("Failed to cache kiosk credentials of user {}: {}", user_id, e);
+                }
+                self.get_user(user_id)
+            }
+            Ok(false) => Err(AuthError::UserNotFound),
+            Err(StorageError::Duplicate(_)) => Err(AuthError::BadgeTaken),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Badge plus PIN, checked under the same throttles as password logins.
+    // The result only authorizes a punch; no session is issued.
+    // `pin` is None for badges read by a scanner, where holding the badge is
+    // the only proof; otherwise the user must have a PIN and it must match
+    pub fn authenticate_badge(&self, badge_number: &str, pin: Option<&str>) -> Result<User, AuthError> {
+        let storage = match (self.storage(), self.offline_cache()) {
+            (Ok(storage), _) => storage,
+            (Err(StorageError::Unavailable(_)), Some(cache)) => {
+                return self.authenticate_badge_offline(cache, badge_number, pin);
+            }
+            (Err(e), _) => return Err(e.into()),
+        };
+        let device = (ThrottleScope::Device, self.device_id.clone());
+        self.check_login_locks(std::slice::from_ref(&device))?;
+        
+        let Some((user, pin_hash)) = storage.find_kiosk_credentials(&hash_badge(badge_number))? else {
+            return Err(self.record_login_failure(&[device])?);
+        };
+        
+        let throttles = [
+            (ThrottleScope::Username, lockout::username_subject(&user.username)),
+            device,
+        ];
+        self.check_login_locks(&throttles)?;
+        
+        // A scan without a PIN only stands in for users who have none; the
+        // others are asked for it rather than charged a failed attempt
+        if pin.is_none() && pin_hash.is_some() {
+            return Err(AuthError::PinRequired);
+        }
+        
+        if !self.kiosk_pin_matches(pin, pin_hash.as_deref())? {
+            return Err(self.record_login_failure(&throttles)?);
+        }
+        
+        self.record_login_success(&throttles)?;
+        
+        if user.status == UserStatus::Inactive {
+            return Err(AuthError::AccountDeactivated);
+        }
+        
+        Ok(user)
+    }
+
+    // The same checks against the offline cache while the server is
+    // unreachable. The shared throttles are out of reach too, so failures
+    // count against a lock kept by this terminal alone.
+    fn authenticate_badge_offline(
+        &self,
+        cache: &OfflineCache,
+        badge_number: &str,
+        pin: Option<&str>,
+    ) -> Result<User, AuthError> {
+        let now = now_utc();
+        Self::check_offline_lock(cache, now)?;
+        
+        let Some((user, pin_hash)) = cache.find_kiosk_credentials(&hash_badge(badge_number))? else {
+            return Err(Self::record_offline_failure(cache, now)?);
+        };
+        
+        if pin.is_none() && pin_hash.is_some() {
+            return Err(AuthError::PinRequired);
+        }
+        
+        if !self.kiosk_pin_matches(pin, pin_hash.as_deref())? {
+            return Err(Self::record_offline_failure(cache, now)?);
+        }
+        
+        cache.clear_device_failures()?;
+        
+        if user.status == UserStatus::Inactive {
+            return Err(AuthError::AccountDeactivated);
+        }
+        
+        Ok(user)
+    }
+
+    // Without a stored PIN the badge alone would do, so such users cannot
+    // use the PIN path
+    fn kiosk_pin_matches(&self, pin: Option<&str>, pin_hash: Option<&str>) -> Result<bool, AuthError> {
+        match (pin, pin_hash) {
+            (None, None) => Ok(true),
+            (Some(pin), Some(pin_hash)) => self.hasher.verify(pin, pin_hash),
+            (None, Some(_)) | (Some(_), None) => Ok(false),
+        }
+    }
+
+    fn check_offline_lock(cache: &OfflineCache, now: NaiveDateTime) -> Result<(), AuthError> {
+        match cache.device_locked_until()? {
+            Some(until) if until > now => Err(AuthError::AccountLocked { until }),
+            _ => Ok(()),
+        }
+    }
+
+    fn record_offline_failure(cache: &OfflineCache, now: NaiveDateTime) -> Result<AuthError, AuthError> {
+        let failures = cache.record_device_failure(now, lockout::window_start(now))?;
+        
+        match lockout::lockout_duration(failures, ThrottleScope::Device.max_failures()) {
+            Some(duration) => {
+                cache.lock_device(now + duration)?;
+                Ok(AuthError::AccountLocked { until: now + duration })
+            }
+            None => Ok(AuthError::InvalidCredentials),
+        }
+    }
+
+    // The code the user's phone should show right now
+    pub fn qr_code(&self, user_id: i32) -> QrCode {
+        self.qr_signer.issue(user_id, now_utc())
+    }
+
+    // Each code punches once. Forged codes count against this terminal's
+    // failed-login limit like unknown badges do.
+    pub fn authenticate_qr_code(&self, payload: &str) -> Result<QrPass, AuthError> {
+        let storage = match (self.storage(), self.offline_cache(), self.queue()) {
+            (Ok(storage), _, _) => storage,
+            (Err(StorageError::Unavailable(_)), Some(cache), Some(queue)) => {
+                return self.authenticate_qr_code_offline(cache, queue, payload);
+            }
+            (Err(e), _, _) => return Err(e.into()),
+        };
+        let now = now_utc();
+        let device = [(ThrottleScope::Device, self.device_id.clone())];
+        self.check_login_locks(&device)?;
+        
+        let claim = match self.qr_signer.verify(payload, now) {
+            Err(AuthError::InvalidQrCode) => {
+                return Err(match self.record_login_failure(&device)? {
+                    locked @ AuthError::AccountLocked { .. } => locked,
+                    _ => AuthError::InvalidQrCode,
+                });
+            }
+            result => result?,
+        };
+        
+        self.record_login_success(&device)?;
+        
+        let user = storage.find_user(claim.user_id)?.ok_or(AuthError::InvalidQrCode)?;
+        if user.status == UserStatus::Inactive {
+            return Err(AuthError::AccountDeactivated);
+        }
+        
+        if !Self::redeem_qr_claim(storage, claim, now)? {
+            return Err(AuthError::QrCodeUsed);
+        }
+        
+        Ok(QrPass { user, unredeemed: None })
+    }
+
+    // Checks the signature, which needs no server, and the user against the
+    // offline cache. Reuse is caught among the queued punches here and on
+    // the server when the punch syncs.
+    fn authenticate_qr_code_offline(
+        &self,
+        cache: &OfflineCache,
+        queue: &PunchQueue,
+        payload: &str,
+    ) -> Result<QrPass, AuthError> {
+        let now = now_utc();
+        Self::check_offline_lock(cache, now)?;
+        
+        let claim = match self.qr_signer.verify(payload, now) {
+            Err(AuthError::InvalidQrCode) => {
+                return Err(match Self::record_offline_failure(cache, now)? {
+                    locked @ AuthError::AccountLocked { .. } => locked,
+                    _ => AuthError::InvalidQrCode,
+                });
+            }
+            result => result?,
+        };
+        
+        cache.clear_device_failures()?;
+        
+        let user = cache.find_user(claim.user_id)?.ok_or(AuthError::InvalidQrCode)?;
+        if user.status == UserStatus::Inactive {
+            return Err(AuthError::AccountDeactivated);
+        }
+        
+        if queue.qr_code_queued(claim)? {
+            return Err(AuthError::QrCodeUsed);
+        }
+        
+        Ok(QrPass { user, unredeemed: Some(claim) })
+    }
+
+    fn redeem_qr_claim(storage: &dyn Storage, claim: QrClaim, now: NaiveDateTime) -> Result<bool, StorageError> {
+        let oldest_window = QrSigner::window_at(now - Duration::days(QR_REDEMPTION_RETENTION_DAYS));
+        storage.redeem_qr_code(claim.user_id, claim.window, oldest_window, now)
+    }
+
+    fn check_login_locks(&self, throttles: &[(ThrottleScope, String)]) -> Result<(), AuthError> {
+        let storage = self.storage()?;
+        let now = now_utc();
+        
+        for (scope, subject) in throttles {
+            if let Some(until) = storage.login_locked_until(*scope, subject)? {
+                if until > now {
+                    return Err(AuthError::AccountLocked { until });
+                }
+            }
+        }
+        
+        Ok(())
+    }
+
+    // Counts the failure against every throttle and reports the lock if this
+    // attempt triggered one
+    fn record_login_failure(&self, throttles: &[(ThrottleScope, String)]) -> Result<AuthError, AuthError> {
+        let storage = self.storage()?;
+        let now = now_utc();
+        let window_start = lockout::window_start(now);
+        let mut locked_until = None;
+        
+        for (scope, subject) in throttles {
+            let failures = storage.record_login_failure(*scope, subject, now, window_start)?;
+            if let Some(duration) = lockout::lockout_duration(failures, scope.max_failures()) {
+                let until = now + duration;
+                storage.lock_login(*scope, subject, until)?;
+                locked_until = locked_until.max(Some(until));
+            }
+        }
+        
+        Ok(match locked_until {
+            Some(until) => AuthError::AccountLocked { until },
+            None => AuthError::InvalidCredentials,
+        })
+    }
+
+    // A proven credential starts every counter over, so a busy kiosk's
+    // device counter only builds up from failures in a row
+    fn record_login_success(&self, throttles: &[(ThrottleScope, String)]) -> Result<(), AuthError> {
+        let storage = self.storage()?;
+        
+        for (scope, subject) in throttles {
+            storage.clear_login_failures(*scope, subject)?;
+        }
+        
+        Ok(())
+    }
+
+    // Lifts a username lockout early
+    pub fn unlock_account(&self, user_id: i32) -> Result<(), AuthError> {
+        let storage = self.storage()?;
+        let user = storage.find_user(user_id)?.ok_or(AuthError::UserNotFound)?;
+        
+        storage.clear_login_failures(ThrottleScope::Username, &lockout::username_subject(&user.username))?;
+        
+        Ok(())
+    }
+
+    // Lifts a kiosk's lockout early; `device_id` defaults to this terminal
+    pub fn unlock_device(&self, device_id: Option<&str>) -> Result<(), AuthError> {
+        let device_id = device_id.map(str::trim).unwrap_or(&self.device_id);
+        self.storage()?.clear_login_failures(ThrottleScope::Device, device_id)?;
+        
+        Ok(())
+    }
+
+    pub fn validate_session(&self, token: &str) -> Result<User, AuthError> {
+        let token_hash = hash_token(token);
+        let now = now_utc();
+        let result = self
+            .storage()
+            .and_then(|storage| storage.find_session_user(&token_hash, now));
+        let cache = self.offline_cache();
+        
+        match result {
+            Ok(Some((user, expires_at))) => {
+                if let Some(Err(e)) = cache.map(|cache| cache.store_session(&token_hash, &user, expires_at)) {
+                    eprintln!("Failed to cache session of user {}: {}", user.id, e);
+                }
+                Ok(user)
+            }
+            Ok(None) => {
+                if let Some(cache) = cache {
+                    cache.remove_session(&token_hash)?;
+                }
+                Err(AuthError::InvalidSession)
+            }
+            // Fall back to the last known state of the session while offline,
+            // which survives restarts so a kiosk can start without the server
+            Err(StorageError::Unavailable(reason)) => {
+                match cache.map(|cache| cache.find_session_user(&token_hash, now)).transpose()?.flatten() {
+                    Some(user) => Ok(user),
+                    None => Err(StorageError::Unavailable(reason).into()),
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn logout_user(&self, token: &str) -> Result<(), AuthError> {
+        let token_hash = hash_token(token);
+        
+        if let Some(cache) = self.offline_cache() {
+            cache.remove_session(&token_hash)?;
+        }
+        self.storage()?.revoke_session(&token_hash, now_utc())?;
+        
+        Ok(())
+    }
+
+    pub fn is_supervisor_of(&self, supervisor_id: i32, user_id: i32) -> Result<bool, AuthError> {
+        Ok(self.storage()?.is_supervisor_of(supervisor_id, user_id)?)
+    }
+
+    pub fn get_attendance_events(
+        &self,
+        user_id: i32,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+    ) -> Result<Vec<AttendanceEvent>, AttendanceError> {
+        Ok(self.storage()?.attendance_events(user_id, from, to)?)
+    }
+
+    pub fn clock_in(&self, user_id: i32) -> Result<AttendanceEvent, AttendanceError> {
+        self.punch(user_id, AttendanceEventType::In)
+    }
+
+    pub fn clock_out(&self, user_id: i32) -> Result<AttendanceEvent, AttendanceError> {
+        self.punch(user_id, AttendanceEventType::Out)
+    }
+
+    pub fn start_break(&self, user_id: i32) -> Result<AttendanceEvent, AttendanceError> {
+        self.punch(user_id, AttendanceEventType::BreakStart)
+    }
+
+    pub fn end_break(&self, user_id: i32) -> Result<AttendanceEvent, AttendanceError> {
+        self.punch(user_id, AttendanceEventType::BreakEnd)
+    }
+
+    // Clocks in when off shift and out when on shift
+    pub fn clock_toggle(&self, user_id: i32) -> Result<AttendanceEvent, AttendanceError> {
+        let event_type = self.toggled_event_type(user_id)?;
+        self.punch(user_id, event_type)
+    }
+
+    // Punches for the holder of `pass`, toggling when no type is given
+    pub fn punch_with_qr_pass(
+        &self,
+        pass: &QrPass,
+        event_type: Option<AttendanceEventType>,
+    ) -> Result<AttendanceEvent, AttendanceError> {
+        let event_type = match event_type {
+            Some(event_type) => event_type,
+            None => self.toggled_event_type(pass.user.id)?,
+        };
+        
+        self.punch_with_claim(pass.user.id, event_type, pass.unredeemed)
+    }
+
+    fn toggled_event_type(&self, user_id: i32) -> Result<AttendanceEventType, AttendanceError> {
+        let queued = self.queue().map(|queue| queue.last_pending(user_id)).transpose()?.flatten();
+        
+        // Queued punches are newer than anything the server has
+        let last_event = match queued {
+            Some(event_type) => Some(event_type),
+            None => self.last_recorded_event_type(user_id)?,
+        };
+        
+        Ok(AttendanceEventType::toggled(last_event))
+    }
+
+    // The server's latest punch type, or the cached one while it is unreachable
+    fn last_recorded_event_type(&self, user_id: i32) -> Result<Option<AttendanceEventType>, AttendanceError> {
+        match (self.storage(), self.offline_cache()) {
+            (Ok(storage), _) => Ok(storage.last_attendance_event_type(user_id)?),
+            (Err(StorageError::Unavailable(_)), Some(cache)) => Ok(cache.last_event_type(user_id)?),
+            (Err(e), _) => Err(e.into()),
+        }
+    }
+
+    fn punch(&self, user_id: i32, event_type: AttendanceEventType) -> Result<AttendanceEvent, AttendanceError> {
+        self.punch_with_claim(user_id, event_type, None)
+    }
+
+    fn punch_with_claim(
+        &self,
+        user_id: i32,
+        event_type: AttendanceEventType,
+        qr_code: Option<QrClaim>,
+    ) -> Result<AttendanceEvent, AttendanceError> {
+        let event = self.record_or_queue_punch(Punch {
+            idempotency_key: random_hex(16),
+            user_id,
+            event_type,
+            event_time: now_utc(),
+        }, qr_code)?;
+        
+        // Queued punches are judged once they are synced
+        if !event.queued {
+            self.punch_recorded(&event);
+        }
+        
+        Ok(event)
+    }
+
+    // Follow-up work once a punch reaches the server
+    fn punch_recorded(&self, event: &AttendanceEvent) {
+        self.refresh_exceptions_near(event.user_id, event.event_time);
+        
+        if let Some(Err(e)) = self
+            .offline_cache()
+            .map(|cache| cache.set_last_event_type(event.user_id, event.event_type))
+        {
+            eprintln!("Failed to cache the last punch of user {}: {}", event.user_id, e);
+        }
+    }
+
+    // `qr_code` is an unredeemed code that authorized the punch
+    fn record_or_queue_punch(&self, punch: Punch, qr_code: Option<QrClaim>) -> Result<AttendanceEvent, AttendanceError> {
+        let Some(queue) = self.queue() else {
+            return Self::record_punch(self.storage()?, &punch);
+        };
+        
+        // The code is redeemed before its punch is recorded, which the sync
+        // already does for queued punches
+        if qr_code.is_some() {
+            return Self::enqueue_punch(queue, punch, qr_code);
+        }
+        
+        // Earlier offline punches must reach the server before this one
+        if queue.last_pending(punch.user_id)?.is_some() {
+            self.sync_pending_punches()?;
+            if queue.last_pending(punch.user_id)?.is_some() {
+                return Self::enqueue_punch(queue, punch, None);
+            }
+        }
+        
+        let result = self
+            .storage()
+            .map_err(AttendanceError::from)
+            .and_then(|storage| Self::record_punch(storage, &punch));
+            
+        match result {
+            Err(AttendanceError::Database(StorageError::Unavailable(reason))) => {
+                eprintln!("Database unavailable, queueing punch locally: {}", reason);
+                Self::enqueue_punch(queue, punch, None)
+            }
+            result => result,
+        }
+    }
+
+    // Every punch reaches storage through here, so none lands in a locked
+    // pay period
+    fn record_punch(storage: &dyn Storage, punch: &Punch) -> Result<AttendanceEvent, AttendanceError> {
+        Self::check_period_open(storage, punch.event_time)?;
+        storage.record_attendance_event(punch)
+    }
+
+    fn check_period_open(storage: &dyn Storage, event_time: NaiveDateTime) -> Result<(), AttendanceError> {
+        match Self::period_lock_at(storage, event_time)? {
+            Some(lock) => Err(AttendanceError::PeriodLocked {
+                starts_on: lock.starts_on,
+                ends_on: lock.ends_on,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // The lock in force over the local day of `event_time`, if any
+    fn period_lock_at(storage: &dyn Storage, event_time: NaiveDateTime) -> Result<Option<PeriodLock>, StorageError> {
+        let date = utc_to_local(event_time).date();
+        
+        Ok(storage.list_period_locks(date, date)?.into_iter().find(PeriodLock::is_active))
+    }
+
+    fn enqueue_punch(queue: &PunchQueue, punch: Punch, qr_code: Option<QrClaim>) -> Result<AttendanceEvent, AttendanceError> {
+        // Without the server only the queued punches are known; anything the
+        // server later refuses is kept in the queue as rejected
+        if let Some(last_event) = queue.last_pending(punch.user_id)? {
+            check_attendance_transition(Some(last_event), punch.event_type)?;
+        }
+        
+        queue.enqueue(&punch, qr_code)?;
+        
+        Ok(AttendanceEvent {
+            id: 0,
+            user_id: punch.user_id,
+            event_type: punch.event_type,
+            event_time: punch.event_time,
+            idempotency_key: Some(punch.idempotency_key),
+            corrects_event_id: None,
+            correction_id: None,
+            queued: true,
+        })
+    }
+
+    // Replays queued punches in order, stopping at the first sign the server
+    // is still unreachable
+    pub fn sync_pending_punches(&self) -> Result<SyncReport, StorageError> {
+        let Some(queue) = self.queue() else {
+            return Ok(SyncReport::default());
+        };
+        
+        let mut report = SyncReport::default();
+        
+        let Ok(storage) = self.storage() else {
+            report.remaining = queue.pending_count()?;
+            return Ok(report);
+        };
+        
+        for punch in queue.pending()? {
+            // A code used offline is redeemed first; if it was already used
+            // elsewhere the punch was a replay
+            if let Some(claim) = queue.unredeemed_qr_code(&punch.idempotency_key)? {
+                match Self::redeem_qr_claim(storage, claim, now_utc()) {
+                    Ok(true) => queue.mark_qr_code_redeemed(&punch.idempotency_key)?,
+                    Ok(false) => {
+                        queue.reject(&punch.idempotency_key, &AuthError::QrCodeUsed.to_string())?;
+                        report.rejected += 1;
+                        continue;
+                    }
+                    Err(StorageError::Unavailable(_)) => break,
+                    Err(e) => {
+                        queue.reject(&punch.idempotency_key, &e.to_string())?;
+                        report.rejected += 1;
+                        continue;
+                    }
+                }
+            }
+            
+            match Self::record_punch(storage, &punch) {
+                Ok(event) => {
+                    queue.remove(&punch.idempotency_key)?;
+                    report.synced += 1;
+                    self.punch_recorded(&event);
+                }
+                // Only an unreachable server is retried on the next sync;
+                // any other failure would block the queue for good
+                Err(AttendanceError::Database(StorageError::Unavailable(_))) => break,
+                Err(e) => {
+                    queue.reject(&punch.idempotency_key, &e.to_string())?;
+                    report.rejected += 1;
+                }
+            }
+        }
+        
+        report.remaining = queue.pending_count()?;
+        
+        Ok(report)
+    }
+
+    // Copies the active users to the offline cache, so the kiosk can verify
+    // them after losing the server, even across a restart
+    pub fn refresh_offline_cache(&self) -> Result<usize, StorageError> {
+        let Some(cache) = self.offline_cache() else {
+            return Ok(0);
+        };
+        
+        let users = self.storage()?.list_offline_users()?;
+        cache.replace_users(&users)?;
+        
+        Ok(users.len())
+    }
+
+    pub fn create_shift(&self, req: ShiftRequest) -> Result<Shift, ScheduleError> {
+        let req = Database::validate_shift(req)?;
+        
+        let shift_id = match self.storage()?.insert_shift(&req) {
+            Ok(shift_id) => shift_id,
+            Err(StorageError::Duplicate(_)) => return Err(ScheduleError::ShiftNameTaken),
+            Err(e) => return Err(e.into()),
+        };
+        
+        self.get_shift(shift_id)
+    }
+
+    pub fn list_shifts(&self) -> Result<Vec<Shift>, ScheduleError> {
+        Ok(self.storage()?.list_shifts()?)
+    }
+
+    pub fn get_shift(&self, shift_id: i32) -> Result<Shift, ScheduleError> {
+        self.storage()?
+            .find_shift(shift_id)?
+            .ok_or(ScheduleError::ShiftNotFound)
+    }
+
+    pub fn update_shift(&self, shift_id: i32, req: ShiftRequest) -> Result<Shift, ScheduleError> {
+        let req = Database::validate_shift(req)?;
+        
+        match self.storage()?.update_shift(shift_id, &req) {
+            Ok(true) => self.get_shift(shift_id),
+            Ok(false) => Err(ScheduleError::ShiftNotFound),
+            Err(StorageError::Duplicate(_)) => Err(ScheduleError::ShiftNameTaken),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn delete_shift(&self, shift_id: i32) -> Result<(), ScheduleError> {
+        match self.storage()?.delete_shift(shift_id) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(ScheduleError::ShiftNotFound),
+            Err(StorageError::Referenced(_)) => Err(ScheduleError::ShiftInUse),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn validate_shift(req: ShiftRequest) -> Result<ShiftRequest, ScheduleError> {
+        let req = ShiftRequest {
+            name: req.name.trim().to_string(),
+            ..req
+        };
+        
+        let mut validator = Validator::default();
+        validator.shift_name(&req.name);
+        
+        match (req.split_start_time, req.split_end_time) {
+            (Some(_), None) => validator.push("split_end_time", "Set both split times or neither"),
+            (None, Some(_)) => validator.push("split_start_time", "Set both split times or neither"),
+            _ => {}
+        }
+        
+        // Any day will do; only the layout of the segments matters here
+        let segments = shift_segments(
+            NaiveDate::default(),
+            req.start_time,
+            req.end_time,
+            req.split_start_time.zip(req.split_end_time),
+        );
+        validator
+            .shift_segments(&segments)
+            .finish()
+            .map_err(ScheduleError::Validation)?;
+            
+        Ok(req)
+    }
+
+    pub fn create_schedule_assignment(&self, req: ScheduleAssignmentRequest) -> Result<ScheduleAssignment, ScheduleError> {
+        self.validate_schedule_assignment(&req)?;
+        
+        let assignment_id = self.storage()?.insert_schedule_assignment(&req)?;
+        
+        self.get_schedule_assignment(assignment_id)
+    }
+
+    pub fn get_schedule_assignment(&self, assignment_id: i32) -> Result<ScheduleAssignment, ScheduleError> {
+        self.storage()?
+            .find_schedule_assignment(assignment_id)?
+            .ok_or(ScheduleError::AssignmentNotFound)
+    }
+
+    // Every assignment of `user_id`, or of everyone when None
+    pub fn list_schedule_assignments(&self, user_id: Option<i32>) -> Result<Vec<ScheduleAssignment>, ScheduleError> {
+        Ok(self.storage()?.list_schedule_assignments(user_id, None, None)?)
+    }
+
+    pub fn update_schedule_assignment(
+        &self,
+        assignment_id: i32,
+        req: ScheduleAssignmentRequest,
+    ) -> Result<ScheduleAssignment, ScheduleError> {
+        self.validate_schedule_assignment(&req)?;
+        
+        if !self.storage()?.update_schedule_assignment(assignment_id, &req)? {
+            return Err(ScheduleError::AssignmentNotFound);
+        }
+        
+        self.get_schedule_assignment(assignment_id)
+    }
+
+    pub fn delete_schedule_assignment(&self, assignment_id: i32) -> Result<(), ScheduleError> {
+        if !self.storage()?.delete_schedule_assignment(assignment_id)? {
+            return Err(ScheduleError::AssignmentNotFound);
+        }
+        
+        Ok(())
+    }
+
+    fn validate_schedule_assignment(&self, req: &ScheduleAssignmentRequest) -> Result<(), ScheduleError> {
+        Validator::default()
+            .schedule_pattern(&req.pattern)
+            .date_range(req.starts_on, req.ends_on)
+            .finish()
+            .map_err(ScheduleError::Validation)?;
+            
+        let storage = self.storage()?;
+        storage.find_user(req.user_id)?.ok_or(ScheduleError::UserNotFound)?;
+        storage.find_shift(req.shift_id)?.ok_or(ScheduleError::ShiftNotFound)?;
+        
+        Ok(())
+    }
+
+    // Every shift occurrence of `user_id` (everyone when None) that starts on
+    // a local day from `from` through `to`, in day order
+    pub fn scheduled_shifts(
+        &self,
+        user_id: Option<i32>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<ScheduledShift>, StorageError> {
+        let storage = self.storage()?;
+        let shifts: HashMap<i32, Shift> = storage
+            .list_shifts()?
+            .into_iter()
+            .map(|shift| (shift.id, shift))
+            .collect();
+        let assignments = storage.list_schedule_assignments(user_id, Some(from), Some(to))?;
+        let mut scheduled = Vec::new();
+        
+        for date in from.iter_days().take_while(|date| *date <= to) {
+            for assignment in assignments.iter().filter(|assignment| assignment.works_on(date)) {
+                let Some(shift) = shifts.get(&assignment.shift_id) else {
+                    continue;
+                };
+                let segments = shift
+                    .segments_on(date)
+                    .into_iter()
+                    .map(|(starts_at, ends_at)| ShiftSegment {
+                        starts_at: local_to_utc(starts_at),
+                        ends_at: local_to_utc(ends_at),
+                    })
+                    .collect();
+                    
+                scheduled.push(ScheduledShift {
+                    assignment_id: assignment.id,
+                    user_id: assignment.user_id,
+                    shift: shift.clone(),
+                    date,
+                    segments,
+                });
+            }
+        }
+        
+        Ok(scheduled)
+    }
+
+    // Active users whose schedule has them working at `at` (UTC), including
+    // overnight shifts that began the previous day
+    pub fn expected_on_site(&self, at: NaiveDateTime) -> Result<Vec<OnSiteWorker>, ScheduleError> {
+        let storage = self.storage()?;
+        let today = utc_to_local(at).date();
+        let yesterday = today.pred_opt().unwrap_or(today);
+        let mut workers: Vec<OnSiteWorker> = Vec::new();
+        
+        for scheduled in self.scheduled_shifts(None, yesterday, today)? {
+            let Some(segment) = scheduled
+                .segments
+                .iter()
+                .find(|segment| segment.starts_at <= at && at < segment.ends_at)
+            else {
+                continue;
+            };
+            
+            // Overlapping assignments still list a user once
+            if workers.iter().any(|worker| worker.user.id == scheduled.user_id) {
+                continue;
+            }
+            
+            match storage.find_user(scheduled.user_id)? {
+                Some(user) if user.status == UserStatus::Active => workers.push(OnSiteWorker {
+                    user,
+                    shift: scheduled.shift,
+                    segment: *segment,
+                }),
+                _ => {}
+            }
+        }
+        
+        Ok(workers)
+    }
+
+    // Judges each shift of `user_id` starting on a local day from `from`
+    // through `to`, plus any unscheduled work. Only reads; the stored
+    // exceptions are kept current by `refresh_attendance_exceptions`.
+    pub fn attendance_status(
+        &self,
+        user_id: i32,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<DayAttendance>, ScheduleError> {
+        Validator::default()
+            .report_range(from, to)
+            .finish()
+            .map_err(ScheduleError::Validation)?;
+            
+        let storage = self.storage()?;
+        storage.find_user(user_id)?.ok_or(ScheduleError::UserNotFound)?;
+        
+        self.classify_attendance(storage, user_id, from, to, now_utc())
+    }
+
+    fn classify_attendance(
+        &self,
+        storage: &dyn Storage,
+        user_id: i32,
+        from: NaiveDate,
+        to: NaiveDate,
+        now: NaiveDateTime,
+    ) -> Result<Vec<DayAttendance>, ScheduleError> {
+        // The previous day's overnight shift claims its punches after
+        // midnight, so they are not mistaken for unscheduled work
+        let day_before = from.pred_opt().unwrap_or(from);
+        let shifts = self.scheduled_shifts(Some(user_id), day_before, to)?;
+        
+        let margin = Duration::hours(PUNCH_MATCH_HOURS);
+        let events_from = local_to_utc(day_before.and_time(NaiveTime::MIN)) - margin;
+        let events_to = local_to_utc((to + Duration::days(2)).and_time(NaiveTime::MIN)) + margin;
+        let events = storage.attendance_events(user_id, Some(events_from), Some(events_to))?;
+        
+        let leave_days: Vec<NaiveDate> = storage
+            .list_leaves(Some(user_id), None, Some(LeaveStatus::Approved), Some(day_before), Some(to))?
+            .iter()
+            .flat_map(|leave| leave.starts_on.iter_days().take_while(|date| *date <= leave.ends_on))
+            .collect();
+        
+        Ok(self
+            .attendance_rules
+            .classify(user_id, &shifts, &events, &leave_days, now, |time| utc_to_local(time).date())
+            .into_iter()
+            .filter(|day| day.date >= from && day.date <= to)
+            .collect())
+    }
+
+    // Recomputes the exceptions of `user_id` (every active user when None)
+    // dated `from` through `to` and stores them for review
+    pub fn refresh_attendance_exceptions(
+        &self,
+        user_id: Option<i32>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<(), ScheduleError> {
+        Validator::default()
+            .report_range(from, to)
+            .finish()
+            .map_err(ScheduleError::Validation)?;
+            
+        let storage = self.storage()?;
+        
+        let user_ids = match user_id {
+            Some(user_id) => vec![user_id],
+            None => storage
+                .list_users()?
+                .into_iter()
+                .filter(|user| user.status == UserStatus::Active)
+                .map(|user| user.id)
+                .collect(),
+        };
+        
+        let now = now_utc();
+        for user_id in user_ids {
+            let days = self.classify_attendance(storage, user_id, from, to, now)?;
+            storage.replace_attendance_exceptions(user_id, from, to, &Self::exceptions_of(&days), now)?;
+        }
+        
+        Ok(())
+    }
+
+    // Run by the database worker: shifts end, and become late or absent,
+    // without anything being recorded
+    pub fn refresh_recent_attendance_exceptions(&self) -> Result<(), ScheduleError> {
+        let today = utc_to_local(now_utc()).date();
+        
+        self.refresh_attendance_exceptions(None, today.pred_opt().unwrap_or(today), today)
+    }
+
+    // After a punch or correction at `event_time`: its local day and the
+    // day before, whose overnight shift may claim it. The change itself is
+    // already stored, so a failure here is only logged.
+    fn refresh_exceptions_near(&self, user_id: i32, event_time: NaiveDateTime) {
+        let date = utc_to_local(event_time).date();
+        
+        if let Err(e) = self.refresh_attendance_exceptions(Some(user_id), date.pred_opt().unwrap_or(date), date) {
+            eprintln!("Failed to refresh attendance exceptions of user {}: {}", user_id, e);
+        }
+    }
+
+    // Two shifts on one day still give one exception of each kind
+    fn exceptions_of(days: &[DayAttendance]) -> Vec<NewAttendanceException> {
+        let mut exceptions: Vec<NewAttendanceException> = Vec::new();
+        for day in days {
+            for kind in day.statuses.iter().copied().filter(AttendanceStatus::is_exception) {
+                let minutes = match kind {
+                    AttendanceStatus::Late => Some(day.late_minutes),
+                    AttendanceStatus::EarlyLeave => Some(day.early_leave_minutes),
+                    _ => None,
+                };
+                
+                match exceptions.iter_mut().find(|e| e.work_date == day.date && e.kind == kind) {
+                    Some(existing) => {
+                        existing.minutes = match (existing.minutes, minutes) {
+                            (Some(a), Some(b)) => Some(a + b),
+                            (a, b) => a.or(b),
+                        };
+                    }
+                    None => exceptions.push(NewAttendanceException { work_date: day.date, kind, minutes }),
+                }
+            }
+        }
+        
+        exceptions
+    }
+
+    // Worked hours of `user_id` for `from` through `to` in local days
+    pub fn timesheet(&self, user_id: i32, from: NaiveDate, to: NaiveDate) -> Result<Timesheet, ScheduleError> {
+        Validator::default()
+            .report_range(from, to)
+            .finish()
+            .map_err(ScheduleError::Validation)?;
+            
+        let storage = self.storage()?;
+        storage.find_user(user_id)?.ok_or(ScheduleError::UserNotFound)?;
+        
+        // Weekly overtime needs the whole first week, plus the day before
+        // so an overnight shift into it keeps its clock-in; work that
+        // starts on `to` may end the day after
+        let week_start = self.timesheet_rules.week_start(from);
+        let events_from = local_to_utc((week_start - Duration::days(1)).and_time(NaiveTime::MIN));
+        let events_to = local_to_utc((to + Duration::days(2)).and_time(NaiveTime::MIN));
+        
+        let events: Vec<TimesheetEvent> = storage
+            .attendance_events(user_id, Some(events_from), Some(events_to))?
+            .into_iter()
+            // Oldest first, so punches within the same second keep their order
+            .rev()
+            .map(|event| TimesheetEvent {
+                event_type: event.event_type,
+                at: utc_to_local(event.event_time),
+            })
+            .collect();
+            
+        Ok(self.timesheet_rules.compute(user_id, from, to, &events))
+    }
+
+    // Stored exceptions of `user_id` (everyone when None) dated `from`
+    // through `to`
+    pub fn list_attendance_exceptions(
+        &self,
+        user_id: Option<i32>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<AttendanceException>, ScheduleError> {
+        Validator::default()
+            .report_range(from, to)
+            .finish()
+            .map_err(ScheduleError::Validation)?;
+            
+        Ok(self.storage()?.list_attendance_exceptions(user_id, from, to)?)
+    }
+
+    pub fn get_attendance_exception(&self, exception_id: i32) -> Result<AttendanceException, ScheduleError> {
+        self.storage()?
+            .find_attendance_exception(exception_id)?
+            .ok_or(ScheduleError::ExceptionNotFound)
+    }
+
+    pub fn review_attendance_exception(
+        &self,
+        exception_id: i32,
+        reviewer_id: i32,
+        note: Option<String>,
+    ) -> Result<AttendanceException, ScheduleError> {
+        let note = note.map(|note| note.trim().to_string()).filter(|note| !note.is_empty());
+        
+        Validator::default()
+            .note(note.as_deref())
+            .finish()
+            .map_err(ScheduleError::Validation)?;
+            
+        if !self.storage()?.review_attendance_exception(exception_id, reviewer_id, note.as_deref(), now_utc())? {
+            return Err(ScheduleError::ExceptionNotFound);
+        }
+        
+        self.get_attendance_exception(exception_id)
+    }
+
+    // The configured pay periods overlapping `from` through `to`, with the
+    // lock of each that has one
+    pub fn pay_periods(&self, from: NaiveDate, to: NaiveDate) -> Result<Vec<PayPeriodStatus>, PayPeriodError> {
+        Validator::default()
+            .report_range(from, to)
+            .finish()
+            .map_err(PayPeriodError::Validation)?;
+            
+        let periods = self.pay_periods.periods(from, to);
+        let (Some(first), Some(last)) = (periods.first(), periods.last()) else {
+            return Ok(Vec::new());
+        };
+        
+        let locks: Vec<PeriodLock> = self
+            .storage()?
+            .list_period_locks(first.starts_on, last.ends_on)?
+            .into_iter()
+            .filter(PeriodLock::is_active)
+            .collect();
+            
+        Ok(periods
+            .into_iter()
+            .map(|period| PayPeriodStatus {
+                starts_on: period.starts_on,
+                ends_on: period.ends_on,
+                lock: locks
+                    .iter()
+                    .find(|lock| lock.starts_on <= period.ends_on && lock.ends_on >= period.starts_on)
+                    .cloned(),
+            })
+            .collect())
+    }
+
+    // Locks the pay period containing `date`. Only a period that has ended
+    // can be locked, so nobody is stopped from punching today.
+    pub fn lock_period(&self, date: NaiveDate, admin_id: i32) -> Result<PeriodLock, PayPeriodError> {
+        let PayPeriod { starts_on, ends_on } = self.pay_periods.period_containing(date);
+        
+        if ends_on >= utc_to_local(now_utc()).date() {
+            return Err(PayPeriodError::Validation(vec![FieldError {
+                field: "date",
+                message: "This pay period has not ended yet".to_string(),
+            }]));
+        }
+        
+        let storage = self.storage()?;
+        
+        // Periods can shift when the configuration changes, so any overlap
+        // counts as already locked
+        let lock_id = storage.insert_period_lock(starts_on, ends_on, admin_id, now_utc())?;
+        
+        storage.find_period_lock(lock_id)?.ok_or(PayPeriodError::LockNotFound)
+    }
+
+    // Reopens a locked period. The reason is required and kept with the
+    // lock for payroll to audit.
+    pub fn unlock_period(&self, lock_id: i32, admin_id: i32, reason: &str) -> Result<PeriodLock, PayPeriodError> {
+        let reason = reason.trim();
+        
+        Validator::default()
+            .reason(reason)
+            .finish()
+            .map_err(PayPeriodError::Validation)?;
+            
+        let storage = self.storage()?;
+        
+        if !storage.unlock_period(lock_id, admin_id, reason, now_utc())? {
+            return match storage.find_period_lock(lock_id)? {
+                Some(_) => Err(PayPeriodError::NotLocked),
+                None => Err(PayPeriodError::LockNotFound),
+            };
+        }
+        
+        let lock = storage.find_period_lock(lock_id)?.ok_or(PayPeriodError::LockNotFound)?;
+        eprintln!(
+            "Pay period {} to {} unlocked by user {}: {}",
+            lock.starts_on, lock.ends_on, admin_id, reason
+        );
+        
+        Ok(lock)
+    }
+
+    pub fn submit_correction(
+        &self,
+        user_id: i32,
+        request: CorrectionRequest,
+    ) -> Result<AttendanceCorrection, CorrectionError> {
+        let reason = request.reason.trim().to_string();
+        
+        Validator::default()
+            .correction(&request, now_utc())
+            .reason(&reason)
+            .finish()
+            .map_err(CorrectionError::Validation)?;
+            
+        let storage = self.storage()?;
+        storage.find_user(user_id)?.ok_or(CorrectionError::UserNotFound)?;
+        
+        // A moved punch keeps its type, and its old time must not be in a
+        // locked period either
+        let (event_type, original_time) = match request.event_id {
+            Some(event_id) => {
+                let original = storage
+                    .find_attendance_event(event_id)?
+                    .filter(|event| event.user_id == user_id)
+                    .ok_or(CorrectionError::EventNotFound)?;
+                (original.event_type, Some(original.event_time))
+            }
+            None => (request.event_type.unwrap_or(AttendanceEventType::In), None),
+        };
+        
+        Self::check_correction_period(storage, request.event_time, original_time)?;
+        
+        let correction_id = storage.insert_correction(
+            user_id,
+            &NewAttendanceCorrection {
+                event_id: request.event_id,
+                event_type,
+                event_time: request.event_time,
+                reason,
+            },
+            now_utc(),
+        )?;
+        
+        self.get_correction(correction_id)
+    }
+
+    pub fn get_correction(&self, correction_id: i32) -> Result<AttendanceCorrection, CorrectionError> {
+        self.storage()?
+            .find_correction(correction_id)?
+            .ok_or(CorrectionError::CorrectionNotFound)
+    }
+
+    // Corrections of `user_id`, or of the reports of `supervisor_id`;
+    // everyone's when both are None
+    pub fn list_corrections(
+        &self,
+        user_id: Option<i32>,
+        supervisor_id: Option<i32>,
+        status: Option<CorrectionStatus>,
+    ) -> Result<Vec<AttendanceCorrection>, CorrectionError> {
+        Ok(self.storage()?.list_corrections(user_id, supervisor_id, status)?)
+    }
+
+    // Approving writes the adjusted event; rejecting only records the
+    // decision. Either way the correction can no longer change.
+    pub fn decide_correction(
+        &self,
+        correction_id: i32,
+        decided_by: i32,
+        approve: bool,
+        note: Option<String>,
+    ) -> Result<AttendanceCorrection, CorrectionError> {
+        let note = note.map(|note| note.trim().to_string()).filter(|note| !note.is_empty());
+        
+        Validator::default()
+            .note(note.as_deref())
+            .finish()
+            .map_err(CorrectionError::Validation)?;
+            
+        let storage = self.storage()?;
+        let correction = self.get_correction(correction_id)?;
+        
+        if correction.status != CorrectionStatus::Pending {
+            return Err(CorrectionError::AlreadyDecided);
+        }
+        
+        let original_time = match correction.event_id {
+            Some(event_id) if approve => Some(
+                storage
+                    .find_attendance_event(event_id)?
+                    .ok_or(CorrectionError::AlreadyCorrected)?
+                    .event_time,
+            ),
+            _ => None,
+        };
+        
+        let status = if approve {
+            Self::check_correction_period(storage, correction.event_time, original_time)?;
+            CorrectionStatus::Approved
+        } else {
+            CorrectionStatus::Rejected
+        };
+        
+        match storage.decide_correction(correction_id, status, decided_by, note.as_deref(), now_utc()) {
+            Ok(true) => {}
+            Ok(false) => return Err(CorrectionError::AlreadyDecided),
+            // Another correction of the same punch was approved first
+            Err(CorrectionError::Database(StorageError::Duplicate(_))) => return Err(CorrectionError::AlreadyCorrected),
+            Err(e) => return Err(e),
+        }
+        
+        if approve {
+            for time in std::iter::once(correction.event_time).chain(original_time) {
+                self.refresh_exceptions_near(correction.user_id, time);
+            }
+        }
+        
+        self.get_correction(correction_id)
+    }
+
+    fn check_correction_period(
+        storage: &dyn Storage,
+        event_time: NaiveDateTime,
+        original_time: Option<NaiveDateTime>,
+    ) -> Result<(), CorrectionError> {
+        for time in std::iter::once(event_time).chain(original_time) {
+            if let Some(lock) = Self::period_lock_at(storage, time)? {
+                return Err(CorrectionError::PeriodLocked {
+                    starts_on: lock.starts_on,
+                    ends_on: lock.ends_on,
+                });
+            }
+        }
+        
+        Ok(())
+    }
+
+    pub fn accrue_leave(
+        &self,
+        user_id: i32,
+        request: LeaveAccrualRequest,
+        admin_id: i32,
+    ) -> Result<LeaveAccrual, LeaveError> {
+        let note = request.note.map(|note| note.trim().to_string()).filter(|note| !note.is_empty());
+        
+        Validator::default()
+            .leave_accrual(request.leave_type, request.days)
+            .note(note.as_deref())
+            .finish()
+            .map_err(LeaveError::Validation)?;
+            
+        let storage = self.storage()?;
+        storage.find_user(user_id)?.ok_or(LeaveError::UserNotFound)?;
+        
+        let now = now_utc();
+        let accrual_id = storage.insert_leave_accrual(
+            user_id,
+            request.leave_type,
+            request.days,
+            note.as_deref(),
+            admin_id,
+            now,
+        )?;
+        
+        Ok(LeaveAccrual {
+            id: accrual_id,
+            user_id,
+            leave_type: request.leave_type,
+            days: request.days,
+            note,
+            created_by: admin_id,
+            created_at: now,
+        })
+    }
+
+    // One balance per accrued leave type; unpaid leave has none
+    pub fn leave_balances(&self, user_id: i32) -> Result<Vec<LeaveBalance>, LeaveError> {
+        let storage = self.storage()?;
+        storage.find_user(user_id)?.ok_or(LeaveError::UserNotFound)?;
+        
+        Self::leave_balances_of(storage, user_id)
+    }
+
+    // `days` is the number of those dates `user_id` is scheduled to work,
+    // so weekends and days off cost nothing
+    pub fn request_leave(&self, user_id: i32, request: LeaveRequest) -> Result<Leave, LeaveError> {
+        let reason = request.reason.map(|reason| reason.trim().to_string()).filter(|reason| !reason.is_empty());
+        
+        Validator::default()
+            .leave(request.starts_on, request.ends_on, reason.as_deref())
+            .finish()
+            .map_err(LeaveError::Validation)?;
+            
+        let storage = self.storage()?;
+        storage.find_user(user_id)?.ok_or(LeaveError::UserNotFound)?;
+        
+        let mut work_dates: Vec<NaiveDate> = self
+            .scheduled_shifts(Some(user_id), request.starts_on, request.ends_on)?
+            .into_iter()
+            .map(|scheduled| scheduled.date)
+            .collect();
+        work_dates.dedup();
+        
+        if work_dates.is_empty() {
+            return Err(LeaveError::Validation(vec![FieldError {
+                field: "ends_on",
+                message: "No scheduled shifts fall within these dates".to_string(),
+            }]));
+        }
+        
+        // Overlap and balance are checked as the request is stored
+        let leave_id = storage.insert_leave(
+            user_id,
+            &NewLeave {
+                leave_type: request.leave_type,
+                starts_on: request.starts_on,
+                ends_on: request.ends_on,
+                days: work_dates.len() as f64,
+                reason,
+            },
+            now_utc(),
+        )?;
+        
+        self.get_leave(leave_id)
+    }
+
+    pub fn get_leave(&self, leave_id: i32) -> Result<Leave, LeaveError> {
+        self.storage()?
+            .find_leave(leave_id)?
+            .ok_or(LeaveError::LeaveNotFound)
+    }
+
+    // Leave of `user_id`, or of the reports of `supervisor_id`; everyone's
+    // when both are None
+    pub fn list_leaves(
+        &self,
+        user_id: Option<i32>,
+        supervisor_id: Option<i32>,
+        status: Option<LeaveStatus>,
+    ) -> Result<Vec<Leave>, LeaveError> {
+        Ok(self.storage()?.list_leaves(user_id, supervisor_id, status, None, None)?)
+    }
+
+    // Approval only needs the balance left after approved leave; other
+    // pending requests do not block it
+    pub fn decide_leave(
+        &self,
+        leave_id: i32,
+        decided_by: i32,
+        approve: bool,
+        note: Option<String>,
+    ) -> Result<Leave, LeaveError> {
+        let note = note.map(|note| note.trim().to_string()).filter(|note| !note.is_empty());
+        
+        Validator::default()
+            .note(note.as_deref())
+            .finish()
+            .map_err(LeaveError::Validation)?;
+            
+        let storage = self.storage()?;
+        let leave = self.get_leave(leave_id)?;
+        
+        if leave.status != LeaveStatus::Pending {
+            return Err(LeaveError::AlreadyDecided);
+        }
+        
+        let status = if approve { LeaveStatus::Approved } else { LeaveStatus::Rejected };
+        
+        if !storage.decide_leave(leave_id, status, decided_by, note.as_deref(), now_utc())? {
+            return Err(LeaveError::AlreadyDecided);
+        }
+        
+        // Approved leave excuses absences already recorded on those days
+        if approve {
+            if let Err(e) = self.refresh_attendance_exceptions(Some(leave.user_id), leave.starts_on, leave.ends_on) {
+                eprintln!("Failed to refresh attendance exceptions of user {}: {}", leave.user_id, e);
+            }
+        }
+        
+        self.get_leave(leave_id)
+    }
+
+    fn leave_balances_of(storage: &dyn Storage, user_id: i32) -> Result<Vec<LeaveBalance>, LeaveError> {
+        let accruals = storage.list_leave_accruals(user_id)?;
+        let leaves = storage.list_leaves(Some(user_id), None, None, None, None)?;
+        
+        let balances = LeaveType::ACCRUED
+            .into_iter()
+            .map(|leave_type| {
+                let accrued: f64 = accruals
+                    .iter()
+                    .filter(|accrual| accrual.leave_type == leave_type)
+                    .map(|accrual| accrual.days)
+                    .sum();
+                let taken = |status: LeaveStatus| -> f64 {
+                    leaves
+                        .iter()
+                        .filter(|leave| leave.leave_type == leave_type && leave.status == status)
+                        .map(|leave| leave.days)
+                        .sum()
+                };
+                let used = taken(LeaveStatus::Approved);
+                let pending = taken(LeaveStatus::Pending);
+                
+                LeaveBalance {
+                    leave_type,
+                    accrued,
+                    used,
+                    pending,
+                    available: accrued - used - pending,
+                }
+            })
+            .collect();
+            
+        Ok(balances)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    const THREADS: usize = 16;
+
+    // Many callers race to create the same username; exactly one may win
+    // and every other caller must see UsernameTaken, never a raw database error
+    fn hammer_create_user(databases: &[Arc<Database>], username: &str) {
+        let handles: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let database = Arc::clone(&databases[i % databases.len()]);
+                let username = username.to_string();
+                thread::spawn(move || {
+                    database.create_user(CreateUserRequest {
+                        username,
+                        password: "correct-horse-42".to_string(),
+                        email: None,
+                        full_name: None,
+                        role: None,
+                        supervisor_id: None,
+                    })
+                })
+            })
+            .collect();
+            
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        for result in results.iter().filter(|r| r.is_err()) {
+            assert!(matches!(result, Err(AuthError::UsernameTaken)), "{:?}", result);
+        }
+    }
+
+    fn temp_sqlite_path() -> std::path::PathBuf {
+        env::temp_dir().join(format!("attendance-test-{}.db", random_hex(8)))
+    }
+
+    fn remove_sqlite(path: &std::path::Path) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+    }
+
+    #[test]
+    fn concurrent_create_user_sqlite() {
+        let path = temp_sqlite_path();
+        let url = format!("sqlite://{}", path.display());
+        
+        // Separate connections behave like several kiosks sharing one file
+        let databases: Vec<_> = (0..4)
+            .map(|_| Arc::new(Database::open(&url).unwrap()))
+            .collect();
+            
+        hammer_create_user(&databases, "racer");
+        
+        drop(databases);
+        remove_sqlite(&path);
+    }
+
+    // Set TEST_MYSQL_URL to run against a disposable MySQL database
+    #[test]
+    fn concurrent_create_user_mysql() {
+        let Ok(url) = env::var("TEST_MYSQL_URL") else {
+            return;
+        };
+        
+        let databases = vec![Arc::new(Database::open(&url).unwrap())];
+        
+        hammer_create_user(&databases, &format!("racer-{}", random_hex(4)));
+    }
+
+    fn create_user(database: &Database, username: &str) -> User {
+        database
+            .create_user(CreateUserRequest {
+                username: username.to_string(),
+                password: "correct-horse-42".to_string(),
+                email: None,
+                full_name: None,
+                role: None,
+                supervisor_id: None,
+            })
+            .unwrap()
+    }
+
+    // A stored punch at `hour` local time, `days_ago` days back
+    fn record(database: &Database, user_id: i32, event_type: AttendanceEventType, days_ago: i64, hour: u32) -> AttendanceEvent {
+        database
+            .storage()
+            .unwrap()
+            .record_attendance_event(&Punch {
+                idempotency_key: random_hex(16),
+                user_id,
+                event_type,
+                event_time: local_at(days_ago, hour),
+            })
+            .unwrap()
+    }
+
+    fn local_at(days_ago: i64, hour: u32) -> NaiveDateTime {
+        let day = utc_to_local(now_utc()).date() - Duration::days(days_ago);
+        local_to_utc(day.and_hms_opt(hour, 0, 0).unwrap())
+    }
+
+    fn move_punch(database: &Database, user_id: i32, event: &AttendanceEvent, days_ago: i64, hour: u32) -> AttendanceCorrection {
+        database
+            .submit_correction(
+                user_id,
+                CorrectionRequest {
+                    kind: CorrectionKind::WrongTime,
+                    event_id: Some(event.id),
+                    event_type: None,
+                    event_time: local_at(days_ago, hour),
+                    reason: "Punched late".to_string(),
+                },
+            )
+            .unwrap()
+    }
+
+    fn raw_event_time(path: &std::path::Path, event_id: u64) -> Option<NaiveDateTime> {
+        rusqlite::Connection::open(path)
+            .unwrap()
+            .query_row("SELECT event_time FROM attendance_events WHERE id = ?1", [event_id], |row| row.get(0))
+            .ok()
+    }
+
+    #[test]
+    fn approved_correction_replaces_the_punch_sqlite() {
+        let path = temp_sqlite_path();
+        let database = Database::open(&format!("sqlite://{}", path.display())).unwrap();
+        let boss = create_user(&database, "boss");
+        let worker = create_user(&database, "worker");
+        
+        let clock_in = record(&database, worker.id, AttendanceEventType::In, 3, 9);
+        record(&database, worker.id, AttendanceEventType::Out, 3, 17);
+        let correction = move_punch(&database, worker.id, &clock_in, 3, 8);
+        let duplicate = move_punch(&database, worker.id, &clock_in, 3, 7);
+        
+        let approved = database.decide_correction(correction.id, boss.id, true, None).unwrap();
+        
+        assert_eq!(approved.status, CorrectionStatus::Approved);
+        assert_eq!(approved.decided_by, Some(boss.id));
+        let storage = database.storage().unwrap();
+        let adjusted = storage.find_attendance_event(approved.adjusted_event_id.unwrap()).unwrap().unwrap();
+        assert_eq!(adjusted.event_type, AttendanceEventType::In);
+        assert_eq!(adjusted.event_time, local_at(3, 8));
+        assert_eq!(adjusted.corrects_event_id, Some(clock_in.id));
+        assert_eq!(adjusted.correction_id, Some(correction.id));
+        
+        // Hidden from every reader, but kept for the audit trail
+        let current: Vec<u64> = storage.attendance_events(worker.id, None, None).unwrap().iter().map(|e| e.id).collect();
+        assert!(current.contains(&adjusted.id));
+        assert!(!current.contains(&clock_in.id));
+        assert!(storage.find_attendance_event(clock_in.id).unwrap().is_none());
+        assert_eq!(raw_event_time(&path, clock_in.id), Some(local_at(3, 9)));
+        
+        // The punch can only be replaced once
+        assert!(matches!(
+            database.decide_correction(duplicate.id, boss.id, true, None),
+            Err(CorrectionError::AlreadyCorrected)
+        ));
+        assert!(matches!(
+            storage.decide_correction(duplicate.id, CorrectionStatus::Approved, boss.id, None, now_utc()),
+            Err(CorrectionError::Database(StorageError::Duplicate(_)))
+        ));
+        assert_eq!(database.get_correction(duplicate.id).unwrap().status, CorrectionStatus::Pending);
+        
+        drop(database);
+        remove_sqlite(&path);
+    }
+
+    #[test]
+    fn correction_that_breaks_the_sequence_is_refused_sqlite() {
+        let path = temp_sqlite_path();
+        let database = Database::open(&format!("sqlite://{}", path.display())).unwrap();
+        let boss = create_user(&database, "boss");
+        let worker = create_user(&database, "worker");
+        
+        let clock_in = record(&database, worker.id, AttendanceEventType::In, 3, 9);
+        let clock_out = record(&database, worker.id, AttendanceEventType::Out, 3, 17);
+        
+        // A second clock-in inside the open shift
+        let extra_in = database
+            .submit_correction(
+                worker.id,
+                CorrectionRequest {
+                    kind: CorrectionKind::MissingPunch,
+                    event_id: None,
+                    event_type: Some(AttendanceEventType::In),
+                    event_time: local_at(3, 12),
+                    reason: "Forgot to punch".to_string(),
+                },
+            )
+            .unwrap();
+        assert!(matches!(
+            database.decide_correction(extra_in.id, boss.id, true, None),
+            Err(CorrectionError::InvalidSequence(AttendanceError::AlreadyClockedIn))
+        ));
+        
+        // A clock-out moved before its clock-in
+        let early_out = move_punch(&database, worker.id, &clock_out, 3, 8);
+        assert!(matches!(
+            database.decide_correction(early_out.id, boss.id, true, None),
+            Err(CorrectionError::InvalidSequence(AttendanceError::NotClockedIn))
+        ));
+        
+        // Nothing was written and both can still be rejected
+        let current: Vec<u64> = database
+            .storage()
+            .unwrap()
+            .attendance_events(worker.id, None, None)
+            .unwrap()
+            .iter()
+            .map(|e| e.id)
+            .collect();
+        assert_eq!(current, vec![clock_out.id, clock_in.id]);
+        assert_eq!(database.decide_correction(extra_in.id, boss.id, false, None).unwrap().status, CorrectionStatus::Rejected);
+        assert_eq!(database.decide_correction(early_out.id, boss.id, false, None).unwrap().status, CorrectionStatus::Rejected);
+        
+        drop(database);
+        remove_sqlite(&path);
+    }
+
+    #[test]
+    fn correction_in_a_locked_period_is_refused_sqlite() {
+        let path = temp_sqlite_path();
+        let database = Database::open(&format!("sqlite://{}", path.display())).unwrap();
+        let boss = create_user(&database, "boss");
+        let worker = create_user(&database, "worker");
+        
+        let clock_in = record(&database, worker.id, AttendanceEventType::In, 70, 9);
+        record(&database, worker.id, AttendanceEventType::Out, 70, 17);
+        let correction = move_punch(&database, worker.id, &clock_in, 70, 8);
+        database.lock_period(utc_to_local(local_at(70, 9)).date(), boss.id).unwrap();
+        
+        assert!(matches!(
+            database.decide_correction(correction.id, boss.id, true, None),
+            Err(CorrectionError::PeriodLocked { .. })
+        ));
+        assert_eq!(database.get_correction(correction.id).unwrap().status, CorrectionStatus::Pending);
+        assert_eq!(raw_event_time(&path, clock_in.id), Some(local_at(70, 9)));
+        
+        drop(database);
+        remove_sqlite(&path);
+    }
+
+    // Racing locks of the same period may not both succeed
+    #[test]
+    fn concurrent_lock_period_sqlite() {
+        let path = temp_sqlite_path();
+        let databases = open_sqlite_connections(&path);
+        let boss = create_user(&databases[0], "boss");
+        let day = utc_to_local(local_at(70, 9)).date();
+        
+        let handles: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let database = Arc::clone(&databases[i % databases.len()]);
+                thread::spawn(move || database.lock_period(day, boss.id))
+            })
+            .collect();
+            
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        for result in results.iter().filter(|r| r.is_err()) {
+            assert!(matches!(result, Err(PayPeriodError::AlreadyLocked { .. })), "{:?}", result);
+        }
+        let locks = databases[0].storage().unwrap().list_period_locks(day, day).unwrap();
+        assert_eq!(locks.len(), 1);
+        
+        drop(databases);
+        remove_sqlite(&path);
+    }
+
+    // Every day from `starts_on` is a scheduled working day
+    fn schedule_every_day(database: &Database, user_id: i32, starts_on: NaiveDate) {
+        let shift = database
+            .create_shift(ShiftRequest {
+                name: "Day".to_string(),
+                start_time: time(9, 0),
+                end_time: time(17, 0),
+                split_start_time: None,
+                split_end_time: None,
+            })
+            .unwrap();
+        database
+            .create_schedule_assignment(ScheduleAssignmentRequest {
+                user_id,
+                shift_id: shift.id,
+                pattern: SchedulePattern::Rotating { days_on: 1, days_off: 0 },
+                starts_on,
+                ends_on: None,
+            })
+            .unwrap();
+    }
+
+    fn accrue(database: &Database, user_id: i32, days: f64, admin_id: i32) {
+        database
+            .accrue_leave(user_id, LeaveAccrualRequest { leave_type: LeaveType::Vacation, days, note: None }, admin_id)
+            .unwrap();
+    }
+
+    // Separate connections to one file, like several terminals
+    fn open_sqlite_connections(path: &std::path::Path) -> Vec<Arc<Database>> {
+        let url = format!("sqlite://{}", path.display());
+        (0..4).map(|_| Arc::new(Database::open(&url).unwrap())).collect()
+    }
+
+    // Racing requests for separate days may not together exceed the balance
+    // or overlap one another
+    #[test]
+    fn concurrent_request_leave_sqlite() {
+        let path = temp_sqlite_path();
+        let databases = open_sqlite_connections(&path);
+        let boss = create_user(&databases[0], "boss");
+        let worker = create_user(&databases[0], "worker");
+        let first_day = utc_to_local(now_utc()).date() + Duration::days(1);
+        schedule_every_day(&databases[0], worker.id, first_day);
+        accrue(&databases[0], worker.id, 3.0, boss.id);
+        
+        // Each day is asked for twice
+        let handles: Vec<_> = (0..THREADS)
+            .map(|i| {
+                let database = Arc::clone(&databases[i % databases.len()]);
+                let day = first_day + Duration::days((i / 2) as i64);
+                thread::spawn(move || {
+                    database.request_leave(
+                        worker.id,
+                        LeaveRequest { leave_type: LeaveType::Vacation, starts_on: day, ends_on: day, reason: None },
+                    )
+                })
+            })
+            .collect();
+            
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 3);
+        for result in results.iter().filter(|r| r.is_err()) {
+            assert!(
+                matches!(result, Err(LeaveError::InsufficientBalance { .. } | LeaveError::Overlaps { .. })),
+                "{:?}",
+                result
+            );
+        }
+        let balance = &databases[0].leave_balances(worker.id).unwrap()[0];
+        assert_eq!((balance.pending, balance.available), (3.0, 0.0));
+        
+        drop(databases);
+        remove_sqlite(&path);
+    }
+
+    // Racing approvals may not together exceed what is left after a
+    // balance was reduced under pending requests
+    #[test]
+    fn concurrent_decide_leave_sqlite() {
+        let path = temp_sqlite_path();
+        let databases = open_sqlite_connections(&path);
+        let boss = create_user(&databases[0], "boss");
+        let worker = create_user(&databases[0], "worker");
+        let first_day = utc_to_local(now_utc()).date() + Duration::days(1);
+        schedule_every_day(&databases[0], worker.id, first_day);
+        accrue(&databases[0], worker.id, THREADS as f64, boss.id);
+        
+        let leave_ids: Vec<i32> = (0..THREADS)
+            .map(|i| {
+                let day = first_day + Duration::days(i as i64);
+                databases[0]
+                    .request_leave(
+                        worker.id,
+                        LeaveRequest { leave_type: LeaveType::Vacation, starts_on: day, ends_on: day, reason: None },
+                    )
+                    .unwrap()
+                    .id
+            })
+            .collect();
+        accrue(&databases[0], worker.id, 2.0 - THREADS as f64, boss.id);
+        
+        let handles: Vec<_> = leave_ids
+            .into_iter()
+            .enumerate()
+            .map(|(i, leave_id)| {
+                let database = Arc::clone(&databases[i % databases.len()]);
+                thread::spawn(move || database.decide_leave(leave_id, boss.id, true, None))
+            })
+            .collect();
+            
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 2);
+        for result in results.iter().filter(|r| r.is_err()) {
+            assert!(matches!(result, Err(LeaveError::InsufficientBalance { .. })), "{:?}", result);
+        }
+        
+        drop(databases);
+        remove_sqlite(&path);
+    }
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, month, day).unwrap()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn shift(start: NaiveTime, end: NaiveTime, split: Option<(NaiveTime, NaiveTime)>) -> Shift {
+        Shift {
+            id: 1,
+            name: "Test".to_string(),
+            start_time: start,
+            end_time: end,
+            split_start_time: split.map(|(start, _)| start),
+            split_end_time: split.map(|(_, end)| end),
+        }
+    }
+
+    #[test]
+    fn weekly_pattern_follows_the_weekdays() {
+        let pattern = SchedulePattern::Weekly { weekdays: vec![Weekday::Mon, Weekday::Wed] };
+        
+        // 2026-03-02 is a Monday
+        let worked: Vec<_> = (2..9).filter(|&day| pattern.works_on(date(1, 1), date(3, day))).collect();
+        
+        assert_eq!(worked, vec![2, 4]);
+    }
+
+    #[test]
+    fn rotation_repeats_from_the_anchor() {
+        let pattern = SchedulePattern::Rotating { days_on: 2, days_off: 3 };
+        let anchor = date(3, 10);
+        
+        let worked: Vec<_> = (10..21).filter(|&day| pattern.works_on(anchor, date(3, day))).collect();
+        
+        assert_eq!(worked, vec![10, 11, 15, 16, 20]);
+    }
+
+    #[test]
+    fn rotation_extends_back_before_the_anchor() {
+        let pattern = SchedulePattern::Rotating { days_on: 2, days_off: 3 };
+        let anchor = date(3, 10);
+        
+        let worked: Vec<_> = (1..10).filter(|&day| pattern.works_on(anchor, date(3, day))).collect();
+        
+        assert_eq!(worked, vec![1, 5, 6]);
+    }
+
+    #[test]
+    fn empty_rotation_is_never_worked() {
+        let pattern = SchedulePattern::Rotating { days_on: 0, days_off: 0 };
+        
+        assert!(!pattern.works_on(date(3, 10), date(3, 10)));
+    }
+
+    #[test]
+    fn assignment_is_only_worked_between_its_dates() {
+        let assignment = ScheduleAssignment {
+            id: 1,
+            user_id: 1,
+            shift_id: 1,
+            pattern: SchedulePattern::Rotating { days_on: 1, days_off: 1 },
+            starts_on: date(3, 10),
+            ends_on: Some(date(3, 14)),
+        };
+        
+        let worked: Vec<_> = (1..31).filter(|&day| assignment.works_on(date(3, day))).collect();
+        
+        assert_eq!(worked, vec![10, 12, 14]);
+    }
+
+    #[test]
+    fn day_shift_ends_the_same_day() {
+        let segments = shift(time(9, 0), time(17, 0), None).segments_on(date(3, 10));
+        
+        assert_eq!(segments, vec![(date(3, 10).and_time(time(9, 0)), date(3, 10).and_time(time(17, 0)))]);
+    }
+
+    #[test]
+    fn overnight_shift_ends_the_next_day() {
+        let segments = shift(time(22, 0), time(6, 0), None).segments_on(date(3, 10));
+        
+        assert_eq!(segments, vec![(date(3, 10).and_time(time(22, 0)), date(3, 11).and_time(time(6, 0)))]);
+    }
+
+    #[test]
+    fn split_shift_has_a_second_segment_after_the_first() {
+        let segments = shift(time(7, 0), time(11, 0), Some((time(16, 0), time(20, 0)))).segments_on(date(3, 10));
+        
+        assert_eq!(
+            segments,
+            vec![
+                (date(3, 10).and_time(time(7, 0)), date(3, 10).and_time(time(11, 0))),
+                (date(3, 10).and_time(time(16, 0)), date(3, 10).and_time(time(20, 0))),
+            ]
+        );
+    }
+
+    #[test]
+    fn split_shift_second_segment_can_start_past_midnight() {
+        let segments = shift(time(18, 0), time(22, 0), Some((time(1, 0), time(4, 0)))).segments_on(date(3, 10));
+        
+        assert_eq!(
+            segments,
+            vec![
+                (date(3, 10).and_time(time(18, 0)), date(3, 10).and_time(time(22, 0))),
+                (date(3, 11).and_time(time(1, 0)), date(3, 11).and_time(time(4, 0))),
+            ]
+        );
+    }
+
+    fn shift_request(start: NaiveTime, end: NaiveTime, split: Option<(NaiveTime, NaiveTime)>) -> ShiftRequest {
+        ShiftRequest {
+            name: "Shift".to_string(),
+            start_time: start,
+            end_time: end,
+            split_start_time: split.map(|(start, _)| start),
+            split_end_time: split.map(|(_, end)| end),
+        }
+    }
+
+    fn shift_error_fields(req: ShiftRequest) -> Vec<&'static str> {
+        match Database::validate_shift(req) {
+            Ok(_) => Vec::new(),
+            Err(ScheduleError::Validation(errors)) => errors.into_iter().map(|e| e.field).collect(),
+            Err(e) => panic!("{e}"),
+        }
+    }
+
+    #[test]
+    fn validate_shift_accepts_overnight_and_split_shifts() {
+        assert!(shift_error_fields(shift_request(time(22, 0), time(6, 0), None)).is_empty());
+        assert!(shift_error_fields(shift_request(time(7, 0), time(11, 0), Some((time(16, 0), time(20, 0))))).is_empty());
+        assert!(shift_error_fields(shift_request(time(18, 0), time(22, 0), Some((time(1, 0), time(4, 0))))).is_empty());
+    }
+
+    #[test]
+    fn validate_shift_rejects_bad_layouts() {
+        assert_eq!(shift_error_fields(shift_request(time(9, 0), time(9, 0), None)), ["end_time"]);
+        assert_eq!(
+            shift_error_fields(shift_request(time(9, 0), time(13, 0), Some((time(14, 0), time(14, 0))))),
+            ["split_end_time"]
+        );
+        // The second half would end more than a day after the shift starts
+        assert_eq!(
+            shift_error_fields(shift_request(time(9, 0), time(13, 0), Some((time(8, 0), time(10, 0))))),
+            ["split_start_time"]
+        );
+        
+        let mut half_split = shift_request(time(9, 0), time(13, 0), None);
+        half_split.split_start_time = Some(time(14, 0));
+        assert_eq!(shift_error_fields(half_split), ["split_end_time"]);
+    }
+
+    // Holds in any time zone: every local time converts back to itself,
+    // except one skipped by a DST change, which lands within the hour after it
+    #[test]
+    fn local_times_round_trip_through_utc() {
+        let mut local = date(1, 1).and_time(time(0, 30));
+        while local < date(12, 31).and_time(time(0, 0)) {
+            let back = utc_to_local(local_to_utc(local));
+            
+            assert!(back >= local && back <= local + Duration::hours(1), "{local} came back as {back}");
+            local += Duration::minutes(30);
+        }
+    }
+
+    // The plaintext is only known at login, which is when a legacy bcrypt
+    // hash gets replaced
+    #[test]
+    fn login_upgrades_bcrypt_hashes_sqlite() {
+        let path = temp_sqlite_path();
+        let database = Database::open(&format!("sqlite://{}", path.display())).unwrap();
+        let user = create_user(&database, "legacy");
+        let legacy = bcrypt::hash("correct-horse-42", 4).unwrap();
+        database.storage().unwrap().update_password_hash(user.id, &legacy).unwrap();
+        
+        database.authenticate("legacy", "correct-horse-42").unwrap();
+        
+        let (_, upgraded) = database.storage().unwrap().find_user_credentials("legacy").unwrap().unwrap();
+        assert!(upgraded.starts_with("$argon2id$"), "{}", upgraded);
+        assert!(database.authenticate("legacy", "correct-horse-42").is_ok());
+        
+        remove_sqlite(&path);
+    }
+
+    fn temp_local_dir() -> std::path::PathBuf {
+        let dir = env::temp_dir().join(format!("attendance-kiosk-{}", random_hex(8)));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    // A kiosk restarted while the server is down still accepts punches from
+    // sessions it confirmed earlier, queues them, and syncs them later
+    #[test]
+    fn punches_are_queued_after_an_offline_cold_start() {
+        let server = temp_sqlite_path();
+        let url = format!("sqlite://{}", server.display());
+        let local_dir = temp_local_dir();
+        
+        let online = Database::open_kiosk(&url, &local_dir, true).unwrap();
+        let user = create_user(&online, "nightshift");
+        let session = online
+            .login_user(LoginRequest {
+                username: "nightshift".to_string(),
+                password: "correct-horse-42".to_string(),
+            })
+            .unwrap();
+        online.validate_session(&session.token).unwrap();
+        online.clock_in(user.id).unwrap();
+        assert_eq!(online.refresh_offline_cache().unwrap(), 1);
+        drop(online);
+        
+        let offline = Database::open_kiosk(&url, &local_dir, false).unwrap();
+        assert!(matches!(
+            offline.get_user(user.id),
+            Err(AuthError::Database(StorageError::Unavailable(_)))
+        ));
+        
+        let caller = offline.validate_session(&session.token).unwrap();
+        assert_eq!(caller.id, user.id);
+        assert!(matches!(
+            offline.validate_session("never-seen"),
+            Err(AuthError::Database(StorageError::Unavailable(_)))
+        ));
+        
+        // The cached last punch decides the direction of the toggle
+        let clock_out = offline.clock_toggle(caller.id).unwrap();
+        let clock_in = offline.clock_toggle(caller.id).unwrap();
+        assert!(clock_out.queued && clock_in.queued);
+        assert_eq!(clock_out.event_type, AttendanceEventType::Out);
+        assert_eq!(clock_in.event_type, AttendanceEventType::In);
+        assert_eq!(offline.status().queued_punches, 2);
+        
+        offline.init().unwrap();
+        let report = offline.sync_pending_punches().unwrap();
+        assert_eq!((report.synced, report.rejected, report.remaining), (2, 0, 0));
+        assert_eq!(offline.get_attendance_events(user.id, None, None).unwrap().len(), 3);
+        
+        drop(offline);
+        remove_sqlite(&server);
+        let _ = std::fs::remove_dir_all(&local_dir);
+    }
+
+    // Deactivation reaches the cache at once, not only at the next refresh
+    #[test]
+    fn deactivated_users_lose_their_cached_sessions() {
+        let server = temp_sqlite_path();
+        let url = format!("sqlite://{}", server.display());
+        let local_dir = temp_local_dir();
+        
+        let online = Database::open_kiosk(&url, &local_dir, true).unwrap();
+        let user = create_user(&online, "leaver");
+        let session = online
+            .login_user(LoginRequest {
+                username: "leaver".to_string(),
+                password: "correct-horse-42".to_string(),
+            })
+            .unwrap();
+        online.validate_session(&session.token).unwrap();
+        online.deactivate_user(user.id).unwrap();
+        drop(online);
+        
+        let offline = Database::open_kiosk(&url, &local_dir, false).unwrap();
+        assert!(offline.validate_session(&session.token).is_err());
+        
+        drop(offline);
+        remove_sqlite(&server);
+        let _ = std::fs::remove_dir_all(&local_dir);
+    }
+
+    // Badges verify against the cache while the server is down, PINs
+    // included, and failures there lock the terminal like they do online
+    #[test]
+    fn badges_are_verified_offline_and_punches_queued() {
+        let server = temp_sqlite_path();
+        let url = format!("sqlite://{}", server.display());
+        let local_dir = temp_local_dir();
+        
+        let online = Database::open_kiosk(&url, &local_dir, true).unwrap();
+        let with_pin = create_user(&online, "withpin");
+        let badge_only = create_user(&online, "badgeonly");
+        online
+            .set_kiosk_credentials(with_pin.id, KioskCredentialsRequest {
+                badge_number: Some("B1001".to_string()),
+                pin: Some("4821".to_string()),
+            })
+            .unwrap();
+        online
+            .set_kiosk_credentials(badge_only.id, KioskCredentialsRequest {
+                badge_number: Some("B1002".to_string()),
+                pin: None,
+            })
+            .unwrap();
+        online.refresh_offline_cache().unwrap();
+        drop(online);
+        
+        let offline = Database::open_kiosk(&url, &local_dir, false).unwrap();
+        
+        assert!(matches!(offline.authenticate_badge("b1001", None), Err(AuthError::PinRequired)));
+        assert!(matches!(offline.authenticate_badge("B1001", Some("0000")), Err(AuthError::InvalidCredentials)));
+        assert!(matches!(offline.authenticate_badge("B1002", Some("0000")), Err(AuthError::InvalidCredentials)));
+        assert!(matches!(offline.authenticate_badge("B9999", None), Err(AuthError::InvalidCredentials)));
+        
+        let user = offline.authenticate_badge("B1001", Some("4821")).unwrap();
+        assert_eq!(user.id, with_pin.id);
+        let user = offline.authenticate_badge("b1002", None).unwrap();
+        assert_eq!(user.id, badge_only.id);
+        
+        let event = offline.clock_toggle(user.id).unwrap();
+        assert!(event.queued);
+        
+        // The success above started the count over
+        for _ in 1..lockout::DEVICE_MAX_FAILURES {
+            assert!(matches!(offline.authenticate_badge("B9999", None), Err(AuthError::InvalidCredentials)));
+        }
+        assert!(matches!(offline.authenticate_badge("B9999", None), Err(AuthError::AccountLocked { .. })));
+        assert!(matches!(offline.authenticate_badge("B1002", None), Err(AuthError::AccountLocked { .. })));
+        
+        offline.init().unwrap();
+        assert_eq!(offline.sync_pending_punches().unwrap().synced, 1);
+        assert_eq!(offline.get_attendance_events(badge_only.id, None, None).unwrap().len(), 1);
+        
+        drop(offline);
+        remove_sqlite(&server);
+        let _ = std::fs::remove_dir_all(&local_dir);
+    }
+
+    // QR codes verify offline by their signature alone; reuse is caught in
+    // the queue at once and on the server when the punch syncs
+    #[test]
+    fn qr_codes_are_redeemed_when_offline_punches_sync() {
+        let server = temp_sqlite_path();
+        let url = format!("sqlite://{}", server.display());
+        let local_dir = temp_local_dir();
+        
+        let online = Database::open_kiosk(&url, &local_dir, true).unwrap();
+        let replayed = create_user(&online, "replayed");
+        let honest = create_user(&online, "honest");
+        online.refresh_offline_cache().unwrap();
+        drop(online);
+        
+        let offline = Database::open_kiosk(&url, &local_dir, false).unwrap();
+        let code = offline.qr_code(replayed.id);
+        
+        let pass = offline.authenticate_qr_code(&code.payload).unwrap();
+        assert_eq!(pass.user.id, replayed.id);
+        let event = offline.punch_with_qr_pass(&pass, None).unwrap();
+        assert!(event.queued);
+        assert_eq!(event.event_type, AttendanceEventType::In);
+        
+        assert!(matches!(offline.authenticate_qr_code(&code.payload), Err(AuthError::QrCodeUsed)));
+        assert!(matches!(offline.authenticate_qr_code("ATTQR1.1.2.00"), Err(AuthError::InvalidQrCode)));
+        
+        let other = offline.authenticate_qr_code(&offline.qr_code(honest.id).payload).unwrap();
+        offline.punch_with_qr_pass(&other, Some(AttendanceEventType::In)).unwrap();
+        
+        offline.init().unwrap();
+        
+        // Meanwhile the same code was used on a kiosk that stayed online
+        let claim = pass.unredeemed.unwrap();
+        assert!(Database::redeem_qr_claim(offline.storage().unwrap(), claim, now_utc()).unwrap());
+        
+        let report = offline.sync_pending_punches().unwrap();
+        assert_eq!((report.synced, report.rejected, report.remaining), (1, 1, 0));
+        assert!(offline.get_attendance_events(replayed.id, None, None).unwrap().is_empty());
+        assert_eq!(offline.get_attendance_events(honest.id, None, None).unwrap().len(), 1);
+        
+        // Once synced, the code is spent on the server too
+        let code = offline.qr_code(honest.id);
+        assert!(matches!(offline.authenticate_qr_code(&code.payload), Err(AuthError::QrCodeUsed)));
+        
+        drop(offline);
+        remove_sqlite(&server);
+        let _ = std::fs::remove_dir_all(&local_dir);
+    }
+
+    // The server refuses a queued punch it cannot accept; it is kept as
+    // rejected and the punches after it still sync
+    #[test]
+    fn sync_rejects_refused_punches_and_carries_on() {
+        let server = temp_sqlite_path();
+        let url = format!("sqlite://{}", server.display());
+        let local_dir = temp_local_dir();
+        
+        let online = Database::open_kiosk(&url, &local_dir, true).unwrap();
+        let user = create_user(&online, "outfirst");
+        drop(online);
+        
+        let offline = Database::open_kiosk(&url, &local_dir, false).unwrap();
+        // Nothing is known offline to rule out a clock-out first
+        offline.clock_out(user.id).unwrap();
+        offline.clock_in(user.id).unwrap();
+        offline.clock_out(user.id).unwrap();
+        
+        let report = offline.sync_pending_punches().unwrap();
+        assert_eq!((report.synced, report.rejected, report.remaining), (0, 0, 3));
+        
+        offline.init().unwrap();
+        let report = offline.sync_pending_punches().unwrap();
+        assert_eq!((report.synced, report.rejected, report.remaining), (2, 1, 0));
+        
+        let events = offline.get_attendance_events(user.id, None, None).unwrap();
+        let types: Vec<_> = events.iter().map(|e| e.event_type).collect();
+        assert_eq!(types.len(), 2);
+        assert!(types.contains(&AttendanceEventType::In) && types.contains(&AttendanceEventType::Out));
+        
+        drop(offline);
+        remove_sqlite(&server);
+        let _ = std::fs::remove_dir_all(&local_dir);
+    }
+
+    // A server that drops mid-replay leaves every punch pending for the next
+    // sync instead of rejecting it
+    #[cfg(feature = "mysql")]
+    #[test]
+    fn sync_stops_when_the_server_is_unreachable() {
+        let local_dir = temp_local_dir();
+        // Nothing listens on port 1, so every query fails to connect
+        let storage = storage::connect("mysql://attendance@127.0.0.1:1/attendance").unwrap();
+        let backend = Backend::with_local_state(
+            storage,
+            &local_dir.join("punch_queue.db").to_string_lossy(),
+            &local_dir.join("offline_cache.db").to_string_lossy(),
+        )
+        .unwrap();
+        let database = Database::new_unconnected();
+        let _ = database.backend.set(backend);
+        // As if migrations had run before the server went away
+        database.initialized.store(true, Ordering::SeqCst);
+        
+        let queue = database.queue().unwrap();
+        for (key, event_type) in [("a", AttendanceEventType::In), ("b", AttendanceEventType::Out)] {
+            let punch = Punch {
+                idempotency_key: key.to_string(),
+                user_id: 1,
+                event_type,
+                event_time: now_utc(),
+            };
+            queue.enqueue(&punch, None).unwrap();
+        }
+        
+        let report = database.sync_pending_punches().unwrap();
+        
+        assert_eq!((report.synced, report.rejected, report.remaining), (0, 0, 2));
+        assert_eq!(queue.pending().unwrap().len(), 2);
+        
+        drop(database);
+        let _ = std::fs::remove_dir_all(&local_dir);
+    }
+}
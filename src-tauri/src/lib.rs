@@ -2,26 +2,273 @@
 
 mod setup;
 mod database;
+mod lockout;
+mod migrations;
+mod offline_cache;
+mod password;
+mod pay_period;
+mod permissions;
+mod punch_queue;
+mod qr;
+mod rules;
+mod scanner;
+mod storage;
+mod timesheet;
+mod validation;
 
-use database::{Database, User, CreateUserRequest, RegisterRequest, LoginRequest, AuthError};
+use database::{
+    Database, DatabaseStatus, User, UserPage, Session, CreateUserRequest, UpdateUserRequest, ListUsersRequest,
+    RegisterRequest, LoginRequest, ChangePasswordRequest, PasswordReset, KioskCredentialsRequest,
+    BadgePunchRequest, KioskPunch, AuthError, AttendanceEvent, AttendanceEventType, Shift, ShiftRequest,
+    ScheduleAssignment, ScheduleAssignmentRequest, OnSiteWorker, ScheduleError, AttendanceException, PayPeriodError,
+    PayPeriodStatus, PeriodLock, AttendanceCorrection, CorrectionError, CorrectionRequest, CorrectionStatus,
+    Leave, LeaveAccrual, LeaveAccrualRequest, LeaveBalance, LeaveError, LeaveRequest, LeaveStatus, now_utc,
+};
+use permissions::Permission;
+use qr::QrCode;
+use rules::DayAttendance;
+use scanner::{BadgeScanResult, ScanDetector};
+use timesheet::Timesheet;
+use chrono::{NaiveDate, NaiveDateTime};
 use setup::SystemSetup;
 use tauri::{Manager, Emitter};
 use anyhow::Result;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use serde::Serialize;
+use validation::FieldError;
+
+// Also how often an unavailable database is retried
+const PUNCH_SYNC_INTERVAL: Duration = Duration::from_secs(15);
+// How often the worker recomputes recent exceptions, so a shift that ends
+// without any punch still becomes one, and refreshes the offline cache
+const EXCEPTION_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+// Error payload for commands whose failures the frontend maps onto form
+// fields; `fields` is empty for errors that are not about a single input
+#[derive(Debug, Serialize)]
+struct CommandError {
+    message: String,
+    fields: Vec<FieldError>,
+}
+
+impl From<String> for CommandError {
+    fn from(message: String) -> Self {
+        CommandError { message, fields: Vec::new() }
+    }
+}
+
+impl From<AuthError> for CommandError {
+    fn from(e: AuthError) -> Self {
+        let message = e.to_string();
+        let fields = match e {
+            AuthError::Validation(fields) => fields,
+            AuthError::UsernameTaken => vec![FieldError { field: "username", message: message.clone() }],
+            AuthError::PinRequired => vec![FieldError { field: "pin", message: message.clone() }],
+            _ => Vec::new(),
+        };
+        CommandError { message, fields }
+    }
+}
+
+impl From<ScheduleError> for CommandError {
+    fn from(e: ScheduleError) -> Self {
+        let message = e.to_string();
+        let fields = match e {
+            ScheduleError::Validation(fields) => fields,
+            ScheduleError::ShiftNameTaken => vec![FieldError { field: "name", message: message.clone() }],
+            ScheduleError::UserNotFound => vec![FieldError { field: "user_id", message: message.clone() }],
+            _ => Vec::new(),
+        };
+        CommandError { message, fields }
+    }
+}
+
+impl From<CorrectionError> for CommandError {
+    fn from(e: CorrectionError) -> Self {
+        let message = e.to_string();
+        let fields = match e {
+            CorrectionError::Validation(fields) => fields,
+            CorrectionError::EventNotFound => vec![FieldError { field: "event_id", message: message.clone() }],
+            _ => Vec::new(),
+        };
+        CommandError { message, fields }
+    }
+}
+
+impl From<LeaveError> for CommandError {
+    fn from(e: LeaveError) -> Self {
+        let message = e.to_string();
+        let fields = match e {
+            LeaveError::Validation(fields) => fields,
+            LeaveError::InsufficientBalance { .. } => vec![FieldError { field: "leave_type", message: message.clone() }],
+            _ => Vec::new(),
+        };
+        CommandError { message, fields }
+    }
+}
+
+impl From<PayPeriodError> for CommandError {
+    fn from(e: PayPeriodError) -> Self {
+        let message = e.to_string();
+        let fields = match e {
+            PayPeriodError::Validation(fields) => fields,
+            _ => Vec::new(),
+        };
+        CommandError { message, fields }
+    }
+}
+
+// Every privileged command resolves its caller from the session token
+// before touching the database
+fn authorize(database: &Database, token: &str) -> Result<User, String> {
+    database
+        .validate_session(token)
+        .map_err(|e| e.to_string())
+}
+
+fn require(user: &User, permission: Permission) -> Result<(), String> {
+    if user.role.grants(permission) {
+        Ok(())
+    } else {
+        Err(AuthError::Forbidden.to_string())
+    }
+}
+
+// Admins cannot lock themselves out by deactivating, deleting or demoting
+// their own account
+fn require_other_user(caller: &User, user_id: i32) -> Result<(), String> {
+    if caller.id == user_id {
+        Err(AuthError::SelfModification.to_string())
+    } else {
+        Ok(())
+    }
+}
+
+// Anyone may read their own log, supervisors their direct reports and
+// admins everyone
+fn require_attendance_access(database: &Database, user: &User, user_id: i32) -> Result<(), String> {
+    if user.id == user_id || user.role.grants(Permission::ViewAllAttendance) {
+        return Ok(());
+    }
+    
+    if user.role.grants(Permission::ViewTeamAttendance)
+        && database
+            .is_supervisor_of(user.id, user_id)
+            .map_err(|e| e.to_string())?
+    {
+        return Ok(());
+    }
+    
+    Err(AuthError::Forbidden.to_string())
+}
+
+// Employees punch only themselves; punching for someone else is an edit
+fn require_punch(user: &User, user_id: Option<i32>) -> Result<i32, String> {
+    match user_id {
+        Some(user_id) if user_id != user.id => {
+            require(user, Permission::EditAttendance)?;
+            Ok(user_id)
+        }
+        _ => {
+            require(user, Permission::PunchSelf)?;
+            Ok(user.id)
+        }
+    }
+}
+
+// Runs database work on the blocking pool. Commands that hash passwords
+// use it so bcrypt never stalls the UI thread.
+async fn run_blocking<T, F>(app: tauri::AppHandle, work: F) -> Result<T, String>
+where
+    F: FnOnce(&Database) -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tauri::async_runtime::spawn_blocking(move || work(&app.state::<Database>()))
+        .await
+        .map_err(|e| e.to_string())
+}
 
 #[tauri::command]
-fn create_user(
-    database: tauri::State<Database>, 
+async fn create_user(
+    app: tauri::AppHandle,
+    token: String,
     request: CreateUserRequest
-) -> Result<User, String> {
+) -> Result<User, CommandError> {
+    run_blocking(app, move |database| {
+        let caller = authorize(database, &token)?;
+        require(&caller, Permission::ManageUsers)?;
+        Ok(database.create_user(request)?)
+    })
+    .await?
+}
+
+#[tauri::command]
+fn get_users(database: tauri::State<Database>, token: String) -> Result<Vec<User>, String> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageUsers)?;
     database
-        .create_user(request)
+        .get_all_users()
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-fn get_users(database: tauri::State<Database>) -> Result<Vec<User>, String> {
+fn list_users(
+    database: tauri::State<Database>,
+    token: String,
+    request: Option<ListUsersRequest>
+) -> Result<UserPage, String> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageUsers)?;
     database
-        .get_all_users()
+        .list_users(request.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_user(database: tauri::State<Database>, token: String, user_id: i32) -> Result<User, String> {
+    let caller = authorize(&database, &token)?;
+    if caller.id != user_id {
+        require(&caller, Permission::ManageUsers)?;
+    }
+    database
+        .get_user(user_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_user(
+    database: tauri::State<Database>,
+    token: String,
+    user_id: i32,
+    request: UpdateUserRequest
+) -> Result<User, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageUsers)?;
+    if request.role != caller.role {
+        require_other_user(&caller, user_id)?;
+    }
+    Ok(database.update_user(user_id, request)?)
+}
+
+#[tauri::command]
+fn deactivate_user(database: tauri::State<Database>, token: String, user_id: i32) -> Result<User, String> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageUsers)?;
+    require_other_user(&caller, user_id)?;
+    database
+        .deactivate_user(user_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_user(database: tauri::State<Database>, token: String, user_id: i32) -> Result<User, String> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageUsers)?;
+    require_other_user(&caller, user_id)?;
+    database
+        .delete_user(user_id)
         .map_err(|e| e.to_string())
 }
 
@@ -39,35 +286,701 @@ async fn check_system_requirements(app: tauri::AppHandle) -> Result<String, Stri
     }
 }
 
+// Public so the window can explain why it is running degraded
+#[tauri::command]
+fn database_status(database: tauri::State<Database>) -> DatabaseStatus {
+    database.status()
+}
+
+#[tauri::command]
+async fn register_user(app: tauri::AppHandle, request: RegisterRequest) -> Result<User, CommandError> {
+    run_blocking(app, move |database| Ok(database.register_user(request)?)).await?
+}
+
+#[tauri::command]
+async fn login_user(app: tauri::AppHandle, request: LoginRequest) -> Result<Session, String> {
+    run_blocking(app, move |database| {
+        database
+            .login_user(request)
+            .map_err(|e| e.to_string())
+    })
+    .await?
+}
+
+// Public: the current password is the proof of identity
+#[tauri::command]
+async fn change_password(app: tauri::AppHandle, request: ChangePasswordRequest) -> Result<User, CommandError> {
+    run_blocking(app, move |database| Ok(database.change_password(request)?)).await?
+}
+
+#[tauri::command]
+async fn reset_password(app: tauri::AppHandle, token: String, user_id: i32) -> Result<PasswordReset, String> {
+    run_blocking(app, move |database| {
+        let caller = authorize(database, &token)?;
+        require(&caller, Permission::ManageUsers)?;
+        require_other_user(&caller, user_id)?;
+        database
+            .reset_password(user_id)
+            .map_err(|e| e.to_string())
+    })
+    .await?
+}
+
+#[tauri::command]
+fn logout_user(database: tauri::State<Database>, token: String) -> Result<(), String> {
+    database
+        .logout_user(&token)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn unlock_account(database: tauri::State<Database>, token: String, user_id: i32) -> Result<(), String> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageUsers)?;
+    database
+        .unlock_account(user_id)
+        .map_err(|e| e.to_string())
+}
+
+// `device_id` is the DEVICE_ID of the kiosk to unlock, this one by default
+#[tauri::command]
+fn unlock_device(database: tauri::State<Database>, token: String, device_id: Option<String>) -> Result<(), String> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageUsers)?;
+    database
+        .unlock_device(device_id.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn clock_in(database: tauri::State<Database>, token: String, user_id: Option<i32>) -> Result<AttendanceEvent, String> {
+    let caller = authorize(&database, &token)?;
+    let user_id = require_punch(&caller, user_id)?;
+    database
+        .clock_in(user_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn clock_out(database: tauri::State<Database>, token: String, user_id: Option<i32>) -> Result<AttendanceEvent, String> {
+    let caller = authorize(&database, &token)?;
+    let user_id = require_punch(&caller, user_id)?;
+    database
+        .clock_out(user_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn start_break(database: tauri::State<Database>, token: String, user_id: Option<i32>) -> Result<AttendanceEvent, String> {
+    let caller = authorize(&database, &token)?;
+    let user_id = require_punch(&caller, user_id)?;
+    database
+        .start_break(user_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn end_break(database: tauri::State<Database>, token: String, user_id: Option<i32>) -> Result<AttendanceEvent, String> {
+    let caller = authorize(&database, &token)?;
+    let user_id = require_punch(&caller, user_id)?;
+    database
+        .end_break(user_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_kiosk_credentials(
+    app: tauri::AppHandle,
+    token: String,
+    user_id: i32,
+    request: KioskCredentialsRequest
+) -> Result<User, CommandError> {
+    run_blocking(app, move |database| {
+        let caller = authorize(database, &token)?;
+        require(&caller, Permission::ManageUsers)?;
+        Ok(database.set_kiosk_credentials(user_id, request)?)
+    })
+    .await?
+}
+
+// Public: badge and PIN stand in for a session, but only for the caller's
+// own clock-in/out. The PIN may be left out for users who have none; a
+// `pin` field error means the kiosk must ask for it.
+#[tauri::command]
+async fn punch_by_badge(app: tauri::AppHandle, request: BadgePunchRequest) -> Result<KioskPunch, CommandError> {
+    run_blocking(app, move |database| {
+        let user = database.authenticate_badge(&request.badge_number, request.pin.as_deref())?;
+        Ok(kiosk_punch(database, user, request.event_type)?)
+    })
+    .await?
+}
+
+// Shared by the kiosk commands once the badge or code has identified `user`;
+// no event type toggles between in and out, or ends a break in progress
+fn kiosk_punch(
+    database: &Database,
+    user: User,
+    event_type: Option<AttendanceEventType>
+) -> Result<KioskPunch, String> {
+    require(&user, Permission::PunchSelf)?;
+    
+    let event = match event_type {
+        Some(AttendanceEventType::In) => database.clock_in(user.id),
+        Some(AttendanceEventType::Out) => database.clock_out(user.id),
+        Some(AttendanceEventType::BreakStart) => database.start_break(user.id),
+        Some(AttendanceEventType::BreakEnd) => database.end_break(user.id),
+        None => database.clock_toggle(user.id),
+    }
+    .map_err(|e| e.to_string())?;
+    
+    Ok(KioskPunch { user, event })
+}
+
+// Public: fed every key press outside a form by the frontend so a
+// keyboard-wedge badge reader works without focusing a field. A completed
+// scan clocks the badge holder in or out, or asks for their PIN when they
+// have one, and reports through the `badge-scanned` event. Anyone able to
+// type fast enough can pass as the reader, which is why PIN holders are
+// never punched by a scan alone.
+#[tauri::command]
+fn scanner_key(
+    app: tauri::AppHandle,
+    detector: tauri::State<Mutex<ScanDetector>>,
+    key: String,
+    timestamp_ms: f64
+) {
+    let scanned = detector
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .key(&key, timestamp_ms as u64);
+    let Some(badge_number) = scanned else {
+        return;
+    };
+    
+    tauri::async_runtime::spawn(async move {
+        let emitter = app.clone();
+        let badge = badge_number.clone();
+        let result = run_blocking(app, move |database| scan_badge(database, badge))
+            .await
+            .unwrap_or_else(|error| BadgeScanResult { badge_number, error: Some(error), ..BadgeScanResult::default() });
+        
+        let _ = emitter.emit("badge-scanned", result);
+    });
+}
+
+// Punches for a scanned badge unless its holder has to enter a PIN first
+fn scan_badge(database: &Database, badge_number: String) -> BadgeScanResult {
+    let user = match database.authenticate_badge(&badge_number, None) {
+        Ok(user) => user,
+        Err(AuthError::PinRequired) => {
+            return BadgeScanResult { badge_number, pin_required: true, ..BadgeScanResult::default() };
+        }
+        Err(e) => return BadgeScanResult { badge_number, error: Some(e.to_string()), ..BadgeScanResult::default() },
+    };
+    
+    match kiosk_punch(database, user.clone(), None) {
+        Ok(KioskPunch { user, event }) => {
+            BadgeScanResult { badge_number, user: Some(user), event: Some(event), ..BadgeScanResult::default() }
+        }
+        Err(error) => BadgeScanResult { badge_number, user: Some(user), error: Some(error), ..BadgeScanResult::default() },
+    }
+}
+
+// The caller's current QR code; the app asks again once `expires_at` passes
+#[tauri::command]
+fn get_qr_code(database: tauri::State<Database>, token: String) -> Result<QrCode, String> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::PunchSelf)?;
+    Ok(database.qr_code(caller.id))
+}
+
+// Public: a fresh, unused QR code from the employee's phone stands in for
+// a session, like a badge and PIN
+#[tauri::command]
+fn punch_by_qr(
+    database: tauri::State<Database>,
+    payload: String,
+    event_type: Option<AttendanceEventType>
+) -> Result<KioskPunch, String> {
+    let pass = database
+        .authenticate_qr_code(&payload)
+        .map_err(|e| e.to_string())?;
+    require(&pass.user, Permission::PunchSelf)?;
+    
+    // Unlike a badge, a code checked offline must go out with its punch
+    let event = database
+        .punch_with_qr_pass(&pass, event_type)
+        .map_err(|e| e.to_string())?;
+        
+    Ok(KioskPunch { user: pass.user, event })
+}
+
+#[tauri::command]
+fn create_shift(database: tauri::State<Database>, token: String, request: ShiftRequest) -> Result<Shift, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageSchedules)?;
+    Ok(database.create_shift(request)?)
+}
+
+#[tauri::command]
+fn list_shifts(database: tauri::State<Database>, token: String) -> Result<Vec<Shift>, String> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ViewSchedules)?;
+    database
+        .list_shifts()
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_shift(
+    database: tauri::State<Database>,
+    token: String,
+    shift_id: i32,
+    request: ShiftRequest
+) -> Result<Shift, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageSchedules)?;
+    Ok(database.update_shift(shift_id, request)?)
+}
+
+#[tauri::command]
+fn delete_shift(database: tauri::State<Database>, token: String, shift_id: i32) -> Result<(), String> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageSchedules)?;
+    database
+        .delete_shift(shift_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn create_schedule_assignment(
+    database: tauri::State<Database>,
+    token: String,
+    request: ScheduleAssignmentRequest
+) -> Result<ScheduleAssignment, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageSchedules)?;
+    Ok(database.create_schedule_assignment(request)?)
+}
+
+// Everyone may read their own schedule
+#[tauri::command]
+fn list_schedule_assignments(
+    database: tauri::State<Database>,
+    token: String,
+    user_id: Option<i32>
+) -> Result<Vec<ScheduleAssignment>, String> {
+    let caller = authorize(&database, &token)?;
+    if user_id != Some(caller.id) {
+        require(&caller, Permission::ViewSchedules)?;
+    }
+    database
+        .list_schedule_assignments(user_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_schedule_assignment(
+    database: tauri::State<Database>,
+    token: String,
+    assignment_id: i32,
+    request: ScheduleAssignmentRequest
+) -> Result<ScheduleAssignment, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageSchedules)?;
+    Ok(database.update_schedule_assignment(assignment_id, request)?)
+}
+
 #[tauri::command]
-fn register_user(database: tauri::State<Database>, request: RegisterRequest) -> Result<User, String> {
+fn delete_schedule_assignment(database: tauri::State<Database>, token: String, assignment_id: i32) -> Result<(), String> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageSchedules)?;
     database
-        .register_user(request)
+        .delete_schedule_assignment(assignment_id)
         .map_err(|e| e.to_string())
 }
 
+// `at` defaults to now
+#[tauri::command]
+fn get_expected_on_site(
+    database: tauri::State<Database>,
+    token: String,
+    at: Option<NaiveDateTime>
+) -> Result<Vec<OnSiteWorker>, String> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ViewSchedules)?;
+    database
+        .expected_on_site(at.unwrap_or_else(now_utc))
+        .map_err(|e| e.to_string())
+}
+
+// `user_id` defaults to the caller
+#[tauri::command]
+fn get_attendance_status(
+    database: tauri::State<Database>,
+    token: String,
+    user_id: Option<i32>,
+    from: NaiveDate,
+    to: NaiveDate
+) -> Result<Vec<DayAttendance>, CommandError> {
+    let caller = authorize(&database, &token)?;
+    let user_id = user_id.unwrap_or(caller.id);
+    require_attendance_access(&database, &caller, user_id)?;
+    Ok(database.attendance_status(user_id, from, to)?)
+}
+
+// `user_id` defaults to the caller
+#[tauri::command]
+fn get_timesheet(
+    database: tauri::State<Database>,
+    token: String,
+    user_id: Option<i32>,
+    from: NaiveDate,
+    to: NaiveDate
+) -> Result<Timesheet, CommandError> {
+    let caller = authorize(&database, &token)?;
+    let user_id = user_id.unwrap_or(caller.id);
+    require_attendance_access(&database, &caller, user_id)?;
+    Ok(database.timesheet(user_id, from, to)?)
+}
+
+// Without `user_id` this covers every user, which needs the global view
 #[tauri::command]
-fn login_user(database: tauri::State<Database>, request: LoginRequest) -> Result<User, String> {
+fn list_attendance_exceptions(
+    database: tauri::State<Database>,
+    token: String,
+    user_id: Option<i32>,
+    from: NaiveDate,
+    to: NaiveDate
+) -> Result<Vec<AttendanceException>, CommandError> {
+    let caller = authorize(&database, &token)?;
+    match user_id {
+        Some(user_id) => require_attendance_access(&database, &caller, user_id)?,
+        None => require(&caller, Permission::ViewAllAttendance)?,
+    }
+    Ok(database.list_attendance_exceptions(user_id, from, to)?)
+}
+
+// Supervisors review their reports' exceptions, never their own
+#[tauri::command]
+fn review_attendance_exception(
+    database: tauri::State<Database>,
+    token: String,
+    exception_id: i32,
+    note: Option<String>
+) -> Result<AttendanceException, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ViewTeamAttendance)?;
+    
+    let exception = database.get_attendance_exception(exception_id)?;
+    if exception.user_id == caller.id {
+        return Err(AuthError::Forbidden.into());
+    }
+    require_attendance_access(&database, &caller, exception.user_id)?;
+    
+    Ok(database.review_attendance_exception(exception_id, caller.id, note)?)
+}
+
+// Employees ask for their own punches to be fixed
+#[tauri::command]
+fn submit_correction(
+    database: tauri::State<Database>,
+    token: String,
+    request: CorrectionRequest
+) -> Result<AttendanceCorrection, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::PunchSelf)?;
+    Ok(database.submit_correction(caller.id, request)?)
+}
+
+// Without `user_id`, everything the caller may decide: all pending
+// corrections for the global view, otherwise those of direct reports
+#[tauri::command]
+fn list_pending_corrections(
+    database: tauri::State<Database>,
+    token: String,
+    user_id: Option<i32>
+) -> Result<Vec<AttendanceCorrection>, CommandError> {
+    let caller = authorize(&database, &token)?;
+    let supervisor_id = match user_id {
+        Some(user_id) => {
+            require_attendance_access(&database, &caller, user_id)?;
+            None
+        }
+        None if caller.role.grants(Permission::ViewAllAttendance) => None,
+        None => {
+            require(&caller, Permission::ViewTeamAttendance)?;
+            Some(caller.id)
+        }
+    };
+    Ok(database.list_corrections(user_id, supervisor_id, Some(CorrectionStatus::Pending))?)
+}
+
+// Like exception reviews, supervisors decide their reports' corrections,
+// never their own
+#[tauri::command]
+fn decide_correction(
+    database: tauri::State<Database>,
+    token: String,
+    correction_id: i32,
+    approve: bool,
+    note: Option<String>
+) -> Result<AttendanceCorrection, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ViewTeamAttendance)?;
+    
+    let correction = database.get_correction(correction_id)?;
+    if correction.user_id == caller.id {
+        return Err(AuthError::Forbidden.into());
+    }
+    require_attendance_access(&database, &caller, correction.user_id)?;
+    
+    Ok(database.decide_correction(correction_id, caller.id, approve, note)?)
+}
+
+#[tauri::command]
+fn list_pay_periods(
+    database: tauri::State<Database>,
+    token: String,
+    from: NaiveDate,
+    to: NaiveDate
+) -> Result<Vec<PayPeriodStatus>, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManagePayroll)?;
+    Ok(database.pay_periods(from, to)?)
+}
+
+// Freezes the pay period containing `date` once payroll has approved it
+#[tauri::command]
+fn lock_period(database: tauri::State<Database>, token: String, date: NaiveDate) -> Result<PeriodLock, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManagePayroll)?;
+    Ok(database.lock_period(date, caller.id)?)
+}
+
+#[tauri::command]
+fn unlock_period(
+    database: tauri::State<Database>,
+    token: String,
+    lock_id: i32,
+    reason: String
+) -> Result<PeriodLock, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManagePayroll)?;
+    Ok(database.unlock_period(lock_id, caller.id, &reason)?)
+}
+
+// Credits days to a user's vacation or sick balance; negative days correct
+// an earlier grant
+#[tauri::command]
+fn accrue_leave(
+    database: tauri::State<Database>,
+    token: String,
+    user_id: i32,
+    request: LeaveAccrualRequest
+) -> Result<LeaveAccrual, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ManageLeave)?;
+    Ok(database.accrue_leave(user_id, request, caller.id)?)
+}
+
+// `user_id` defaults to the caller
+#[tauri::command]
+fn get_leave_balances(
+    database: tauri::State<Database>,
+    token: String,
+    user_id: Option<i32>
+) -> Result<Vec<LeaveBalance>, CommandError> {
+    let caller = authorize(&database, &token)?;
+    let user_id = user_id.unwrap_or(caller.id);
+    require_attendance_access(&database, &caller, user_id)?;
+    Ok(database.leave_balances(user_id)?)
+}
+
+#[tauri::command]
+fn request_leave(
+    database: tauri::State<Database>,
+    token: String,
+    request: LeaveRequest
+) -> Result<Leave, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::PunchSelf)?;
+    Ok(database.request_leave(caller.id, request)?)
+}
+
+// Without `user_id`, the leave the caller may decide on, as for
+// corrections
+#[tauri::command]
+fn list_leave_requests(
+    database: tauri::State<Database>,
+    token: String,
+    user_id: Option<i32>,
+    status: Option<LeaveStatus>
+) -> Result<Vec<Leave>, CommandError> {
+    let caller = authorize(&database, &token)?;
+    let supervisor_id = match user_id {
+        Some(user_id) => {
+            require_attendance_access(&database, &caller, user_id)?;
+            None
+        }
+        None if caller.role.grants(Permission::ViewAllAttendance) => None,
+        None => {
+            require(&caller, Permission::ViewTeamAttendance)?;
+            Some(caller.id)
+        }
+    };
+    Ok(database.list_leaves(user_id, supervisor_id, status)?)
+}
+
+// Supervisors decide their reports' leave, never their own
+#[tauri::command]
+fn decide_leave(
+    database: tauri::State<Database>,
+    token: String,
+    leave_id: i32,
+    approve: bool,
+    note: Option<String>
+) -> Result<Leave, CommandError> {
+    let caller = authorize(&database, &token)?;
+    require(&caller, Permission::ViewTeamAttendance)?;
+    
+    let leave = database.get_leave(leave_id)?;
+    if leave.user_id == caller.id {
+        return Err(AuthError::Forbidden.into());
+    }
+    require_attendance_access(&database, &caller, leave.user_id)?;
+    
+    Ok(database.decide_leave(leave_id, caller.id, approve, note)?)
+}
+
+#[tauri::command]
+fn get_attendance_events(
+    database: tauri::State<Database>,
+    token: String,
+    user_id: i32,
+    from: Option<NaiveDateTime>,
+    to: Option<NaiveDateTime>
+) -> Result<Vec<AttendanceEvent>, String> {
+    let caller = authorize(&database, &token)?;
+    require_attendance_access(&database, &caller, user_id)?;
     database
-        .login_user(request)
+        .get_attendance_events(user_id, from, to)
         .map_err(|e| e.to_string())
 }
 
+// Retries connecting and migrating until the database is available, then
+// replays punches that were queued while it was offline
+fn spawn_database_worker(app_handle: tauri::AppHandle) {
+    thread::spawn(move || {
+        let mut last_refresh: Option<Instant> = None;
+        loop {
+            thread::sleep(PUNCH_SYNC_INTERVAL);
+            
+            let database = app_handle.state::<Database>();
+            if !database.is_initialized() {
+                if let Err(e) = database.init() {
+                    eprintln!("Database still unavailable: {}", e);
+                    continue;
+                }
+                println!("✓ Database is now available");
+                let _ = app_handle.emit("database-available", database.status());
+            }
+            
+            match database.sync_pending_punches() {
+                Ok(report) if report.synced > 0 || report.rejected > 0 => {
+                    println!("✓ Synced {} queued punches ({} rejected)", report.synced, report.rejected);
+                    let _ = app_handle.emit("punches-synced", report);
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Punch sync failed: {}", e),
+            }
+            
+            if last_refresh.is_none_or(|at| at.elapsed() >= EXCEPTION_REFRESH_INTERVAL) {
+                last_refresh = Some(Instant::now());
+                if let Err(e) = database.refresh_recent_attendance_exceptions() {
+                    eprintln!("Attendance exception refresh failed: {}", e);
+                }
+                if let Err(e) = database.refresh_offline_cache() {
+                    eprintln!("Offline cache refresh failed: {}", e);
+                }
+            }
+        }
+    });
+}
+
 pub fn run() {
-    let database = Database::new().expect("Failed to create database");
-    database.init().expect("Failed to initialize database");
+    let database = Database::new();
+    if let Err(e) = database.init() {
+        // Open the window anyway; `database_status` reports the reason and
+        // the database worker keeps retrying
+        eprintln!("Failed to initialize database: {}", e);
+    }
+    let needs_database_server = database.status().backend.is_some() && !database.is_embedded();
 
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .manage(database)  // Make database state available
+        .manage(Mutex::new(ScanDetector::default()))
         .invoke_handler(tauri::generate_handler![
             check_system_requirements,
+            database_status,
             register_user,
             login_user,
+            logout_user,
+            change_password,
+            reset_password,
+            unlock_account,
+            unlock_device,
             get_users,
-            create_user
+            list_users,
+            create_user,
+            get_user,
+            update_user,
+            deactivate_user,
+            delete_user,
+            clock_in,
+            clock_out,
+            start_break,
+            end_break,
+            set_kiosk_credentials,
+            punch_by_badge,
+            get_qr_code,
+            punch_by_qr,
+            scanner_key,
+            get_attendance_events,
+            create_shift,
+            list_shifts,
+            update_shift,
+            delete_shift,
+            create_schedule_assignment,
+            list_schedule_assignments,
+            update_schedule_assignment,
+            delete_schedule_assignment,
+            get_expected_on_site,
+            get_attendance_status,
+            list_attendance_exceptions,
+            get_timesheet,
+            list_pay_periods,
+            lock_period,
+            unlock_period,
+            submit_correction,
+            list_pending_corrections,
+            decide_correction,
+            accrue_leave,
+            get_leave_balances,
+            request_leave,
+            list_leave_requests,
+            decide_leave,
+            review_attendance_exception
         ])
-        .setup(|app| {
+        .setup(move |app| {
+            spawn_database_worker(app.handle().clone());
+            
+            // Docker and the MySQL container are only needed for a server backend
+            if !needs_database_server {
+                return Ok(());
+            }
+            
             let app_handle = app.handle().clone();
             
             tauri::async_runtime::spawn(async move {
@@ -0,0 +1,162 @@
+// src/lockout.rs
+
+use std::env;
+use chrono::{Duration, NaiveDateTime};
+
+// Failed logins allowed for one username before it is locked
+pub const USERNAME_MAX_FAILURES: u32 = 5;
+// A kiosk tries many usernames legitimately, so it gets more slack
+pub const DEVICE_MAX_FAILURES: u32 = 20;
+// Counters start over once no failure has been seen for this long
+pub const FAILURE_WINDOW_MINUTES: i64 = 15;
+
+const BASE_LOCKOUT_SECS: i64 = 30;
+const MAX_LOCKOUT_SECS: i64 = 60 * 60;
+
+// What a failure counter is keyed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThrottleScope {
+    Username,
+    Device,
+}
+
+impl ThrottleScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ThrottleScope::Username => "username",
+            ThrottleScope::Device => "device",
+        }
+    }
+
+    pub fn max_failures(&self) -> u32 {
+        match self {
+            ThrottleScope::Username => USERNAME_MAX_FAILURES,
+            ThrottleScope::Device => DEVICE_MAX_FAILURES,
+        }
+    }
+}
+
+// Lock length after `failures` consecutive failures: nothing below the
+// threshold, then 30s doubling with every further failure up to an hour
+pub fn lockout_duration(failures: u32, max_failures: u32) -> Option<Duration> {
+    if failures < max_failures {
+        return None;
+    }
+    
+    let doublings = (failures - max_failures).min(16);
+    let secs = BASE_LOCKOUT_SECS.saturating_mul(1 << doublings).min(MAX_LOCKOUT_SECS);
+    
+    Some(Duration::seconds(secs))
+}
+
+// Failures older than this no longer count towards a lockout
+pub fn window_start(now: NaiveDateTime) -> NaiveDateTime {
+    now - Duration::minutes(FAILURE_WINDOW_MINUTES)
+}
+
+// Usernames are matched case-insensitively by MySQL, so counters are too
+pub fn username_subject(username: &str) -> String {
+    username.trim().to_lowercase()
+}
+
+// Identifies this terminal in the shared throttle table. Set DEVICE_ID per
+// kiosk; otherwise the machine name is used.
+pub fn device_id() -> String {
+    env::var("DEVICE_ID")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .or_else(|_| env::var("HOSTNAME"))
+        .unwrap_or_else(|_| "unknown-device".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    use crate::storage::{self, Storage};
+
+    fn at(minutes: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 4).unwrap().and_hms_opt(9, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    fn storage() -> Box<dyn Storage> {
+        let storage = storage::connect("sqlite::memory:").unwrap();
+        storage.migrate().unwrap();
+        storage
+    }
+
+    fn fail(storage: &dyn Storage, minutes: i64) -> u32 {
+        let now = at(minutes);
+        storage.record_login_failure(ThrottleScope::Username, "alice", now, window_start(now)).unwrap()
+    }
+
+    #[test]
+    fn no_lockout_below_the_threshold() {
+        for failures in 0..USERNAME_MAX_FAILURES {
+            assert_eq!(lockout_duration(failures, USERNAME_MAX_FAILURES), None);
+        }
+    }
+
+    #[test]
+    fn lockout_starts_at_thirty_seconds_and_doubles() {
+        let secs = |failures| lockout_duration(failures, USERNAME_MAX_FAILURES).unwrap().num_seconds();
+        
+        assert_eq!(secs(USERNAME_MAX_FAILURES), 30);
+        assert_eq!(secs(USERNAME_MAX_FAILURES + 1), 60);
+        assert_eq!(secs(USERNAME_MAX_FAILURES + 2), 120);
+        assert_eq!(secs(USERNAME_MAX_FAILURES + 6), 1920);
+    }
+
+    #[test]
+    fn lockout_is_capped_at_an_hour() {
+        let hour = Some(Duration::hours(1));
+        
+        assert_eq!(lockout_duration(USERNAME_MAX_FAILURES + 7, USERNAME_MAX_FAILURES), hour);
+        assert_eq!(lockout_duration(USERNAME_MAX_FAILURES + 40, USERNAME_MAX_FAILURES), hour);
+        assert_eq!(lockout_duration(u32::MAX, USERNAME_MAX_FAILURES), hour);
+    }
+
+    #[test]
+    fn devices_get_more_attempts_than_usernames() {
+        let username = ThrottleScope::Username.max_failures();
+        let device = ThrottleScope::Device.max_failures();
+        
+        assert_eq!(username, 5);
+        assert_eq!(device, 20);
+        assert!(lockout_duration(username, username).is_some());
+        assert!(lockout_duration(username, device).is_none());
+        assert_eq!(lockout_duration(device, device), Some(Duration::seconds(30)));
+    }
+
+    #[test]
+    fn failures_inside_the_window_add_up() {
+        let storage = storage();
+        
+        assert_eq!(fail(storage.as_ref(), 0), 1);
+        assert_eq!(fail(storage.as_ref(), 10), 2);
+        assert_eq!(fail(storage.as_ref(), 20), 3);
+    }
+
+    #[test]
+    fn failures_start_over_once_the_window_has_passed() {
+        let storage = storage();
+        
+        fail(storage.as_ref(), 0);
+        fail(storage.as_ref(), 1);
+        
+        assert_eq!(fail(storage.as_ref(), 1 + FAILURE_WINDOW_MINUTES + 1), 1);
+    }
+
+    #[test]
+    fn a_recent_lock_keeps_the_count_past_the_window() {
+        let storage = storage();
+        
+        for minute in 0..USERNAME_MAX_FAILURES {
+            fail(storage.as_ref(), minute.into());
+        }
+        storage.lock_login(ThrottleScope::Username, "alice", at(60)).unwrap();
+        
+        // The next failure after the lock must lock for longer, not start over
+        assert_eq!(fail(storage.as_ref(), 61), USERNAME_MAX_FAILURES + 1);
+    }
+}
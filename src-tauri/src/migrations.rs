@@ -0,0 +1,701 @@
+// src/migrations.rs
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+use crate::storage::StorageError;
+
+pub struct Migration {
+    pub version: u32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        hex::encode(Sha256::digest(self.sql.as_bytes()))
+    }
+
+    pub fn failed(&self, source: impl Into<StorageError>) -> MigrationError {
+        MigrationError::Failed {
+            version: self.version,
+            name: self.name.to_string(),
+            source: source.into(),
+        }
+    }
+}
+
+// A row of the schema_migrations table
+pub struct AppliedMigration {
+    pub version: u32,
+    pub name: String,
+    pub checksum: String,
+}
+
+#[derive(Error, Debug)]
+pub enum MigrationError {
+    #[error("Database error: {0}")]
+    Database(#[from] StorageError),
+    #[error("Database schema is at version {database} but this build only supports up to version {binary}; please upgrade the application")]
+    DatabaseAhead { database: u32, binary: u32 },
+    #[error("Migration {version} ({name}) was changed after it was applied to this database")]
+    ChecksumMismatch { version: u32, name: String },
+    #[error("Database has applied migration {0} which is unknown to this build")]
+    UnknownMigration(u32),
+    #[error("Migration {version} ({name}) failed: {source}")]
+    Failed { version: u32, name: String, source: StorageError },
+    #[error("Timed out waiting for another instance to finish migrating the database")]
+    LockTimeout,
+}
+
+// Applied migrations must never be edited; add a new migration instead.
+// Each backend keeps its own list, but versions must stay in step so a
+// version number means the same schema everywhere. The first migrations
+// use IF NOT EXISTS so databases created before versioning existed are
+// adopted without changes.
+//
+// SQLite runs all pending migrations in one transaction, so a failure
+// leaves the database untouched. MySQL commits DDL implicitly and cannot
+// roll a migration back, so from version 17 on each MySQL migration must be
+// a single statement: a failed migration then either did nothing or
+// completed without being recorded. To recover, check whether the schema
+// already matches the migration named in the error. If it does, insert its
+// version, name and checksum (the SHA-256 of its SQL) into
+// schema_migrations; otherwise fix whatever made it fail and restart the
+// application to run it again.
+pub const MYSQL: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_users",
+        sql: r"CREATE TABLE IF NOT EXISTS users (
+            id INT PRIMARY KEY AUTO_INCREMENT,
+            username VARCHAR(255) UNIQUE NOT NULL,
+            password_hash VARCHAR(255) NOT NULL,
+            email VARCHAR(255),
+            full_name VARCHAR(255),
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+    },
+    Migration {
+        version: 2,
+        name: "create_attendance_events",
+        sql: r"CREATE TABLE IF NOT EXISTS attendance_events (
+            id BIGINT UNSIGNED PRIMARY KEY AUTO_INCREMENT,
+            user_id INT NOT NULL,
+            event_type ENUM('IN', 'OUT') NOT NULL,
+            event_time DATETIME NOT NULL,
+            INDEX idx_attendance_user_time (user_id, event_time),
+            FOREIGN KEY (user_id) REFERENCES users(id)
+        )",
+    },
+    Migration {
+        version: 3,
+        name: "create_sessions",
+        sql: r"CREATE TABLE sessions (
+            id BIGINT UNSIGNED PRIMARY KEY AUTO_INCREMENT,
+            token_hash CHAR(64) NOT NULL UNIQUE,
+            user_id INT NOT NULL,
+            created_at DATETIME NOT NULL,
+            expires_at DATETIME NOT NULL,
+            revoked_at DATETIME NULL,
+            INDEX idx_sessions_user (user_id),
+            FOREIGN KEY (user_id) REFERENCES users(id)
+        )",
+    },
+    Migration {
+        version: 4,
+        name: "add_user_roles",
+        // The oldest account is promoted so existing installs keep an administrator
+        sql: r"ALTER TABLE users
+            ADD COLUMN role ENUM('admin', 'supervisor', 'employee') NOT NULL DEFAULT 'employee',
+            ADD COLUMN supervisor_id INT NULL,
+            ADD CONSTRAINT fk_users_supervisor FOREIGN KEY (supervisor_id) REFERENCES users(id);
+        UPDATE users SET role = 'admin' ORDER BY id LIMIT 1",
+    },
+    Migration {
+        version: 5,
+        name: "add_attendance_idempotency_keys",
+        sql: r"ALTER TABLE attendance_events
+            ADD COLUMN idempotency_key CHAR(32) NULL,
+            ADD UNIQUE INDEX uq_attendance_idempotency (idempotency_key)",
+    },
+    Migration {
+        version: 6,
+        name: "create_login_throttles",
+        sql: r"CREATE TABLE login_throttles (
+            scope ENUM('username', 'device') NOT NULL,
+            subject VARCHAR(255) NOT NULL,
+            failures INT UNSIGNED NOT NULL DEFAULT 0,
+            last_failure_at DATETIME NOT NULL,
+            locked_until DATETIME NULL,
+            PRIMARY KEY (scope, subject)
+        )",
+    },
+    Migration {
+        version: 7,
+        name: "add_user_status",
+        sql: r"ALTER TABLE users
+            ADD COLUMN status ENUM('active', 'inactive') NOT NULL DEFAULT 'active'",
+    },
+    Migration {
+        version: 8,
+        name: "add_must_change_password",
+        sql: r"ALTER TABLE users
+            ADD COLUMN must_change_password BOOLEAN NOT NULL DEFAULT FALSE",
+    },
+    Migration {
+        version: 9,
+        name: "add_kiosk_credentials",
+        sql: r"ALTER TABLE users
+            ADD COLUMN badge_hash CHAR(64) NULL,
+            ADD COLUMN pin_hash VARCHAR(255) NULL,
+            ADD UNIQUE INDEX uq_users_badge (badge_hash)",
+    },
+    Migration {
+        version: 10,
+        name: "create_qr_redemptions",
+        sql: r"CREATE TABLE qr_redemptions (
+            user_id INT NOT NULL,
+            time_window BIGINT NOT NULL,
+            redeemed_at DATETIME NOT NULL,
+            PRIMARY KEY (user_id, time_window),
+            FOREIGN KEY (user_id) REFERENCES users(id)
+        )",
+    },
+    Migration {
+        version: 11,
+        name: "create_shift_schedules",
+        sql: r"CREATE TABLE shifts (
+            id INT PRIMARY KEY AUTO_INCREMENT,
+            name VARCHAR(100) NOT NULL UNIQUE,
+            start_time TIME NOT NULL,
+            end_time TIME NOT NULL,
+            split_start_time TIME NULL,
+            split_end_time TIME NULL
+        );
+        CREATE TABLE schedule_assignments (
+            id INT PRIMARY KEY AUTO_INCREMENT,
+            user_id INT NOT NULL,
+            shift_id INT NOT NULL,
+            pattern ENUM('weekly', 'rotating') NOT NULL,
+            weekdays TINYINT UNSIGNED NULL,
+            days_on SMALLINT UNSIGNED NULL,
+            days_off SMALLINT UNSIGNED NULL,
+            starts_on DATE NOT NULL,
+            ends_on DATE NULL,
+            INDEX idx_schedule_user (user_id),
+            FOREIGN KEY (user_id) REFERENCES users(id),
+            FOREIGN KEY (shift_id) REFERENCES shifts(id)
+        )",
+    },
+    Migration {
+        version: 12,
+        name: "create_attendance_exceptions",
+        sql: r"CREATE TABLE attendance_exceptions (
+            id INT PRIMARY KEY AUTO_INCREMENT,
+            user_id INT NOT NULL,
+            work_date DATE NOT NULL,
+            kind ENUM('late', 'early_leave', 'absent', 'unscheduled') NOT NULL,
+            minutes INT NULL,
+            detected_at DATETIME NOT NULL,
+            reviewed_by INT NULL,
+            reviewed_at DATETIME NULL,
+            review_note VARCHAR(255) NULL,
+            UNIQUE INDEX uq_attendance_exception (user_id, work_date, kind),
+            INDEX idx_attendance_exception_date (work_date),
+            FOREIGN KEY (user_id) REFERENCES users(id),
+            FOREIGN KEY (reviewed_by) REFERENCES users(id)
+        )",
+    },
+    Migration {
+        version: 13,
+        name: "add_break_events",
+        sql: r"ALTER TABLE attendance_events
+            MODIFY event_type ENUM('IN', 'OUT', 'BREAK_START', 'BREAK_END') NOT NULL",
+    },
+    Migration {
+        version: 14,
+        name: "create_pay_period_locks",
+        sql: r"CREATE TABLE pay_period_locks (
+            id INT PRIMARY KEY AUTO_INCREMENT,
+            starts_on DATE NOT NULL,
+            ends_on DATE NOT NULL,
+            locked_by INT NULL,
+            locked_at DATETIME NOT NULL,
+            unlocked_by INT NULL,
+            unlocked_at DATETIME NULL,
+            unlock_reason VARCHAR(255) NULL,
+            INDEX idx_pay_period_locks_range (starts_on, ends_on),
+            FOREIGN KEY (locked_by) REFERENCES users(id),
+            FOREIGN KEY (unlocked_by) REFERENCES users(id)
+        )",
+    },
+    Migration {
+        version: 15,
+        name: "create_attendance_corrections",
+        sql: r"CREATE TABLE attendance_corrections (
+            id INT PRIMARY KEY AUTO_INCREMENT,
+            user_id INT NOT NULL,
+            event_id BIGINT UNSIGNED NULL,
+            event_type ENUM('IN', 'OUT', 'BREAK_START', 'BREAK_END') NOT NULL,
+            event_time DATETIME NOT NULL,
+            reason VARCHAR(255) NOT NULL,
+            status ENUM('pending', 'approved', 'rejected') NOT NULL DEFAULT 'pending',
+            requested_at DATETIME NOT NULL,
+            decided_by INT NULL,
+            decided_at DATETIME NULL,
+            decision_note VARCHAR(255) NULL,
+            adjusted_event_id BIGINT UNSIGNED NULL,
+            INDEX idx_corrections_status (status, requested_at),
+            FOREIGN KEY (user_id) REFERENCES users(id),
+            FOREIGN KEY (event_id) REFERENCES attendance_events(id),
+            FOREIGN KEY (decided_by) REFERENCES users(id),
+            FOREIGN KEY (adjusted_event_id) REFERENCES attendance_events(id)
+        );
+        ALTER TABLE attendance_events
+            ADD COLUMN corrects_event_id BIGINT UNSIGNED NULL,
+            ADD COLUMN correction_id INT NULL,
+            ADD UNIQUE INDEX uq_attendance_corrects (corrects_event_id),
+            ADD CONSTRAINT fk_attendance_corrects FOREIGN KEY (corrects_event_id) REFERENCES attendance_events(id),
+            ADD CONSTRAINT fk_attendance_correction FOREIGN KEY (correction_id) REFERENCES attendance_corrections(id)",
+    },
+    Migration {
+        version: 16,
+        name: "create_leave_tables",
+        sql: r"CREATE TABLE leave_accruals (
+            id INT PRIMARY KEY AUTO_INCREMENT,
+            user_id INT NOT NULL,
+            leave_type ENUM('vacation', 'sick') NOT NULL,
+            days DOUBLE NOT NULL,
+            note VARCHAR(255) NULL,
+            created_by INT NULL,
+            created_at DATETIME NOT NULL,
+            INDEX idx_leave_accruals_user (user_id, created_at),
+            FOREIGN KEY (user_id) REFERENCES users(id),
+            FOREIGN KEY (created_by) REFERENCES users(id)
+        );
+        CREATE TABLE leave_requests (
+            id INT PRIMARY KEY AUTO_INCREMENT,
+            user_id INT NOT NULL,
+            leave_type ENUM('vacation', 'sick', 'unpaid') NOT NULL,
+            starts_on DATE NOT NULL,
+            ends_on DATE NOT NULL,
+            days DOUBLE NOT NULL,
+            reason VARCHAR(255) NULL,
+            status ENUM('pending', 'approved', 'rejected') NOT NULL DEFAULT 'pending',
+            requested_at DATETIME NOT NULL,
+            decided_by INT NULL,
+            decided_at DATETIME NULL,
+            decision_note VARCHAR(255) NULL,
+            INDEX idx_leave_requests_user_range (user_id, starts_on, ends_on),
+            INDEX idx_leave_requests_status (status, requested_at),
+            FOREIGN KEY (user_id) REFERENCES users(id),
+            FOREIGN KEY (decided_by) REFERENCES users(id)
+        )",
+    },
+    // Builds that predate this migration cleared locked_by when the locking
+    // admin was deleted; such rows make it fail until an admin is recorded
+    // against them by hand
+    Migration {
+        version: 17,
+        name: "require_pay_period_lock_owner",
+        sql: r"ALTER TABLE pay_period_locks MODIFY locked_by INT NOT NULL",
+    },
+    // Same for accruals whose author was deleted under the old builds
+    Migration {
+        version: 18,
+        name: "require_leave_accrual_author",
+        sql: r"ALTER TABLE leave_accruals MODIFY created_by INT NOT NULL",
+    },
+];
+
+pub const SQLITE: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_users",
+        sql: r"CREATE TABLE IF NOT EXISTS users (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT UNIQUE NOT NULL,
+            password_hash TEXT NOT NULL,
+            email TEXT,
+            full_name TEXT,
+            created_at TEXT DEFAULT CURRENT_TIMESTAMP
+        )",
+    },
+    Migration {
+        version: 2,
+        name: "create_attendance_events",
+        sql: r"CREATE TABLE IF NOT EXISTS attendance_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id),
+            event_type TEXT NOT NULL CHECK (event_type IN ('IN', 'OUT')),
+            event_time TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_attendance_user_time ON attendance_events (user_id, event_time)",
+    },
+    Migration {
+        version: 3,
+        name: "create_sessions",
+        sql: r"CREATE TABLE sessions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            token_hash TEXT NOT NULL UNIQUE,
+            user_id INTEGER NOT NULL REFERENCES users(id),
+            created_at TEXT NOT NULL,
+            expires_at TEXT NOT NULL,
+            revoked_at TEXT NULL
+        );
+        CREATE INDEX idx_sessions_user ON sessions (user_id)",
+    },
+    Migration {
+        version: 4,
+        name: "add_user_roles",
+        sql: r"ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'employee'
+            CHECK (role IN ('admin', 'supervisor', 'employee'));
+        ALTER TABLE users ADD COLUMN supervisor_id INTEGER NULL REFERENCES users(id);
+        UPDATE users SET role = 'admin' WHERE id = (SELECT MIN(id) FROM users)",
+    },
+    Migration {
+        version: 5,
+        name: "add_attendance_idempotency_keys",
+        sql: r"ALTER TABLE attendance_events ADD COLUMN idempotency_key TEXT NULL;
+        CREATE UNIQUE INDEX uq_attendance_idempotency ON attendance_events (idempotency_key)",
+    },
+    Migration {
+        version: 6,
+        name: "create_login_throttles",
+        sql: r"CREATE TABLE login_throttles (
+            scope TEXT NOT NULL CHECK (scope IN ('username', 'device')),
+            subject TEXT NOT NULL,
+            failures INTEGER NOT NULL DEFAULT 0,
+            last_failure_at TEXT NOT NULL,
+            locked_until TEXT NULL,
+            PRIMARY KEY (scope, subject)
+        )",
+    },
+    Migration {
+        version: 7,
+        name: "add_user_status",
+        sql: r"ALTER TABLE users ADD COLUMN status TEXT NOT NULL DEFAULT 'active'
+            CHECK (status IN ('active', 'inactive'))",
+    },
+    Migration {
+        version: 8,
+        name: "add_must_change_password",
+        sql: r"ALTER TABLE users ADD COLUMN must_change_password INTEGER NOT NULL DEFAULT 0",
+    },
+    Migration {
+        version: 9,
+        name: "add_kiosk_credentials",
+        sql: r"ALTER TABLE users ADD COLUMN badge_hash TEXT NULL;
+        ALTER TABLE users ADD COLUMN pin_hash TEXT NULL;
+        CREATE UNIQUE INDEX uq_users_badge ON users (badge_hash)",
+    },
+    Migration {
+        version: 10,
+        name: "create_qr_redemptions",
+        sql: r"CREATE TABLE qr_redemptions (
+            user_id INTEGER NOT NULL REFERENCES users(id),
+            time_window INTEGER NOT NULL,
+            redeemed_at TEXT NOT NULL,
+            PRIMARY KEY (user_id, time_window)
+        )",
+    },
+    Migration {
+        version: 11,
+        name: "create_shift_schedules",
+        sql: r"CREATE TABLE shifts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            name TEXT NOT NULL UNIQUE,
+            start_time TEXT NOT NULL,
+            end_time TEXT NOT NULL,
+            split_start_time TEXT NULL,
+            split_end_time TEXT NULL
+        );
+        CREATE TABLE schedule_assignments (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id),
+            shift_id INTEGER NOT NULL REFERENCES shifts(id),
+            pattern TEXT NOT NULL CHECK (pattern IN ('weekly', 'rotating')),
+            weekdays INTEGER NULL,
+            days_on INTEGER NULL,
+            days_off INTEGER NULL,
+            starts_on TEXT NOT NULL,
+            ends_on TEXT NULL
+        );
+        CREATE INDEX idx_schedule_user ON schedule_assignments (user_id)",
+    },
+    Migration {
+        version: 12,
+        name: "create_attendance_exceptions",
+        sql: r"CREATE TABLE attendance_exceptions (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id),
+            work_date TEXT NOT NULL,
+            kind TEXT NOT NULL CHECK (kind IN ('late', 'early_leave', 'absent', 'unscheduled')),
+            minutes INTEGER NULL,
+            detected_at TEXT NOT NULL,
+            reviewed_by INTEGER NULL REFERENCES users(id),
+            reviewed_at TEXT NULL,
+            review_note TEXT NULL,
+            UNIQUE (user_id, work_date, kind)
+        );
+        CREATE INDEX idx_attendance_exception_date ON attendance_exceptions (work_date)",
+    },
+    Migration {
+        version: 13,
+        name: "add_break_events",
+        // SQLite cannot alter a CHECK constraint, so the table is rebuilt
+        sql: r"CREATE TABLE attendance_events_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id),
+            event_type TEXT NOT NULL CHECK (event_type IN ('IN', 'OUT', 'BREAK_START', 'BREAK_END')),
+            event_time TEXT NOT NULL,
+            idempotency_key TEXT NULL
+        );
+        INSERT INTO attendance_events_new (id, user_id, event_type, event_time, idempotency_key)
+            SELECT id, user_id, event_type, event_time, idempotency_key FROM attendance_events;
+        DROP TABLE attendance_events;
+        ALTER TABLE attendance_events_new RENAME TO attendance_events;
+        CREATE INDEX idx_attendance_user_time ON attendance_events (user_id, event_time);
+        CREATE UNIQUE INDEX uq_attendance_idempotency ON attendance_events (idempotency_key)",
+    },
+    Migration {
+        version: 14,
+        name: "create_pay_period_locks",
+        sql: r"CREATE TABLE pay_period_locks (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            starts_on TEXT NOT NULL,
+            ends_on TEXT NOT NULL,
+            locked_by INTEGER NULL REFERENCES users(id),
+            locked_at TEXT NOT NULL,
+            unlocked_by INTEGER NULL REFERENCES users(id),
+            unlocked_at TEXT NULL,
+            unlock_reason TEXT NULL
+        );
+        CREATE INDEX idx_pay_period_locks_range ON pay_period_locks (starts_on, ends_on)",
+    },
+    Migration {
+        version: 15,
+        name: "create_attendance_corrections",
+        sql: r"CREATE TABLE attendance_corrections (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id),
+            event_id INTEGER NULL REFERENCES attendance_events(id),
+            event_type TEXT NOT NULL CHECK (event_type IN ('IN', 'OUT', 'BREAK_START', 'BREAK_END')),
+            event_time TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected')),
+            requested_at TEXT NOT NULL,
+            decided_by INTEGER NULL REFERENCES users(id),
+            decided_at TEXT NULL,
+            decision_note TEXT NULL,
+            adjusted_event_id INTEGER NULL REFERENCES attendance_events(id)
+        );
+        CREATE INDEX idx_corrections_status ON attendance_corrections (status, requested_at);
+        ALTER TABLE attendance_events ADD COLUMN corrects_event_id INTEGER NULL REFERENCES attendance_events(id);
+        ALTER TABLE attendance_events ADD COLUMN correction_id INTEGER NULL REFERENCES attendance_corrections(id);
+        CREATE UNIQUE INDEX uq_attendance_corrects ON attendance_events (corrects_event_id)",
+    },
+    Migration {
+        version: 16,
+        name: "create_leave_tables",
+        sql: r"CREATE TABLE leave_accruals (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id),
+            leave_type TEXT NOT NULL CHECK (leave_type IN ('vacation', 'sick')),
+            days REAL NOT NULL,
+            note TEXT NULL,
+            created_by INTEGER NULL REFERENCES users(id),
+            created_at TEXT NOT NULL
+        );
+        CREATE INDEX idx_leave_accruals_user ON leave_accruals (user_id, created_at);
+        CREATE TABLE leave_requests (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id),
+            leave_type TEXT NOT NULL CHECK (leave_type IN ('vacation', 'sick', 'unpaid')),
+            starts_on TEXT NOT NULL,
+            ends_on TEXT NOT NULL,
+            days REAL NOT NULL,
+            reason TEXT NULL,
+            status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'approved', 'rejected')),
+            requested_at TEXT NOT NULL,
+            decided_by INTEGER NULL REFERENCES users(id),
+            decided_at TEXT NULL,
+            decision_note TEXT NULL
+        );
+        CREATE INDEX idx_leave_requests_user_range ON leave_requests (user_id, starts_on, ends_on);
+        CREATE INDEX idx_leave_requests_status ON leave_requests (status, requested_at)",
+    },
+    Migration {
+        version: 17,
+        name: "require_pay_period_lock_owner",
+        sql: r"CREATE TABLE pay_period_locks_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            starts_on TEXT NOT NULL,
+            ends_on TEXT NOT NULL,
+            locked_by INTEGER NOT NULL REFERENCES users(id),
+            locked_at TEXT NOT NULL,
+            unlocked_by INTEGER NULL REFERENCES users(id),
+            unlocked_at TEXT NULL,
+            unlock_reason TEXT NULL
+        );
+        INSERT INTO pay_period_locks_new (id, starts_on, ends_on, locked_by, locked_at, unlocked_by, unlocked_at, unlock_reason)
+            SELECT id, starts_on, ends_on, locked_by, locked_at, unlocked_by, unlocked_at, unlock_reason FROM pay_period_locks;
+        DROP TABLE pay_period_locks;
+        ALTER TABLE pay_period_locks_new RENAME TO pay_period_locks;
+        CREATE INDEX idx_pay_period_locks_range ON pay_period_locks (starts_on, ends_on)",
+    },
+    Migration {
+        version: 18,
+        name: "require_leave_accrual_author",
+        sql: r"CREATE TABLE leave_accruals_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL REFERENCES users(id),
+            leave_type TEXT NOT NULL CHECK (leave_type IN ('vacation', 'sick')),
+            days REAL NOT NULL,
+            note TEXT NULL,
+            created_by INTEGER NOT NULL REFERENCES users(id),
+            created_at TEXT NOT NULL
+        );
+        INSERT INTO leave_accruals_new (id, user_id, leave_type, days, note, created_by, created_at)
+            SELECT id, user_id, leave_type, days, note, created_by, created_at FROM leave_accruals;
+        DROP TABLE leave_accruals;
+        ALTER TABLE leave_accruals_new RENAME TO leave_accruals;
+        CREATE INDEX idx_leave_accruals_user ON leave_accruals (user_id, created_at)",
+    },
+];
+
+// Validates what the database has applied against this build and returns
+// the migrations that still need to run, in order
+pub fn pending<'a>(
+    applied: &[AppliedMigration],
+    available: &'a [Migration],
+) -> Result<Vec<&'a Migration>, MigrationError> {
+    let latest = available.last().map_or(0, |m| m.version);
+
+    if let Some(newest) = applied.iter().filter(|a| a.version > latest).max_by_key(|a| a.version) {
+        return Err(MigrationError::DatabaseAhead {
+            database: newest.version,
+            binary: latest,
+        });
+    }
+
+    for entry in applied {
+        let migration = available
+            .iter()
+            .find(|m| m.version == entry.version)
+            .ok_or(MigrationError::UnknownMigration(entry.version))?;
+
+        if migration.checksum() != entry.checksum {
+            return Err(MigrationError::ChecksumMismatch {
+                version: entry.version,
+                name: entry.name.clone(),
+            });
+        }
+    }
+
+    Ok(available
+        .iter()
+        .filter(|m| !applied.iter().any(|a| a.version == m.version))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AVAILABLE: &[Migration] = &[
+        Migration { version: 1, name: "first", sql: "CREATE TABLE a (id INT)" },
+        Migration { version: 2, name: "second", sql: "CREATE TABLE b (id INT)" },
+        Migration { version: 4, name: "fourth", sql: "CREATE TABLE d (id INT)" },
+    ];
+
+    fn applied(migration: &Migration) -> AppliedMigration {
+        AppliedMigration {
+            version: migration.version,
+            name: migration.name.to_string(),
+            checksum: migration.checksum(),
+        }
+    }
+
+    fn versions(pending: &[&Migration]) -> Vec<u32> {
+        pending.iter().map(|m| m.version).collect()
+    }
+
+    #[test]
+    fn fresh_databases_get_every_migration_in_order() {
+        assert_eq!(versions(&pending(&[], AVAILABLE).unwrap()), vec![1, 2, 4]);
+    }
+
+    #[test]
+    fn only_unapplied_migrations_are_pending() {
+        let done = [applied(&AVAILABLE[0])];
+        assert_eq!(versions(&pending(&done, AVAILABLE).unwrap()), vec![2, 4]);
+
+        // The applied rows may come back in any order
+        let done = [applied(&AVAILABLE[2]), applied(&AVAILABLE[0])];
+        assert_eq!(versions(&pending(&done, AVAILABLE).unwrap()), vec![2]);
+
+        let done: Vec<_> = AVAILABLE.iter().map(applied).collect();
+        assert!(pending(&done, AVAILABLE).unwrap().is_empty());
+    }
+
+    #[test]
+    fn edited_migrations_are_rejected() {
+        let mut edited = applied(&AVAILABLE[1]);
+        edited.checksum = Migration { sql: "CREATE TABLE b (id BIGINT)", ..AVAILABLE[1] }.checksum();
+
+        let done = [applied(&AVAILABLE[0]), edited];
+        assert!(matches!(
+            pending(&done, AVAILABLE),
+            Err(MigrationError::ChecksumMismatch { version: 2, ref name }) if name == "second"
+        ));
+    }
+
+    #[test]
+    fn databases_ahead_of_the_build_are_rejected() {
+        let newer = [
+            AppliedMigration { version: 5, name: "fifth".to_string(), checksum: String::new() },
+            AppliedMigration { version: 7, name: "seventh".to_string(), checksum: String::new() },
+        ];
+        let mut done: Vec<_> = AVAILABLE.iter().map(applied).collect();
+        done.extend(newer);
+
+        assert!(matches!(
+            pending(&done, AVAILABLE),
+            Err(MigrationError::DatabaseAhead { database: 7, binary: 4 })
+        ));
+    }
+
+    #[test]
+    fn unknown_applied_versions_are_rejected() {
+        let done = [
+            applied(&AVAILABLE[0]),
+            AppliedMigration { version: 3, name: "third".to_string(), checksum: String::new() },
+        ];
+
+        assert!(matches!(pending(&done, AVAILABLE), Err(MigrationError::UnknownMigration(3))));
+    }
+
+    #[test]
+    fn backends_stay_in_step() {
+        assert_eq!(MYSQL.len(), SQLITE.len());
+
+        for (index, (mysql, sqlite)) in MYSQL.iter().zip(SQLITE).enumerate() {
+            assert_eq!(mysql.version as usize, index + 1);
+            assert_eq!(mysql.version, sqlite.version);
+            assert_eq!(mysql.name, sqlite.name);
+        }
+    }
+
+    #[test]
+    fn new_mysql_migrations_are_single_statements() {
+        for migration in MYSQL.iter().filter(|m| m.version >= 17) {
+            assert!(
+                !migration.sql.trim_end().trim_end_matches(';').contains(';'),
+                "MySQL migration {} has more than one statement",
+                migration.version
+            );
+        }
+    }
+}
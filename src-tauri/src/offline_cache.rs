@@ -0,0 +1,401 @@
+// src/offline_cache.rs
+
+use std::collections::HashSet;
+use std::sync::{Mutex, MutexGuard};
+use chrono::NaiveDateTime;
+use rusqlite::{named_params, Connection, OptionalExtension, Row};
+
+use crate::database::{AttendanceEventType, User, UserStatus};
+use crate::permissions::Role;
+use crate::storage::StorageError;
+
+// Bumped whenever the tables below change. Everything here can be fetched
+// again from the server, so an outdated cache is dropped, not migrated.
+const SCHEMA_VERSION: i32 = 2;
+
+const USER_COLUMNS: &str =
+    "u.id, u.username, u.email, u.full_name, u.role, u.status, u.must_change_password, u.created_at";
+// Index of the first column selected after USER_COLUMNS
+const AFTER_USER_COLUMNS: usize = 8;
+
+fn read_user(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        email: row.get(2)?,
+        full_name: row.get(3)?,
+        role: Role::parse(&row.get::<_, String>(4)?).unwrap_or(Role::Employee),
+        status: UserStatus::parse(&row.get::<_, String>(5)?).unwrap_or(UserStatus::Inactive),
+        must_change_password: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+// What a kiosk keeps about an active user so they can punch while the
+// server backend is unreachable
+#[derive(Debug, Clone)]
+pub struct OfflineUser {
+    pub user: User,
+    // Type of the user's latest punch on the server, so a toggle picks the
+    // right direction offline
+    pub last_event_type: Option<AttendanceEventType>,
+    pub badge_hash: Option<String>,
+    pub pin_hash: Option<String>,
+}
+
+// Local copy of the users, badges and sessions a kiosk needs to verify
+// punches while the server backend is unreachable. It lives next to the
+// punch queue and is refreshed from the server whenever that is reachable.
+// Failed offline attempts are throttled here too, per terminal, since the
+// shared throttles are out of reach.
+pub struct OfflineCache {
+    conn: Mutex<Connection>,
+}
+
+impl OfflineCache {
+    pub fn open(path: &str) -> Result<Self, StorageError> {
+        let conn = Connection::open(path)?;
+
+        conn.execute_batch(
+            "PRAGMA journal_mode = WAL;
+            PRAGMA foreign_keys = ON;"
+        )?;
+
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version != SCHEMA_VERSION {
+            conn.execute_batch(
+                "DROP TABLE IF EXISTS cached_sessions;
+                DROP TABLE IF EXISTS cached_users;
+                DROP TABLE IF EXISTS device_throttle;"
+            )?;
+        }
+
+        conn.execute_batch(&format!(
+            r"CREATE TABLE IF NOT EXISTS cached_users (
+                id INTEGER PRIMARY KEY,
+                username TEXT NOT NULL,
+                email TEXT NULL,
+                full_name TEXT NULL,
+                role TEXT NOT NULL,
+                status TEXT NOT NULL,
+                must_change_password INTEGER NOT NULL,
+                created_at TEXT NULL,
+                last_event_type TEXT NULL,
+                badge_hash TEXT NULL UNIQUE,
+                pin_hash TEXT NULL
+            );
+            CREATE TABLE IF NOT EXISTS cached_sessions (
+                token_hash TEXT PRIMARY KEY,
+                user_id INTEGER NOT NULL REFERENCES cached_users(id) ON DELETE CASCADE,
+                expires_at TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS device_throttle (
+                id INTEGER PRIMARY KEY CHECK (id = 1),
+                failures INTEGER NOT NULL,
+                last_failure_at TEXT NOT NULL,
+                locked_until TEXT NULL
+            );
+            PRAGMA user_version = {};",
+            SCHEMA_VERSION
+        ))?;
+
+        Ok(OfflineCache { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Replaces every cached user with `users`. Users missing from the list,
+    // e.g. deactivated ones, are dropped along with their sessions.
+    pub fn replace_users(&self, users: &[OfflineUser]) -> Result<(), StorageError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        let refreshed: HashSet<i32> = users.iter().map(|offline_user| offline_user.user.id).collect();
+        let cached = tx
+            .prepare("SELECT id FROM cached_users")?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<i32>, _>>()?;
+
+        for user_id in cached.into_iter().filter(|user_id| !refreshed.contains(user_id)) {
+            tx.execute(
+                "DELETE FROM cached_users WHERE id = :user_id",
+                named_params! {
+                    ":user_id": user_id,
+                },
+            )?;
+        }
+
+        // Badges may have changed hands since the last refresh
+        tx.execute("UPDATE cached_users SET badge_hash = NULL", [])?;
+
+        for offline_user in users {
+            upsert_user(&tx, &offline_user.user)?;
+            tx.execute(
+                "UPDATE cached_users SET
+                    last_event_type = :last_event_type,
+                    badge_hash = :badge_hash,
+                    pin_hash = :pin_hash
+                WHERE id = :user_id",
+                named_params! {
+                    ":user_id": offline_user.user.id,
+                    ":last_event_type": offline_user.last_event_type.map(|t| t.as_str()),
+                    ":badge_hash": offline_user.badge_hash,
+                    ":pin_hash": offline_user.pin_hash,
+                },
+            )?;
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    // Remembers a session the server just confirmed, until it expires
+    pub fn store_session(&self, token_hash: &str, user: &User, expires_at: NaiveDateTime) -> Result<(), StorageError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        upsert_user(&tx, user)?;
+        tx.execute(
+            "INSERT INTO cached_sessions (token_hash, user_id, expires_at)
+            VALUES (:token_hash, :user_id, :expires_at)
+            ON CONFLICT (token_hash) DO UPDATE SET
+                user_id = excluded.user_id,
+                expires_at = excluded.expires_at",
+            named_params! {
+                ":token_hash": token_hash,
+                ":user_id": user.id,
+                ":expires_at": expires_at,
+            },
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    // The active user behind an unexpired cached session
+    pub fn find_session_user(&self, token_hash: &str, now: NaiveDateTime) -> Result<Option<User>, StorageError> {
+        let user = self.conn()
+            .query_row(
+                &format!(
+                    "SELECT {} FROM cached_sessions s
+                    JOIN cached_users u ON u.id = s.user_id
+                    WHERE s.token_hash = :token_hash
+                    AND s.expires_at > :now
+                    AND u.status = 'active'",
+                    USER_COLUMNS
+                ),
+                named_params! {
+                    ":token_hash": token_hash,
+                    ":now": now,
+                },
+                read_user,
+            )
+            .optional()?;
+
+        Ok(user)
+    }
+
+    pub fn remove_session(&self, token_hash: &str) -> Result<(), StorageError> {
+        self.conn().execute(
+            "DELETE FROM cached_sessions WHERE token_hash = :token_hash",
+            named_params! {
+                ":token_hash": token_hash,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    pub fn forget_sessions(&self, user_id: i32) -> Result<(), StorageError> {
+        self.conn().execute(
+            "DELETE FROM cached_sessions WHERE user_id = :user_id",
+            named_params! {
+                ":user_id": user_id,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    // Drops the user and their sessions; a later refresh restores users who
+    // are still active
+    pub fn forget_user(&self, user_id: i32) -> Result<(), StorageError> {
+        self.conn().execute(
+            "DELETE FROM cached_users WHERE id = :user_id",
+            named_params! {
+                ":user_id": user_id,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    pub fn find_user(&self, user_id: i32) -> Result<Option<User>, StorageError> {
+        let user = self.conn()
+            .query_row(
+                &format!("SELECT {} FROM cached_users u WHERE u.id = :user_id", USER_COLUMNS),
+                named_params! {
+                    ":user_id": user_id,
+                },
+                read_user,
+            )
+            .optional()?;
+
+        Ok(user)
+    }
+
+    // The cached user holding the badge together with their PIN hash, if set
+    pub fn find_kiosk_credentials(&self, badge_hash: &str) -> Result<Option<(User, Option<String>)>, StorageError> {
+        let row = self.conn()
+            .query_row(
+                &format!("SELECT {}, u.pin_hash FROM cached_users u WHERE u.badge_hash = :badge_hash", USER_COLUMNS),
+                named_params! {
+                    ":badge_hash": badge_hash,
+                },
+                |row| Ok((read_user(row)?, row.get(AFTER_USER_COLUMNS)?)),
+            )
+            .optional()?;
+
+        Ok(row)
+    }
+
+    // Mirrors a change made on the server, so it applies offline before the
+    // next refresh
+    pub fn set_kiosk_credentials(
+        &self,
+        user_id: i32,
+        badge_hash: Option<&str>,
+        pin_hash: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "UPDATE cached_users SET badge_hash = NULL WHERE badge_hash = :badge_hash",
+            named_params! {
+                ":badge_hash": badge_hash,
+            },
+        )?;
+        tx.execute(
+            "UPDATE cached_users SET badge_hash = :badge_hash, pin_hash = :pin_hash WHERE id = :user_id",
+            named_params! {
+                ":user_id": user_id,
+                ":badge_hash": badge_hash,
+                ":pin_hash": pin_hash,
+            },
+        )?;
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    // Counts a failed offline attempt on this terminal and returns the new
+    // total, starting over like the server's throttles do
+    pub fn record_device_failure(&self, now: NaiveDateTime, window_start: NaiveDateTime) -> Result<u32, StorageError> {
+        let failures = self.conn().query_row(
+            "INSERT INTO device_throttle (id, failures, last_failure_at)
+            VALUES (1, 1, :now)
+            ON CONFLICT (id) DO UPDATE SET
+                failures = CASE
+                    WHEN last_failure_at < :window_start
+                        AND (locked_until IS NULL OR locked_until < :window_start)
+                    THEN 1
+                    ELSE failures + 1
+                END,
+                last_failure_at = :now
+            RETURNING failures",
+            named_params! {
+                ":now": now,
+                ":window_start": window_start,
+            },
+            |row| row.get(0),
+        )?;
+
+        Ok(failures)
+    }
+
+    pub fn lock_device(&self, until: NaiveDateTime) -> Result<(), StorageError> {
+        self.conn().execute(
+            "UPDATE device_throttle SET locked_until = :until WHERE id = 1",
+            named_params! {
+                ":until": until,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    pub fn device_locked_until(&self) -> Result<Option<NaiveDateTime>, StorageError> {
+        let until: Option<Option<NaiveDateTime>> = self.conn()
+            .query_row("SELECT locked_until FROM device_throttle WHERE id = 1", [], |row| row.get(0))
+            .optional()?;
+
+        Ok(until.flatten())
+    }
+
+    pub fn clear_device_failures(&self) -> Result<(), StorageError> {
+        self.conn().execute("DELETE FROM device_throttle", [])?;
+
+        Ok(())
+    }
+
+    pub fn last_event_type(&self, user_id: i32) -> Result<Option<AttendanceEventType>, StorageError> {
+        let event_type: Option<Option<String>> = self.conn()
+            .query_row(
+                "SELECT last_event_type FROM cached_users WHERE id = :user_id",
+                named_params! {
+                    ":user_id": user_id,
+                },
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(event_type.flatten().as_deref().and_then(AttendanceEventType::parse))
+    }
+
+    // Keeps the cached type current between refreshes as punches reach the server
+    pub fn set_last_event_type(&self, user_id: i32, event_type: AttendanceEventType) -> Result<(), StorageError> {
+        self.conn().execute(
+            "UPDATE cached_users SET last_event_type = :event_type WHERE id = :user_id",
+            named_params! {
+                ":user_id": user_id,
+                ":event_type": event_type.as_str(),
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+// Inserts or updates the user's own columns, leaving the rest of the row alone
+fn upsert_user(conn: &Connection, user: &User) -> Result<(), StorageError> {
+    conn.execute(
+        "INSERT INTO cached_users
+            (id, username, email, full_name, role, status, must_change_password, created_at)
+        VALUES
+            (:id, :username, :email, :full_name, :role, :status, :must_change_password, :created_at)
+        ON CONFLICT (id) DO UPDATE SET
+            username = excluded.username,
+            email = excluded.email,
+            full_name = excluded.full_name,
+            role = excluded.role,
+            status = excluded.status,
+            must_change_password = excluded.must_change_password,
+            created_at = excluded.created_at",
+        named_params! {
+            ":id": user.id,
+            ":username": user.username,
+            ":email": user.email,
+            ":full_name": user.full_name,
+            ":role": user.role.as_str(),
+            ":status": user.status.as_str(),
+            ":must_change_password": user.must_change_password,
+            ":created_at": user.created_at,
+        },
+    )?;
+
+    Ok(())
+}
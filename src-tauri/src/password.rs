@@ -0,0 +1,289 @@
+// src/password.rs
+
+use std::env;
+use argon2::password_hash::{PasswordHash, PasswordHasher as _, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use bcrypt::DEFAULT_COST;
+use rand::rngs::OsRng;
+
+use crate::database::AuthError;
+
+// bcrypt's accepted range; anything outside falls back to the default
+const MIN_COST: u32 = 4;
+const MAX_COST: u32 = 31;
+
+// One password hashing algorithm, recognised by the prefix of the modular
+// crypt strings it produces
+trait PasswordScheme {
+    fn recognizes(&self, hash: &str) -> bool;
+
+    fn hash(&self, password: &str) -> Result<String, AuthError>;
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, AuthError>;
+
+    // True when `hash` belongs to this scheme but uses other parameters than
+    // the configured ones
+    fn is_outdated(&self, hash: &str) -> bool;
+}
+
+#[derive(Debug, Clone)]
+struct Bcrypt {
+    cost: u32,
+}
+
+impl PasswordScheme for Bcrypt {
+    fn recognizes(&self, hash: &str) -> bool {
+        ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| hash.starts_with(prefix))
+    }
+
+    fn hash(&self, password: &str) -> Result<String, AuthError> {
+        bcrypt::hash(password.as_bytes(), self.cost).map_err(|_| AuthError::HashingError)
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, AuthError> {
+        bcrypt::verify(password.as_bytes(), hash).map_err(|_| AuthError::HashingError)
+    }
+
+    fn is_outdated(&self, hash: &str) -> bool {
+        // The cost is the second field, e.g. `$2b$12$...`
+        hash.split('$').nth(2).and_then(|cost| cost.parse().ok()) != Some(self.cost)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Argon2id {
+    params: Params,
+}
+
+impl Argon2id {
+    fn argon2(&self) -> Argon2<'static> {
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+    }
+}
+
+impl PasswordScheme for Argon2id {
+    fn recognizes(&self, hash: &str) -> bool {
+        hash.starts_with("$argon2id$")
+    }
+
+    fn hash(&self, password: &str) -> Result<String, AuthError> {
+        let salt = SaltString::generate(&mut OsRng);
+        
+        self.argon2()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|_| AuthError::HashingError)
+    }
+
+    fn verify(&self, password: &str, hash: &str) -> Result<bool, AuthError> {
+        let parsed = PasswordHash::new(hash).map_err(|_| AuthError::HashingError)?;
+        
+        // The parameters embedded in the hash are used, not the configured ones
+        Ok(self.argon2().verify_password(password.as_bytes(), &parsed).is_ok())
+    }
+
+    fn is_outdated(&self, hash: &str) -> bool {
+        let Ok(parsed) = PasswordHash::new(hash) else {
+            return true;
+        };
+        
+        Params::try_from(&parsed).map_or(true, |params| {
+            params.m_cost() != self.params.m_cost()
+                || params.t_cost() != self.params.t_cost()
+                || params.p_cost() != self.params.p_cost()
+        })
+    }
+}
+
+// Hashes new passwords with the configured scheme (argon2id unless
+// PASSWORD_HASH=bcrypt) and verifies hashes from any supported scheme, so
+// legacy bcrypt hashes keep working until they are upgraded at login.
+// Hashing is deliberately slow, so callers run it off the UI thread.
+#[derive(Debug, Clone)]
+pub struct PasswordHasher {
+    bcrypt: Bcrypt,
+    argon2id: Argon2id,
+    prefer_bcrypt: bool,
+    // Verified against when there is no account, so a missing username
+    // costs as much time as a wrong password
+    dummy_hash: String,
+}
+
+impl PasswordHasher {
+    pub fn from_env() -> Self {
+        let cost = env_parse("BCRYPT_COST")
+            .filter(|cost| (MIN_COST..=MAX_COST).contains(cost))
+            .unwrap_or(DEFAULT_COST);
+            
+        let params = Params::new(
+            env_parse("ARGON2_MEMORY_KIB").unwrap_or(Params::DEFAULT_M_COST),
+            env_parse("ARGON2_ITERATIONS").unwrap_or(Params::DEFAULT_T_COST),
+            env_parse("ARGON2_PARALLELISM").unwrap_or(Params::DEFAULT_P_COST),
+            None,
+        )
+        .unwrap_or_default();
+        
+        let prefer_bcrypt = env::var("PASSWORD_HASH")
+            .is_ok_and(|algorithm| algorithm.trim().eq_ignore_ascii_case("bcrypt"));
+            
+        let mut hasher = PasswordHasher {
+            bcrypt: Bcrypt { cost },
+            argon2id: Argon2id { params },
+            prefer_bcrypt,
+            dummy_hash: String::new(),
+        };
+        hasher.dummy_hash = hasher.hash("not-a-real-password").unwrap_or_default();
+        
+        hasher
+    }
+
+    fn preferred(&self) -> &dyn PasswordScheme {
+        if self.prefer_bcrypt {
+            &self.bcrypt
+        } else {
+            &self.argon2id
+        }
+    }
+
+    fn scheme_for(&self, hash: &str) -> Option<&dyn PasswordScheme> {
+        [&self.argon2id as &dyn PasswordScheme, &self.bcrypt]
+            .into_iter()
+            .find(|scheme| scheme.recognizes(hash))
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, AuthError> {
+        self.preferred().hash(password)
+    }
+
+    pub fn verify(&self, password: &str, hash: &str) -> Result<bool, AuthError> {
+        self.scheme_for(hash)
+            .ok_or(AuthError::HashingError)?
+            .verify(password, hash)
+    }
+
+    // Does the work of `verify` against a fixed hash and always fails; used
+    // when the account does not exist
+    pub fn verify_dummy(&self, password: &str) -> bool {
+        let _ = self.verify(password, &self.dummy_hash);
+        false
+    }
+
+    // True when `hash` uses another scheme or outdated parameters, so it
+    // should be replaced after the next successful login
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        let preferred = self.preferred();
+        !preferred.recognizes(hash) || preferred.is_outdated(hash)
+    }
+}
+
+fn env_parse<T: std::str::FromStr>(name: &str) -> Option<T> {
+    env::var(name).ok().and_then(|value| value.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSWORD: &str = "correct-horse-42";
+
+    // Cheap parameters keep the tests fast; the behaviour does not depend on them
+    fn argon2_params(m_cost: u32, t_cost: u32) -> Params {
+        Params::new(m_cost, t_cost, 1, None).unwrap()
+    }
+
+    fn hasher(prefer_bcrypt: bool) -> PasswordHasher {
+        let mut hasher = PasswordHasher {
+            bcrypt: Bcrypt { cost: MIN_COST },
+            argon2id: Argon2id { params: argon2_params(64, 1) },
+            prefer_bcrypt,
+            dummy_hash: String::new(),
+        };
+        hasher.dummy_hash = hasher.hash("not-a-real-password").unwrap();
+        hasher
+    }
+
+    #[test]
+    fn argon2id_round_trip() {
+        let hasher = hasher(false);
+        let hash = hasher.hash(PASSWORD).unwrap();
+        
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(hasher.verify(PASSWORD, &hash).unwrap());
+        assert!(!hasher.verify("correct-horse-43", &hash).unwrap());
+        assert!(!hasher.needs_rehash(&hash));
+    }
+
+    #[test]
+    fn legacy_bcrypt_hashes_still_verify() {
+        let hasher = hasher(false);
+        let legacy = bcrypt::hash(PASSWORD, MIN_COST).unwrap();
+        
+        assert!(hasher.verify(PASSWORD, &legacy).unwrap());
+        assert!(!hasher.verify("correct-horse-43", &legacy).unwrap());
+    }
+
+    #[test]
+    fn bcrypt_hashes_are_upgraded_to_argon2id() {
+        let hasher = hasher(false);
+        let legacy = bcrypt::hash(PASSWORD, MIN_COST).unwrap();
+        
+        assert!(hasher.needs_rehash(&legacy));
+        
+        let upgraded = hasher.hash(PASSWORD).unwrap();
+        assert!(upgraded.starts_with("$argon2id$"));
+        assert!(!hasher.needs_rehash(&upgraded));
+    }
+
+    #[test]
+    fn weaker_argon2_parameters_need_rehash() {
+        let hasher = hasher(false);
+        let less_memory = Argon2id { params: argon2_params(32, 1) }.hash(PASSWORD).unwrap();
+        let more_passes = PasswordHasher {
+            argon2id: Argon2id { params: argon2_params(64, 2) },
+            ..hasher.clone()
+        };
+        
+        // Old parameters still verify, since they are read from the hash
+        assert!(hasher.verify(PASSWORD, &less_memory).unwrap());
+        assert!(hasher.needs_rehash(&less_memory));
+        assert!(more_passes.needs_rehash(&hasher.hash(PASSWORD).unwrap()));
+    }
+
+    #[test]
+    fn bcrypt_cost_changes_need_rehash() {
+        let hasher = hasher(true);
+        let current = hasher.hash(PASSWORD).unwrap();
+        let cheaper = Bcrypt { cost: MIN_COST + 1 }.hash(PASSWORD).unwrap();
+        let argon2 = hasher.argon2id.hash(PASSWORD).unwrap();
+        
+        assert!(current.starts_with("$2"));
+        assert!(!hasher.needs_rehash(&current));
+        assert!(hasher.needs_rehash(&cheaper));
+        assert!(hasher.needs_rehash(&argon2));
+    }
+
+    #[test]
+    fn malformed_hashes_are_rejected() {
+        let hasher = hasher(false);
+        
+        for hash in ["", "plaintext", "$argon2id$v=19$garbage", "$2b$04$short", "$md5$abc"] {
+            assert!(
+                matches!(hasher.verify(PASSWORD, hash), Err(AuthError::HashingError) | Ok(false)),
+                "{}",
+                hash
+            );
+            assert!(hasher.needs_rehash(hash), "{}", hash);
+        }
+        // Hashes no scheme recognises are an error rather than a mismatch
+        assert!(matches!(hasher.verify(PASSWORD, "plaintext"), Err(AuthError::HashingError)));
+        assert!(matches!(hasher.verify(PASSWORD, "$md5$abc"), Err(AuthError::HashingError)));
+    }
+
+    #[test]
+    fn dummy_verification_always_fails() {
+        let hasher = hasher(false);
+        
+        assert!(!hasher.verify_dummy("not-a-real-password"));
+        assert!(!hasher.verify_dummy(PASSWORD));
+    }
+}
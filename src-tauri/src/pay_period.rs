@@ -0,0 +1,227 @@
+// src/pay_period.rs
+
+use std::env;
+use chrono::{Datelike, Duration, NaiveDate};
+use serde::{Serialize, Deserialize};
+
+// A Monday, so weekly and bi-weekly periods start on Mondays unless
+// PAY_PERIOD_ANCHOR says otherwise
+const DEFAULT_ANCHOR: &str = "2024-01-01";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PayFrequency {
+    Weekly,
+    Biweekly,
+    // The 1st to the 15th, then the 16th to the end of the month
+    SemiMonthly,
+    Monthly,
+}
+
+impl PayFrequency {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "weekly" => Some(PayFrequency::Weekly),
+            "biweekly" => Some(PayFrequency::Biweekly),
+            "semi_monthly" => Some(PayFrequency::SemiMonthly),
+            "monthly" => Some(PayFrequency::Monthly),
+            _ => None,
+        }
+    }
+}
+
+// Both ends are included
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayPeriod {
+    pub starts_on: NaiveDate,
+    pub ends_on: NaiveDate,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PayPeriodConfig {
+    pub frequency: PayFrequency,
+    // Any first day of a weekly or bi-weekly period; ignored otherwise
+    pub anchor: NaiveDate,
+}
+
+impl Default for PayPeriodConfig {
+    fn default() -> Self {
+        PayPeriodConfig {
+            frequency: PayFrequency::Biweekly,
+            anchor: parse_date(DEFAULT_ANCHOR).unwrap_or_default(),
+        }
+    }
+}
+
+impl PayPeriodConfig {
+    pub fn from_env() -> Self {
+        let defaults = PayPeriodConfig::default();
+
+        PayPeriodConfig {
+            frequency: env::var("PAY_PERIOD")
+                .ok()
+                .and_then(|value| PayFrequency::parse(value.trim().to_lowercase().as_str()))
+                .unwrap_or(defaults.frequency),
+            anchor: env::var("PAY_PERIOD_ANCHOR")
+                .ok()
+                .and_then(|value| parse_date(value.trim()))
+                .unwrap_or(defaults.anchor),
+        }
+    }
+
+    pub fn period_containing(&self, date: NaiveDate) -> PayPeriod {
+        match self.frequency {
+            PayFrequency::Weekly => self.fixed_length(date, 7),
+            PayFrequency::Biweekly => self.fixed_length(date, 14),
+            PayFrequency::SemiMonthly if date.day() <= 15 => PayPeriod {
+                starts_on: date.with_day(1).unwrap_or(date),
+                ends_on: date.with_day(15).unwrap_or(date),
+            },
+            PayFrequency::SemiMonthly => PayPeriod {
+                starts_on: date.with_day(16).unwrap_or(date),
+                ends_on: last_day_of_month(date),
+            },
+            PayFrequency::Monthly => PayPeriod {
+                starts_on: date.with_day(1).unwrap_or(date),
+                ends_on: last_day_of_month(date),
+            },
+        }
+    }
+
+    // Every period with at least one day from `from` through `to`, in order
+    pub fn periods(&self, from: NaiveDate, to: NaiveDate) -> Vec<PayPeriod> {
+        let mut periods = Vec::new();
+        let mut period = self.period_containing(from);
+
+        while period.starts_on <= to {
+            periods.push(period);
+            match period.ends_on.succ_opt() {
+                Some(next) => period = self.period_containing(next),
+                None => break,
+            }
+        }
+
+        periods
+    }
+
+    fn fixed_length(&self, date: NaiveDate, days: i64) -> PayPeriod {
+        let offset = (date - self.anchor).num_days().rem_euclid(days);
+        let starts_on = date - Duration::days(offset);
+
+        PayPeriod {
+            starts_on,
+            ends_on: starts_on + Duration::days(days - 1),
+        }
+    }
+}
+
+fn parse_date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+}
+
+fn last_day_of_month(date: NaiveDate) -> NaiveDate {
+    let (year, month) = if date.month() == 12 {
+        (date.year() + 1, 1)
+    } else {
+        (date.year(), date.month() + 1)
+    };
+
+    NaiveDate::from_ymd_opt(year, month, 1)
+        .and_then(|first| first.pred_opt())
+        .unwrap_or(date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn config(frequency: PayFrequency) -> PayPeriodConfig {
+        PayPeriodConfig { frequency, ..PayPeriodConfig::default() }
+    }
+
+    fn period(starts_on: NaiveDate, ends_on: NaiveDate) -> PayPeriod {
+        PayPeriod { starts_on, ends_on }
+    }
+
+    #[test]
+    fn weekly_periods_follow_the_anchor() {
+        let weekly = config(PayFrequency::Weekly);
+
+        // 2024-06-05 is a Wednesday
+        assert_eq!(weekly.period_containing(date(2024, 6, 5)), period(date(2024, 6, 3), date(2024, 6, 9)));
+        assert_eq!(weekly.period_containing(date(2024, 6, 3)), period(date(2024, 6, 3), date(2024, 6, 9)));
+        assert_eq!(weekly.period_containing(date(2024, 6, 9)), period(date(2024, 6, 3), date(2024, 6, 9)));
+
+        let thursdays = PayPeriodConfig { anchor: date(2024, 1, 4), ..weekly };
+        assert_eq!(thursdays.period_containing(date(2024, 6, 5)), period(date(2024, 5, 30), date(2024, 6, 5)));
+    }
+
+    #[test]
+    fn biweekly_periods_alternate_from_the_anchor() {
+        let biweekly = config(PayFrequency::Biweekly);
+
+        assert_eq!(biweekly.period_containing(date(2024, 1, 1)), period(date(2024, 1, 1), date(2024, 1, 14)));
+        assert_eq!(biweekly.period_containing(date(2024, 1, 15)), period(date(2024, 1, 15), date(2024, 1, 28)));
+        // 2024-06-03 is 22 weeks after the anchor, so it starts a period
+        assert_eq!(biweekly.period_containing(date(2024, 6, 12)), period(date(2024, 6, 3), date(2024, 6, 16)));
+    }
+
+    #[test]
+    fn fixed_length_periods_work_before_the_anchor() {
+        let biweekly = config(PayFrequency::Biweekly);
+
+        assert_eq!(biweekly.period_containing(date(2023, 12, 31)), period(date(2023, 12, 18), date(2023, 12, 31)));
+        assert_eq!(biweekly.period_containing(date(2023, 12, 18)), period(date(2023, 12, 18), date(2023, 12, 31)));
+    }
+
+    #[test]
+    fn semi_monthly_periods_split_on_the_fifteenth() {
+        let semi_monthly = config(PayFrequency::SemiMonthly);
+
+        assert_eq!(semi_monthly.period_containing(date(2024, 6, 1)), period(date(2024, 6, 1), date(2024, 6, 15)));
+        assert_eq!(semi_monthly.period_containing(date(2024, 6, 15)), period(date(2024, 6, 1), date(2024, 6, 15)));
+        assert_eq!(semi_monthly.period_containing(date(2024, 6, 16)), period(date(2024, 6, 16), date(2024, 6, 30)));
+        assert_eq!(semi_monthly.period_containing(date(2024, 2, 20)), period(date(2024, 2, 16), date(2024, 2, 29)));
+        assert_eq!(semi_monthly.period_containing(date(2023, 2, 20)), period(date(2023, 2, 16), date(2023, 2, 28)));
+    }
+
+    #[test]
+    fn monthly_periods_are_calendar_months() {
+        let monthly = config(PayFrequency::Monthly);
+
+        assert_eq!(monthly.period_containing(date(2024, 6, 12)), period(date(2024, 6, 1), date(2024, 6, 30)));
+        assert_eq!(monthly.period_containing(date(2024, 12, 31)), period(date(2024, 12, 1), date(2024, 12, 31)));
+        assert_eq!(monthly.period_containing(date(2024, 2, 1)), period(date(2024, 2, 1), date(2024, 2, 29)));
+    }
+
+    #[test]
+    fn periods_cover_the_range_without_gaps() {
+        for frequency in [PayFrequency::Weekly, PayFrequency::Biweekly, PayFrequency::SemiMonthly, PayFrequency::Monthly] {
+            let periods = config(frequency).periods(date(2024, 11, 20), date(2025, 2, 10));
+
+            assert!(periods[0].starts_on <= date(2024, 11, 20));
+            assert!(periods.last().unwrap().ends_on >= date(2025, 2, 10));
+            for pair in periods.windows(2) {
+                assert_eq!(pair[0].ends_on.succ_opt(), Some(pair[1].starts_on));
+            }
+        }
+    }
+
+    #[test]
+    fn periods_of_a_single_day_give_one_period() {
+        let periods = config(PayFrequency::Monthly).periods(date(2024, 6, 30), date(2024, 6, 30));
+
+        assert_eq!(periods, vec![period(date(2024, 6, 1), date(2024, 6, 30))]);
+    }
+
+    #[test]
+    fn parses_frequencies() {
+        assert_eq!(PayFrequency::parse("semi_monthly"), Some(PayFrequency::SemiMonthly));
+        assert_eq!(PayFrequency::parse("biweekly"), Some(PayFrequency::Biweekly));
+        assert_eq!(PayFrequency::parse("fortnightly"), None);
+    }
+}
@@ -0,0 +1,63 @@
+// src/permissions.rs
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Admin,
+    Supervisor,
+    Employee,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Admin => "admin",
+            Role::Supervisor => "supervisor",
+            Role::Employee => "employee",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "admin" => Some(Role::Admin),
+            "supervisor" => Some(Role::Supervisor),
+            "employee" => Some(Role::Employee),
+            _ => None,
+        }
+    }
+
+    pub fn grants(&self, permission: Permission) -> bool {
+        match self {
+            Role::Admin => true,
+            Role::Supervisor => matches!(
+                permission,
+                Permission::ViewTeamAttendance | Permission::ViewSchedules | Permission::PunchSelf
+            ),
+            Role::Employee => matches!(permission, Permission::PunchSelf),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    // Create users and list every account
+    ManageUsers,
+    // Punch or otherwise change attendance on behalf of someone else
+    EditAttendance,
+    // Read attendance logs of any user
+    ViewAllAttendance,
+    // Read attendance logs of direct reports
+    ViewTeamAttendance,
+    // Create and change shifts and who works them
+    ManageSchedules,
+    // Read shifts, assignments and who is expected on site
+    ViewSchedules,
+    // Lock pay periods against attendance changes and reopen them
+    ManagePayroll,
+    // Grant and adjust leave balances
+    ManageLeave,
+    // Clock the caller in or out
+    PunchSelf,
+}
@@ -0,0 +1,331 @@
+// src/punch_queue.rs
+
+use std::sync::{Mutex, MutexGuard};
+use rusqlite::{named_params, Connection, OptionalExtension};
+
+use crate::database::{AttendanceEventType, Punch};
+use crate::qr::QrClaim;
+use crate::storage::StorageError;
+
+// Durable local buffer for punches taken while the server backend is
+// unreachable. Rows stay here until the server acknowledges them; punches
+// the server refuses are kept as rejected so they can be reviewed.
+// A punch authorized by a QR code carries the code, which is redeemed on
+// the server before the punch is replayed.
+pub struct PunchQueue {
+    conn: Mutex<Connection>,
+}
+
+impl PunchQueue {
+    pub fn open(path: &str) -> Result<Self, StorageError> {
+        let conn = Connection::open(path)?;
+
+        conn.execute_batch(
+            r"PRAGMA journal_mode = WAL;
+            PRAGMA synchronous = FULL;
+            PRAGMA foreign_keys = ON;
+            CREATE TABLE IF NOT EXISTS queued_punches (
+                seq INTEGER PRIMARY KEY AUTOINCREMENT,
+                idempotency_key TEXT NOT NULL UNIQUE,
+                user_id INTEGER NOT NULL,
+                event_type TEXT NOT NULL,
+                event_time TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'pending',
+                last_error TEXT NULL
+            );
+            CREATE TABLE IF NOT EXISTS queued_qr_redemptions (
+                idempotency_key TEXT PRIMARY KEY
+                    REFERENCES queued_punches(idempotency_key) ON DELETE CASCADE,
+                user_id INTEGER NOT NULL,
+                time_window INTEGER NOT NULL,
+                redeemed INTEGER NOT NULL DEFAULT 0,
+                UNIQUE (user_id, time_window)
+            );"
+        )?;
+
+        Ok(PunchQueue { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // `qr_code` is the code that authorized the punch, if it is still to be
+    // redeemed; a code already queued once is refused as a Duplicate
+    pub fn enqueue(&self, punch: &Punch, qr_code: Option<QrClaim>) -> Result<(), StorageError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO queued_punches (idempotency_key, user_id, event_type, event_time)
+            VALUES (:idempotency_key, :user_id, :event_type, :event_time)",
+            named_params! {
+                ":idempotency_key": punch.idempotency_key,
+                ":user_id": punch.user_id,
+                ":event_type": punch.event_type.as_str(),
+                ":event_time": punch.event_time,
+            },
+        )?;
+
+        if let Some(claim) = qr_code {
+            tx.execute(
+                "INSERT INTO queued_qr_redemptions (idempotency_key, user_id, time_window)
+                VALUES (:idempotency_key, :user_id, :window)",
+                named_params! {
+                    ":idempotency_key": punch.idempotency_key,
+                    ":user_id": claim.user_id,
+                    ":window": claim.window,
+                },
+            )?;
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    // True when a queued punch, pending or rejected, already used the code
+    pub fn qr_code_queued(&self, claim: QrClaim) -> Result<bool, StorageError> {
+        let queued = self.conn()
+            .query_row(
+                "SELECT 1 FROM queued_qr_redemptions
+                WHERE user_id = :user_id AND time_window = :window",
+                named_params! {
+                    ":user_id": claim.user_id,
+                    ":window": claim.window,
+                },
+                |_| Ok(()),
+            )
+            .optional()?;
+
+        Ok(queued.is_some())
+    }
+
+    // The code the punch still has to redeem before it can be replayed
+    pub fn unredeemed_qr_code(&self, idempotency_key: &str) -> Result<Option<QrClaim>, StorageError> {
+        let claim = self.conn()
+            .query_row(
+                "SELECT user_id, time_window FROM queued_qr_redemptions
+                WHERE idempotency_key = :idempotency_key AND redeemed = 0",
+                named_params! {
+                    ":idempotency_key": idempotency_key,
+                },
+                |row| {
+                    Ok(QrClaim {
+                        user_id: row.get(0)?,
+                        window: row.get(1)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(claim)
+    }
+
+    // Recorded once the server accepts the code, so a replay interrupted by
+    // an outage does not find its own redemption and take it for a reuse
+    pub fn mark_qr_code_redeemed(&self, idempotency_key: &str) -> Result<(), StorageError> {
+        self.conn().execute(
+            "UPDATE queued_qr_redemptions SET redeemed = 1 WHERE idempotency_key = :idempotency_key",
+            named_params! {
+                ":idempotency_key": idempotency_key,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    // Pending punches in the order they were taken
+    pub fn pending(&self) -> Result<Vec<Punch>, StorageError> {
+        let conn = self.conn();
+
+        let punches = conn
+            .prepare(
+                "SELECT idempotency_key, user_id, event_type, event_time FROM queued_punches
+                WHERE status = 'pending'
+                ORDER BY event_time, seq",
+            )?
+            .query_map([], |row| {
+                Ok(Punch {
+                    idempotency_key: row.get(0)?,
+                    user_id: row.get(1)?,
+                    event_type: AttendanceEventType::parse(&row.get::<_, String>(2)?)
+                        .unwrap_or(AttendanceEventType::Out),
+                    event_time: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(punches)
+    }
+
+    pub fn pending_count(&self) -> Result<usize, StorageError> {
+        let count: usize = self.conn()
+            .query_row("SELECT COUNT(*) FROM queued_punches WHERE status = 'pending'", [], |row| row.get(0))?;
+
+        Ok(count)
+    }
+
+    // Type of the user's most recent pending punch, if any
+    pub fn last_pending(&self, user_id: i32) -> Result<Option<AttendanceEventType>, StorageError> {
+        let event_type: Option<String> = self.conn()
+            .query_row(
+                "SELECT event_type FROM queued_punches
+                WHERE user_id = :user_id AND status = 'pending'
+                ORDER BY event_time DESC, seq DESC
+                LIMIT 1",
+                named_params! {
+                    ":user_id": user_id,
+                },
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(event_type.as_deref().and_then(AttendanceEventType::parse))
+    }
+
+    pub fn remove(&self, idempotency_key: &str) -> Result<(), StorageError> {
+        self.conn().execute(
+            "DELETE FROM queued_punches WHERE idempotency_key = :idempotency_key",
+            named_params! {
+                ":idempotency_key": idempotency_key,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    pub fn reject(&self, idempotency_key: &str, reason: &str) -> Result<(), StorageError> {
+        self.conn().execute(
+            "UPDATE queued_punches SET status = 'rejected', last_error = :reason
+            WHERE idempotency_key = :idempotency_key",
+            named_params! {
+                ":idempotency_key": idempotency_key,
+                ":reason": reason,
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, NaiveDate, NaiveDateTime};
+
+    fn queue() -> PunchQueue {
+        PunchQueue::open(":memory:").unwrap()
+    }
+
+    fn at(minutes: i64) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, 4).unwrap().and_hms_opt(8, 0, 0).unwrap() + Duration::minutes(minutes)
+    }
+
+    fn punch(key: &str, user_id: i32, event_type: AttendanceEventType, minutes: i64) -> Punch {
+        Punch {
+            idempotency_key: key.to_string(),
+            user_id,
+            event_type,
+            event_time: at(minutes),
+        }
+    }
+
+    fn pending_keys(queue: &PunchQueue) -> Vec<String> {
+        queue.pending().unwrap().into_iter().map(|p| p.idempotency_key).collect()
+    }
+
+    #[test]
+    fn enqueued_punches_are_pending() {
+        let queue = queue();
+        queue.enqueue(&punch("a", 7, AttendanceEventType::In, 0), None).unwrap();
+        
+        let pending = queue.pending().unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].user_id, 7);
+        assert_eq!(pending[0].event_type, AttendanceEventType::In);
+        assert_eq!(pending[0].event_time, at(0));
+        assert_eq!(queue.pending_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn idempotency_keys_are_queued_once() {
+        let queue = queue();
+        queue.enqueue(&punch("a", 7, AttendanceEventType::In, 0), None).unwrap();
+        
+        let again = queue.enqueue(&punch("a", 7, AttendanceEventType::Out, 5), None);
+        
+        assert!(matches!(again, Err(StorageError::Duplicate(_))));
+        assert_eq!(queue.pending_count().unwrap(), 1);
+        assert_eq!(queue.last_pending(7).unwrap(), Some(AttendanceEventType::In));
+    }
+
+    #[test]
+    fn punches_replay_in_the_order_they_were_taken() {
+        let queue = queue();
+        queue.enqueue(&punch("late", 7, AttendanceEventType::Out, 60), None).unwrap();
+        queue.enqueue(&punch("early", 7, AttendanceEventType::In, 0), None).unwrap();
+        // Same second: the order of arrival decides
+        queue.enqueue(&punch("first", 8, AttendanceEventType::In, 30), None).unwrap();
+        queue.enqueue(&punch("second", 8, AttendanceEventType::Out, 30), None).unwrap();
+        
+        assert_eq!(pending_keys(&queue), ["early", "first", "second", "late"]);
+        assert_eq!(queue.last_pending(7).unwrap(), Some(AttendanceEventType::Out));
+        assert_eq!(queue.last_pending(8).unwrap(), Some(AttendanceEventType::Out));
+        assert_eq!(queue.last_pending(9).unwrap(), None);
+    }
+
+    #[test]
+    fn rejected_punches_stay_but_are_no_longer_pending() {
+        let queue = queue();
+        queue.enqueue(&punch("a", 7, AttendanceEventType::In, 0), None).unwrap();
+        queue.enqueue(&punch("b", 7, AttendanceEventType::Out, 5), None).unwrap();
+        
+        queue.reject("b", "Not clocked in").unwrap();
+        
+        assert_eq!(pending_keys(&queue), ["a"]);
+        assert_eq!(queue.last_pending(7).unwrap(), Some(AttendanceEventType::In));
+        // Kept for review, so the key cannot be queued again
+        assert!(matches!(
+            queue.enqueue(&punch("b", 7, AttendanceEventType::Out, 5), None),
+            Err(StorageError::Duplicate(_))
+        ));
+        
+        queue.remove("a").unwrap();
+        assert_eq!(queue.pending_count().unwrap(), 0);
+    }
+
+    #[test]
+    fn a_qr_code_is_queued_once() {
+        let queue = queue();
+        let claim = QrClaim { user_id: 7, window: 100 };
+        queue.enqueue(&punch("a", 7, AttendanceEventType::In, 0), Some(claim)).unwrap();
+        
+        assert!(queue.qr_code_queued(claim).unwrap());
+        assert!(!queue.qr_code_queued(QrClaim { user_id: 7, window: 101 }).unwrap());
+        
+        // The punch is not queued without its code
+        let again = queue.enqueue(&punch("b", 7, AttendanceEventType::Out, 1), Some(claim));
+        assert!(matches!(again, Err(StorageError::Duplicate(_))));
+        assert_eq!(pending_keys(&queue), ["a"]);
+    }
+
+    #[test]
+    fn redeemed_codes_are_not_redeemed_again() {
+        let queue = queue();
+        let claim = QrClaim { user_id: 7, window: 100 };
+        queue.enqueue(&punch("a", 7, AttendanceEventType::In, 0), Some(claim)).unwrap();
+        queue.enqueue(&punch("b", 7, AttendanceEventType::Out, 5), None).unwrap();
+        
+        assert_eq!(queue.unredeemed_qr_code("a").unwrap(), Some(claim));
+        assert_eq!(queue.unredeemed_qr_code("b").unwrap(), None);
+        
+        queue.mark_qr_code_redeemed("a").unwrap();
+        assert_eq!(queue.unredeemed_qr_code("a").unwrap(), None);
+        assert!(queue.qr_code_queued(claim).unwrap());
+        
+        // Removing the synced punch frees its code
+        queue.remove("a").unwrap();
+        assert!(!queue.qr_code_queued(claim).unwrap());
+    }
+}
@@ -0,0 +1,206 @@
+// src/qr.rs
+
+use std::env;
+use chrono::{DateTime, NaiveDateTime};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::database::AuthError;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Lets the format change later without old codes being misread
+const PAYLOAD_PREFIX: &str = "ATTQR1";
+// A code is valid for one window and the app shows the next one after that
+pub const QR_WINDOW_SECS: i64 = 30;
+// Neighbouring windows still accepted, for a code scanned just as it
+// rotated or a phone whose clock is slightly off
+pub const QR_WINDOW_TOLERANCE: i64 = 1;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QrCode {
+    pub payload: String,
+    pub expires_at: NaiveDateTime,
+}
+
+// A verified code: whose it is and which window it was issued for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QrClaim {
+    pub user_id: i32,
+    pub window: i64,
+}
+
+// Signs and checks QR payloads of the form
+// `ATTQR1.<user id>.<window>.<hex HMAC-SHA256>`
+pub struct QrSigner {
+    key: Vec<u8>,
+}
+
+impl QrSigner {
+    // Every terminal that shows or scans codes needs the same
+    // QR_SIGNING_SECRET. Without one a random key is used, so codes only
+    // work within this process.
+    pub fn from_env() -> Self {
+        let key = match env::var("QR_SIGNING_SECRET") {
+            Ok(secret) if !secret.trim().is_empty() => secret.into_bytes(),
+            _ => {
+                eprintln!("QR_SIGNING_SECRET is not set; QR codes will not be accepted by other terminals");
+                let mut key = vec![0u8; 32];
+                rand::thread_rng().fill_bytes(&mut key);
+                key
+            }
+        };
+
+        QrSigner { key }
+    }
+
+    pub fn window_at(now: NaiveDateTime) -> i64 {
+        now.and_utc().timestamp().div_euclid(QR_WINDOW_SECS)
+    }
+
+    pub fn issue(&self, user_id: i32, now: NaiveDateTime) -> QrCode {
+        let window = QrSigner::window_at(now);
+        let signature = hex::encode(self.mac(user_id, window).finalize().into_bytes());
+        let expires_at = DateTime::from_timestamp((window + 1) * QR_WINDOW_SECS, 0)
+            .map_or(now, |expires_at| expires_at.naive_utc());
+
+        QrCode {
+            payload: format!("{PAYLOAD_PREFIX}.{user_id}.{window}.{signature}"),
+            expires_at,
+        }
+    }
+
+    // Checks the signature before freshness, so an expired code is only
+    // reported as such when it is genuine
+    pub fn verify(&self, payload: &str, now: NaiveDateTime) -> Result<QrClaim, AuthError> {
+        let claim = self.parse(payload.trim()).ok_or(AuthError::InvalidQrCode)?;
+
+        if (QrSigner::window_at(now) - claim.window).abs() > QR_WINDOW_TOLERANCE {
+            return Err(AuthError::QrCodeExpired);
+        }
+
+        Ok(claim)
+    }
+
+    fn parse(&self, payload: &str) -> Option<QrClaim> {
+        let mut parts = payload.split('.');
+        let (Some(PAYLOAD_PREFIX), Some(user_id), Some(window), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return None;
+        };
+
+        let user_id = user_id.parse().ok()?;
+        let window = window.parse().ok()?;
+        let signature = hex::decode(signature).ok()?;
+
+        // Constant-time comparison
+        self.mac(user_id, window).verify_slice(&signature).ok()?;
+
+        Some(QrClaim { user_id, window })
+    }
+
+    fn mac(&self, user_id: i32, window: i64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(format!("{user_id}.{window}").as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn signer(key: &str) -> QrSigner {
+        QrSigner { key: key.as_bytes().to_vec() }
+    }
+
+    // The start of a window, so whole windows can be added
+    fn window_start() -> NaiveDateTime {
+        DateTime::from_timestamp(1_800_000_000 / QR_WINDOW_SECS * QR_WINDOW_SECS, 0)
+            .unwrap()
+            .naive_utc()
+    }
+
+    fn windows(count: i64) -> Duration {
+        Duration::seconds(count * QR_WINDOW_SECS)
+    }
+
+    #[test]
+    fn issued_code_verifies() {
+        let signer = signer("secret");
+        let code = signer.issue(42, window_start());
+
+        let claim = signer.verify(&code.payload, window_start()).unwrap();
+
+        assert_eq!(claim, QrClaim { user_id: 42, window: QrSigner::window_at(window_start()) });
+        assert_eq!(code.expires_at, window_start() + windows(1));
+    }
+
+    #[test]
+    fn tampered_signature_is_rejected() {
+        let signer = signer("secret");
+        let payload = signer.issue(42, window_start()).payload;
+        let flipped = if payload.ends_with('0') { '1' } else { '0' };
+        let tampered = format!("{}{}", &payload[..payload.len() - 1], flipped);
+
+        assert!(matches!(signer.verify(&tampered, window_start()), Err(AuthError::InvalidQrCode)));
+    }
+
+    #[test]
+    fn code_for_another_user_is_rejected() {
+        let signer = signer("secret");
+        let payload = signer.issue(42, window_start()).payload;
+        let forged = payload.replacen(".42.", ".43.", 1);
+
+        assert!(matches!(signer.verify(&forged, window_start()), Err(AuthError::InvalidQrCode)));
+    }
+
+    #[test]
+    fn code_signed_with_another_key_is_rejected() {
+        let payload = signer("other").issue(42, window_start()).payload;
+
+        assert!(matches!(signer("secret").verify(&payload, window_start()), Err(AuthError::InvalidQrCode)));
+    }
+
+    #[test]
+    fn wrong_prefix_or_version_is_rejected() {
+        let signer = signer("secret");
+        let payload = signer.issue(42, window_start()).payload;
+        let rest = payload.strip_prefix(PAYLOAD_PREFIX).unwrap();
+
+        for prefix in ["ATTQR2", "XXXQR1", "attqr1", ""] {
+            let payload = format!("{prefix}{rest}");
+            assert!(matches!(signer.verify(&payload, window_start()), Err(AuthError::InvalidQrCode)), "{payload}");
+        }
+        assert!(matches!(signer.verify(&format!("{payload}.extra"), window_start()), Err(AuthError::InvalidQrCode)));
+    }
+
+    #[test]
+    fn edge_of_the_tolerance_is_accepted() {
+        let signer = signer("secret");
+        let payload = signer.issue(42, window_start()).payload;
+
+        for offset in -QR_WINDOW_TOLERANCE..=QR_WINDOW_TOLERANCE {
+            assert!(signer.verify(&payload, window_start() + windows(offset)).is_ok(), "{offset}");
+        }
+        // Last second of the last accepted window
+        let last = window_start() + windows(QR_WINDOW_TOLERANCE + 1) - Duration::seconds(1);
+        assert!(signer.verify(&payload, last).is_ok());
+    }
+
+    #[test]
+    fn windows_outside_the_tolerance_are_expired() {
+        let signer = signer("secret");
+        let payload = signer.issue(42, window_start()).payload;
+
+        let later = window_start() + windows(QR_WINDOW_TOLERANCE + 1);
+        let earlier = window_start() - windows(QR_WINDOW_TOLERANCE) - Duration::seconds(1);
+
+        assert!(matches!(signer.verify(&payload, later), Err(AuthError::QrCodeExpired)));
+        assert!(matches!(signer.verify(&payload, earlier), Err(AuthError::QrCodeExpired)));
+    }
+}
@@ -0,0 +1,454 @@
+// src/rules.rs
+
+use std::env;
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use serde::{Serialize, Deserialize};
+
+use crate::database::{AttendanceEvent, AttendanceEventType, ScheduledShift, Shift, ShiftSegment};
+
+const DEFAULT_LATE_GRACE_MINUTES: i64 = 5;
+const DEFAULT_EARLY_LEAVE_GRACE_MINUTES: i64 = 5;
+// Punches this long before a shift starts or after it ends still count
+// towards it; anything further out is unscheduled work
+pub const PUNCH_MATCH_HOURS: i64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttendanceStatus {
+    OnTime,
+    Late,
+    EarlyLeave,
+    Absent,
+    Unscheduled,
+    // No punches, but the day is covered by approved leave
+    Excused,
+    // The shift has not ended yet, so it cannot be judged as a whole
+    Pending,
+}
+
+impl AttendanceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AttendanceStatus::OnTime => "on_time",
+            AttendanceStatus::Late => "late",
+            AttendanceStatus::EarlyLeave => "early_leave",
+            AttendanceStatus::Absent => "absent",
+            AttendanceStatus::Unscheduled => "unscheduled",
+            AttendanceStatus::Excused => "excused",
+            AttendanceStatus::Pending => "pending",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "on_time" => Some(AttendanceStatus::OnTime),
+            "late" => Some(AttendanceStatus::Late),
+            "early_leave" => Some(AttendanceStatus::EarlyLeave),
+            "absent" => Some(AttendanceStatus::Absent),
+            "unscheduled" => Some(AttendanceStatus::Unscheduled),
+            "excused" => Some(AttendanceStatus::Excused),
+            "pending" => Some(AttendanceStatus::Pending),
+            _ => None,
+        }
+    }
+
+    // Statuses a supervisor should look at
+    pub fn is_exception(&self) -> bool {
+        matches!(
+            self,
+            AttendanceStatus::Late
+                | AttendanceStatus::EarlyLeave
+                | AttendanceStatus::Absent
+                | AttendanceStatus::Unscheduled
+        )
+    }
+}
+
+// How one user did on one scheduled shift, or on a day they worked
+// without one. A shift can be both late and an early leave.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DayAttendance {
+    pub user_id: i32,
+    pub date: NaiveDate,
+    // None for unscheduled work
+    pub shift: Option<Shift>,
+    pub statuses: Vec<AttendanceStatus>,
+    pub late_minutes: i64,
+    pub early_leave_minutes: i64,
+    pub first_in: Option<NaiveDateTime>,
+    pub last_out: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct AttendanceRules {
+    // Arriving up to this long after the start still counts as on time
+    pub late_grace: Duration,
+    // Leaving up to this long before the end still counts as on time
+    pub early_leave_grace: Duration,
+}
+
+impl Default for AttendanceRules {
+    fn default() -> Self {
+        AttendanceRules {
+            late_grace: Duration::minutes(DEFAULT_LATE_GRACE_MINUTES),
+            early_leave_grace: Duration::minutes(DEFAULT_EARLY_LEAVE_GRACE_MINUTES),
+        }
+    }
+}
+
+impl AttendanceRules {
+    pub fn from_env() -> Self {
+        let minutes = |name: &str, default: i64| {
+            env::var(name)
+                .ok()
+                .and_then(|value| value.trim().parse::<i64>().ok())
+                .filter(|minutes| *minutes >= 0)
+                .map_or(Duration::minutes(default), Duration::minutes)
+        };
+
+        AttendanceRules {
+            late_grace: minutes("LATE_GRACE_MINUTES", DEFAULT_LATE_GRACE_MINUTES),
+            early_leave_grace: minutes("EARLY_LEAVE_GRACE_MINUTES", DEFAULT_EARLY_LEAVE_GRACE_MINUTES),
+        }
+    }
+
+    // Judges one user's `shifts` against their `events`, all in UTC, as of
+    // `now`. Events no shift claims are reported as unscheduled work on the
+    // day `local_date` puts them. Shifts on `leave_days` without punches are
+    // excused rather than absent.
+    pub fn classify(
+        &self,
+        user_id: i32,
+        shifts: &[ScheduledShift],
+        events: &[AttendanceEvent],
+        leave_days: &[NaiveDate],
+        now: NaiveDateTime,
+        local_date: impl Fn(NaiveDateTime) -> NaiveDate,
+    ) -> Vec<DayAttendance> {
+        let mut events: Vec<&AttendanceEvent> = events.iter().collect();
+        events.sort_by_key(|event| event.event_time);
+        let mut claimed = vec![false; events.len()];
+        let mut days = Vec::new();
+
+        for scheduled in shifts {
+            let on_leave = leave_days.contains(&scheduled.date);
+            days.push(self.classify_shift(user_id, scheduled, &events, &mut claimed, on_leave, now));
+        }
+
+        // Whatever is left was worked outside any shift, grouped by local day
+        let mut unscheduled: Vec<DayAttendance> = Vec::new();
+        for (event, _) in events.iter().zip(&claimed).filter(|(_, claimed)| !**claimed) {
+            let date = local_date(event.event_time);
+            let day = match unscheduled.iter_mut().find(|day| day.date == date) {
+                Some(day) => day,
+                None => {
+                    unscheduled.push(DayAttendance {
+                        user_id,
+                        date,
+                        shift: None,
+                        statuses: vec![AttendanceStatus::Unscheduled],
+                        late_minutes: 0,
+                        early_leave_minutes: 0,
+                        first_in: None,
+                        last_out: None,
+                    });
+                    unscheduled.last_mut().expect("just pushed")
+                }
+            };
+            record_punch(day, event);
+        }
+        days.extend(unscheduled);
+
+        days.sort_by_key(|day| day.date);
+        days
+    }
+
+    fn classify_shift(
+        &self,
+        user_id: i32,
+        scheduled: &ScheduledShift,
+        events: &[&AttendanceEvent],
+        claimed: &mut [bool],
+        on_leave: bool,
+        now: NaiveDateTime,
+    ) -> DayAttendance {
+        let mut day = DayAttendance {
+            user_id,
+            date: scheduled.date,
+            shift: Some(scheduled.shift.clone()),
+            statuses: Vec::new(),
+            late_minutes: 0,
+            early_leave_minutes: 0,
+            first_in: None,
+            last_out: None,
+        };
+        let mut punched = false;
+
+        for (index, segment) in scheduled.segments.iter().enumerate() {
+            let (window_start, window_end) = punch_window(&scheduled.segments, index);
+            let mut first_in = None;
+            let mut last_out = None;
+
+            for (event, claimed) in events.iter().zip(claimed.iter_mut()) {
+                if *claimed || event.event_time < window_start || event.event_time >= window_end {
+                    continue;
+                }
+                *claimed = true;
+                punched = true;
+                record_punch(&mut day, event);
+
+                match event.event_type {
+                    AttendanceEventType::In => {
+                        first_in.get_or_insert(event.event_time);
+                    }
+                    AttendanceEventType::Out => last_out = Some(event.event_time),
+                    // Breaks do not move arrival or departure
+                    AttendanceEventType::BreakStart | AttendanceEventType::BreakEnd => {}
+                }
+            }
+
+            let late_by = match first_in {
+                Some(first_in) => first_in - segment.starts_at,
+                // Missed this segment while working another one
+                None if now >= segment.ends_at => segment.ends_at - segment.starts_at,
+                None => now - segment.starts_at,
+            };
+            if late_by > self.late_grace {
+                day.late_minutes += late_by.num_minutes();
+            }
+
+            // Without an OUT the user is still clocked in or forgot to punch
+            // out, neither of which is leaving early
+            if let Some(last_out) = last_out.filter(|_| now >= segment.ends_at) {
+                let early_by = segment.ends_at - last_out;
+                if early_by > self.early_leave_grace {
+                    day.early_leave_minutes += early_by.num_minutes();
+                }
+            }
+        }
+
+        let ended = scheduled.segments.last().is_none_or(|segment| now >= segment.ends_at);
+
+        // Working on a leave day is judged like any other day
+        if !punched {
+            day.late_minutes = 0;
+            day.statuses.push(if on_leave {
+                AttendanceStatus::Excused
+            } else if ended {
+                AttendanceStatus::Absent
+            } else {
+                AttendanceStatus::Pending
+            });
+            return day;
+        }
+
+        if day.late_minutes > 0 {
+            day.statuses.push(AttendanceStatus::Late);
+        }
+        if day.early_leave_minutes > 0 {
+            day.statuses.push(AttendanceStatus::EarlyLeave);
+        }
+        if !ended {
+            day.statuses.push(AttendanceStatus::Pending);
+        } else if day.statuses.is_empty() {
+            day.statuses.push(AttendanceStatus::OnTime);
+        }
+
+        day
+    }
+}
+
+// Punches between two segments of a split shift go to the nearer one
+fn punch_window(segments: &[ShiftSegment], index: usize) -> (NaiveDateTime, NaiveDateTime) {
+    let segment = segments[index];
+    let margin = Duration::hours(PUNCH_MATCH_HOURS);
+
+    let start = match index.checked_sub(1).map(|previous| segments[previous]) {
+        Some(previous) => previous.ends_at + (segment.starts_at - previous.ends_at) / 2,
+        None => segment.starts_at - margin,
+    };
+    let end = match segments.get(index + 1) {
+        Some(next) => segment.ends_at + (next.starts_at - segment.ends_at) / 2,
+        None => segment.ends_at + margin,
+    };
+
+    (start, end)
+}
+
+fn record_punch(day: &mut DayAttendance, event: &AttendanceEvent) {
+    match event.event_type {
+        AttendanceEventType::In => {
+            day.first_in.get_or_insert(event.event_time);
+        }
+        AttendanceEventType::Out => day.last_out = Some(event.event_time),
+        AttendanceEventType::BreakStart | AttendanceEventType::BreakEnd => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    // (hour, minute) of a segment's start and end
+    type Span = ((u32, u32), (u32, u32));
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2026, 3, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    fn scheduled(day: u32, segments: &[Span]) -> ScheduledShift {
+        ScheduledShift {
+            assignment_id: 1,
+            user_id: 7,
+            shift: Shift {
+                id: 1,
+                name: "Day".to_string(),
+                start_time: NaiveTime::MIN,
+                end_time: NaiveTime::MIN,
+                split_start_time: None,
+                split_end_time: None,
+            },
+            date: NaiveDate::from_ymd_opt(2026, 3, day).unwrap(),
+            segments: segments
+                .iter()
+                .map(|&((start_h, start_m), (end_h, end_m))| ShiftSegment {
+                    starts_at: at(day, start_h, start_m),
+                    ends_at: at(day, end_h, end_m),
+                })
+                .collect(),
+        }
+    }
+
+    fn event(event_type: AttendanceEventType, event_time: NaiveDateTime) -> AttendanceEvent {
+        AttendanceEvent {
+            id: 0,
+            user_id: 7,
+            event_type,
+            event_time,
+            idempotency_key: None,
+            corrects_event_id: None,
+            correction_id: None,
+            queued: false,
+        }
+    }
+
+    fn classify(shifts: &[ScheduledShift], events: &[AttendanceEvent], now: NaiveDateTime) -> Vec<DayAttendance> {
+        AttendanceRules::default().classify(7, shifts, events, &[], now, |time| time.date())
+    }
+
+    const NINE_TO_FIVE: &[Span] = &[((9, 0), (17, 0))];
+
+    #[test]
+    fn punches_within_grace_are_on_time() {
+        let events = [
+            event(AttendanceEventType::In, at(2, 9, 4)),
+            event(AttendanceEventType::Out, at(2, 16, 57)),
+        ];
+
+        let days = classify(&[scheduled(2, NINE_TO_FIVE)], &events, at(3, 0, 0));
+
+        assert_eq!(days.len(), 1);
+        assert_eq!(days[0].statuses, [AttendanceStatus::OnTime]);
+    }
+
+    #[test]
+    fn late_arrival_and_early_leave_are_both_reported() {
+        let events = [
+            event(AttendanceEventType::In, at(2, 9, 20)),
+            event(AttendanceEventType::Out, at(2, 16, 30)),
+        ];
+
+        let days = classify(&[scheduled(2, NINE_TO_FIVE)], &events, at(3, 0, 0));
+
+        assert_eq!(days[0].statuses, [AttendanceStatus::Late, AttendanceStatus::EarlyLeave]);
+        assert_eq!(days[0].late_minutes, 20);
+        assert_eq!(days[0].early_leave_minutes, 30);
+    }
+
+    #[test]
+    fn no_punches_after_the_shift_is_absent() {
+        let days = classify(&[scheduled(2, NINE_TO_FIVE)], &[], at(3, 0, 0));
+
+        assert_eq!(days[0].statuses, [AttendanceStatus::Absent]);
+    }
+
+    #[test]
+    fn missed_shift_on_leave_is_excused() {
+        let leave_days = [NaiveDate::from_ymd_opt(2026, 3, 2).unwrap()];
+        let shifts = [scheduled(2, NINE_TO_FIVE), scheduled(3, NINE_TO_FIVE)];
+
+        let days = AttendanceRules::default().classify(7, &shifts, &[], &leave_days, at(3, 12, 0), |time| time.date());
+
+        assert_eq!(days[0].statuses, [AttendanceStatus::Excused]);
+        assert!(!days[0].statuses[0].is_exception());
+        assert_eq!(days[1].statuses, [AttendanceStatus::Pending]);
+    }
+
+    #[test]
+    fn running_shift_is_pending() {
+        let events = [event(AttendanceEventType::In, at(2, 8, 55))];
+
+        let days = classify(&[scheduled(2, NINE_TO_FIVE)], &events, at(2, 12, 0));
+
+        assert_eq!(days[0].statuses, [AttendanceStatus::Pending]);
+    }
+
+    #[test]
+    fn late_arrival_shows_while_shift_is_pending() {
+        let events = [event(AttendanceEventType::In, at(2, 10, 0))];
+
+        let days = classify(&[scheduled(2, NINE_TO_FIVE)], &events, at(2, 12, 0));
+
+        assert_eq!(days[0].statuses, [AttendanceStatus::Late, AttendanceStatus::Pending]);
+        assert_eq!(days[0].late_minutes, 60);
+    }
+
+    #[test]
+    fn missing_out_punch_is_not_an_early_leave() {
+        let events = [event(AttendanceEventType::In, at(2, 9, 0))];
+
+        let days = classify(&[scheduled(2, NINE_TO_FIVE)], &events, at(3, 0, 0));
+
+        assert_eq!(days[0].statuses, [AttendanceStatus::OnTime]);
+    }
+
+    #[test]
+    fn split_shift_segments_are_judged_separately() {
+        let split = scheduled(2, &[((7, 0), (11, 0)), ((16, 0), (20, 0))]);
+        let events = [
+            event(AttendanceEventType::In, at(2, 7, 0)),
+            event(AttendanceEventType::Out, at(2, 11, 0)),
+            event(AttendanceEventType::In, at(2, 16, 45)),
+            event(AttendanceEventType::Out, at(2, 20, 0)),
+        ];
+
+        let days = classify(&[split], &events, at(3, 0, 0));
+
+        assert_eq!(days[0].statuses, [AttendanceStatus::Late]);
+        assert_eq!(days[0].late_minutes, 45);
+        assert_eq!(days[0].first_in, Some(at(2, 7, 0)));
+        assert_eq!(days[0].last_out, Some(at(2, 20, 0)));
+    }
+
+    #[test]
+    fn punches_far_from_any_shift_are_unscheduled() {
+        let events = [
+            event(AttendanceEventType::In, at(2, 9, 0)),
+            event(AttendanceEventType::Out, at(2, 17, 0)),
+            event(AttendanceEventType::In, at(4, 10, 0)),
+            event(AttendanceEventType::Out, at(4, 14, 0)),
+        ];
+
+        let days = classify(&[scheduled(2, NINE_TO_FIVE)], &events, at(5, 0, 0));
+
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].statuses, [AttendanceStatus::OnTime]);
+        assert_eq!(days[1].date, NaiveDate::from_ymd_opt(2026, 3, 4).unwrap());
+        assert_eq!(days[1].statuses, [AttendanceStatus::Unscheduled]);
+        assert!(days[1].shift.is_none());
+    }
+}
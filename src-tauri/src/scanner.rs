@@ -0,0 +1,191 @@
+// src/scanner.rs
+
+use serde::Serialize;
+
+use crate::database::{AttendanceEvent, User};
+
+// Badge readers that act as keyboards type a whole badge within a few
+// milliseconds per key; people rarely manage less than ~80ms between keys
+pub const MAX_KEY_INTERVAL_MS: u64 = 35;
+pub const MIN_BADGE_LEN: usize = 4;
+pub const MAX_BADGE_LEN: usize = 64;
+
+// Separates badge scans from ordinary typing by keystroke timing. Keys are
+// fed in as they arrive, with timestamps in milliseconds; a scan is an
+// unbroken burst of letters and digits terminated by Enter.
+#[derive(Debug)]
+pub struct ScanDetector {
+    buffer: String,
+    last_key_at: Option<u64>,
+    max_interval_ms: u64,
+}
+
+impl Default for ScanDetector {
+    fn default() -> Self {
+        ScanDetector::new(MAX_KEY_INTERVAL_MS)
+    }
+}
+
+impl ScanDetector {
+    pub fn new(max_interval_ms: u64) -> Self {
+        ScanDetector {
+            buffer: String::new(),
+            last_key_at: None,
+            max_interval_ms,
+        }
+    }
+
+    // `key` uses KeyboardEvent.key names, e.g. "7", "A" or "Enter".
+    // Returns the badge number when this key completes a scan.
+    pub fn key(&mut self, key: &str, at_ms: u64) -> Option<String> {
+        // Readers press Shift for capitals; it says nothing about timing
+        if matches!(key, "Shift" | "CapsLock") {
+            return None;
+        }
+        
+        let in_burst = self
+            .last_key_at
+            .is_some_and(|last| at_ms.saturating_sub(last) <= self.max_interval_ms);
+        if !in_burst {
+            self.buffer.clear();
+        }
+        self.last_key_at = Some(at_ms);
+        
+        if key == "Enter" {
+            let badge = std::mem::take(&mut self.buffer);
+            self.last_key_at = None;
+            return (in_burst && badge.len() >= MIN_BADGE_LEN).then_some(badge);
+        }
+        
+        let mut chars = key.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_ascii_alphanumeric() && self.buffer.len() < MAX_BADGE_LEN => {
+                self.buffer.push(c);
+                None
+            }
+            _ => {
+                self.buffer.clear();
+                None
+            }
+        }
+    }
+}
+
+// Payload of the `badge-scanned` event
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BadgeScanResult {
+    pub badge_number: String,
+    pub user: Option<User>,
+    pub event: Option<AttendanceEvent>,
+    pub error: Option<String>,
+    // The badge holder has a PIN, so nothing was punched; the kiosk asks
+    // for it and finishes through `punch_by_badge`
+    pub pin_required: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feeds `keys` starting at t=1000ms with `interval_ms` between keys and
+    // returns every scan detected
+    fn feed(detector: &mut ScanDetector, keys: &[&str], interval_ms: u64) -> Vec<String> {
+        keys.iter()
+            .enumerate()
+            .filter_map(|(i, key)| detector.key(key, 1000 + i as u64 * interval_ms))
+            .collect()
+    }
+
+    fn badge_keys(badge: &str) -> Vec<String> {
+        badge.chars().map(String::from).chain(["Enter".to_string()]).collect()
+    }
+
+    fn as_refs(keys: &[String]) -> Vec<&str> {
+        keys.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn fast_burst_ending_in_enter_is_a_scan() {
+        let mut detector = ScanDetector::default();
+        let keys = badge_keys("0042A7");
+        
+        assert_eq!(feed(&mut detector, &as_refs(&keys), 8), ["0042A7"]);
+    }
+
+    #[test]
+    fn human_typing_is_ignored() {
+        let mut detector = ScanDetector::default();
+        let keys = badge_keys("0042A7");
+        
+        assert!(feed(&mut detector, &as_refs(&keys), 120).is_empty());
+    }
+
+    #[test]
+    fn burst_shorter_than_minimum_is_ignored() {
+        let mut detector = ScanDetector::default();
+        let keys = badge_keys("123");
+        
+        assert!(feed(&mut detector, &as_refs(&keys), 5).is_empty());
+    }
+
+    #[test]
+    fn pause_restarts_the_burst() {
+        let mut detector = ScanDetector::default();
+        
+        // A person types "99", then the reader scans a badge
+        assert_eq!(detector.key("9", 0), None);
+        assert_eq!(detector.key("9", 150), None);
+        let scans: Vec<_> = ["5", "5", "1", "2", "Enter"]
+            .iter()
+            .enumerate()
+            .filter_map(|(i, key)| detector.key(key, 2000 + i as u64 * 6))
+            .collect();
+            
+        assert_eq!(scans, ["5512"]);
+    }
+
+    #[test]
+    fn slow_enter_after_burst_is_not_a_scan() {
+        let mut detector = ScanDetector::default();
+        
+        for (i, key) in ["1", "2", "3", "4"].iter().enumerate() {
+            assert_eq!(detector.key(key, i as u64 * 5), None);
+        }
+        
+        assert_eq!(detector.key("Enter", 500), None);
+    }
+
+    #[test]
+    fn shift_does_not_break_a_burst() {
+        let mut detector = ScanDetector::default();
+        let keys = ["Shift", "A", "B", "Shift", "C", "1", "2", "Enter"];
+        
+        assert_eq!(feed(&mut detector, &keys, 4), ["ABC12"]);
+    }
+
+    #[test]
+    fn other_keys_discard_the_burst() {
+        let mut detector = ScanDetector::default();
+        let keys = ["1", "2", "3", "Backspace", "4", "Enter"];
+        
+        assert!(feed(&mut detector, &keys, 4).is_empty());
+    }
+
+    #[test]
+    fn consecutive_scans_are_each_detected() {
+        let mut detector = ScanDetector::default();
+        let mut keys = badge_keys("1111");
+        keys.extend(badge_keys("2222"));
+        
+        assert_eq!(feed(&mut detector, &as_refs(&keys), 3), ["1111", "2222"]);
+    }
+
+    #[test]
+    fn first_key_after_enter_starts_fresh() {
+        let mut detector = ScanDetector::default();
+        feed(&mut detector, &as_refs(&badge_keys("1111")), 3);
+        
+        // Typing right after a scan does not extend it
+        assert_eq!(detector.key("Enter", 1020), None);
+    }
+}
@@ -0,0 +1,2053 @@
+// src/storage/mysql.rs
+
+use mysql::*;
+use mysql::prelude::*;
+use std::result::Result;
+use std::time::Duration;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+
+use crate::database::{
+    check_attendance_sequence, check_attendance_transition, AttendanceCorrection, AttendanceError, AttendanceEvent,
+    AttendanceEventType, AttendanceException, CorrectionError, CorrectionStatus, Leave, LeaveAccrual, LeaveError,
+    LeaveStatus, LeaveType, NewAttendanceCorrection, NewAttendanceException, NewLeave, NewUser, PayPeriodError, PeriodLock, Punch,
+    ScheduleAssignment, ScheduleAssignmentRequest, Shift, ShiftRequest, UpdateUserRequest, User, UserQuery,
+    UserStatus,
+};
+use crate::lockout::ThrottleScope;
+use crate::migrations::{self, AppliedMigration, MigrationError};
+use crate::offline_cache::OfflineUser;
+use super::{
+    correction_kind, parse_correction_status, parse_event_type, parse_exception_kind, parse_leave_status,
+    parse_leave_type, parse_pattern, parse_role, parse_status, pattern_columns, user_order_by, Storage, StorageError, CURRENT_EVENT_FILTER, USER_SEARCH_FILTER,
+};
+
+impl From<mysql::Error> for StorageError {
+    fn from(e: mysql::Error) -> Self {
+        match e {
+            mysql::Error::IoError(_)
+            | mysql::Error::DriverError(
+                DriverError::ConnectTimeout | DriverError::CouldNotConnect(_) | DriverError::Timeout,
+            ) => StorageError::Unavailable(e.to_string()),
+            mysql::Error::MySqlError(ref err) if err.code == DUPLICATE_KEY_ERROR => {
+                StorageError::Duplicate(e.to_string())
+            }
+            mysql::Error::MySqlError(ref err) if ROW_IS_REFERENCED_ERRORS.contains(&err.code) => {
+                StorageError::Referenced(e.to_string())
+            }
+            _ => StorageError::Backend(e.to_string()),
+        }
+    }
+}
+
+impl From<mysql::Error> for AttendanceError {
+    fn from(e: mysql::Error) -> Self {
+        AttendanceError::Database(e.into())
+    }
+}
+
+impl From<mysql::Error> for CorrectionError {
+    fn from(e: mysql::Error) -> Self {
+        CorrectionError::Database(e.into())
+    }
+}
+
+impl From<mysql::Error> for LeaveError {
+    fn from(e: mysql::Error) -> Self {
+        LeaveError::Database(e.into())
+    }
+}
+
+impl From<mysql::Error> for PayPeriodError {
+    fn from(e: mysql::Error) -> Self {
+        PayPeriodError::Database(e.into())
+    }
+}
+
+impl From<mysql::Error> for MigrationError {
+    fn from(e: mysql::Error) -> Self {
+        MigrationError::Database(e.into())
+    }
+}
+
+// ER_DUP_ENTRY
+const DUPLICATE_KEY_ERROR: u16 = 1062;
+// ER_ROW_IS_REFERENCED and ER_ROW_IS_REFERENCED_2
+const ROW_IS_REFERENCED_ERRORS: [u16; 2] = [1217, 1451];
+
+const MIGRATION_LOCK_NAME: &str = "attendance_logger_schema_migrations";
+const MIGRATION_LOCK_TIMEOUT_SECS: u32 = 60;
+const PERIOD_LOCK_NAME: &str = "attendance_logger_pay_period_locks";
+const PERIOD_LOCK_TIMEOUT_SECS: u32 = 10;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Columns read into `UserRow`, for queries that alias users as `u`
+const USER_COLUMNS: &str =
+    "u.id, u.username, u.email, u.full_name, u.role, u.status, u.must_change_password, u.created_at";
+
+type UserRow = (
+    i32,
+    String,
+    Option<String>,
+    Option<String>,
+    String,
+    String,
+    bool,
+    Option<NaiveDateTime>,
+);
+
+fn user_from_row(
+    (id, username, email, full_name, role, status, must_change_password, created_at): UserRow,
+) -> User {
+    User {
+        id,
+        username,
+        email,
+        full_name,
+        role: parse_role(&role),
+        status: parse_status(&status),
+        must_change_password,
+        created_at,
+    }
+}
+
+// A user row followed by one extra column
+type UserRowWith<T> = (
+    i32,
+    String,
+    Option<String>,
+    Option<String>,
+    String,
+    String,
+    bool,
+    Option<NaiveDateTime>,
+    T,
+);
+
+fn split_user_row<T>(
+    (id, username, email, full_name, role, status, must_change_password, created_at, extra): UserRowWith<T>,
+) -> (User, T) {
+    let row = (id, username, email, full_name, role, status, must_change_password, created_at);
+    (user_from_row(row), extra)
+}
+
+// A user with their latest punch type, badge hash and PIN hash
+type OfflineUserRow = (
+    i32,
+    String,
+    Option<String>,
+    Option<String>,
+    String,
+    String,
+    bool,
+    Option<NaiveDateTime>,
+    Option<String>,
+    Option<String>,
+    Option<String>,
+);
+
+const SHIFT_COLUMNS: &str = "id, name, start_time, end_time, split_start_time, split_end_time";
+
+type ShiftRow = (i32, String, NaiveTime, NaiveTime, Option<NaiveTime>, Option<NaiveTime>);
+
+fn shift_from_row((id, name, start_time, end_time, split_start_time, split_end_time): ShiftRow) -> Shift {
+    Shift {
+        id,
+        name,
+        start_time,
+        end_time,
+        split_start_time,
+        split_end_time,
+    }
+}
+
+const ASSIGNMENT_COLUMNS: &str =
+    "id, user_id, shift_id, pattern, weekdays, days_on, days_off, starts_on, ends_on";
+
+type AssignmentRow = (
+    i32,
+    i32,
+    i32,
+    String,
+    Option<u8>,
+    Option<u32>,
+    Option<u32>,
+    NaiveDate,
+    Option<NaiveDate>,
+);
+
+fn assignment_from_row(
+    (id, user_id, shift_id, pattern, weekdays, days_on, days_off, starts_on, ends_on): AssignmentRow,
+) -> ScheduleAssignment {
+    ScheduleAssignment {
+        id,
+        user_id,
+        shift_id,
+        pattern: parse_pattern(&pattern, weekdays, days_on, days_off),
+        starts_on,
+        ends_on,
+    }
+}
+
+const EXCEPTION_COLUMNS: &str =
+    "id, user_id, work_date, kind, minutes, detected_at, reviewed_by, reviewed_at, review_note";
+
+type ExceptionRow = (
+    i32,
+    i32,
+    NaiveDate,
+    String,
+    Option<i64>,
+    NaiveDateTime,
+    Option<i32>,
+    Option<NaiveDateTime>,
+    Option<String>,
+);
+
+fn exception_from_row(
+    (id, user_id, work_date, kind, minutes, detected_at, reviewed_by, reviewed_at, review_note): ExceptionRow,
+) -> AttendanceException {
+    AttendanceException {
+        id,
+        user_id,
+        work_date,
+        kind: parse_exception_kind(&kind),
+        minutes,
+        detected_at,
+        reviewed_by,
+        reviewed_at,
+        review_note,
+    }
+}
+
+// For queries that alias attendance_events as `e`
+const EVENT_COLUMNS: &str =
+    "e.id, e.user_id, e.event_type, e.event_time, e.idempotency_key, e.corrects_event_id, e.correction_id";
+
+type EventRow = (u64, i32, String, NaiveDateTime, Option<String>, Option<u64>, Option<i32>);
+
+fn event_from_row(
+    (id, user_id, event_type, event_time, idempotency_key, corrects_event_id, correction_id): EventRow,
+) -> AttendanceEvent {
+    AttendanceEvent {
+        id,
+        user_id,
+        event_type: parse_event_type(&event_type),
+        event_time,
+        idempotency_key,
+        corrects_event_id,
+        correction_id,
+        queued: false,
+    }
+}
+
+// For queries that alias attendance_corrections as `c`
+const CORRECTION_COLUMNS: &str = "c.id, c.user_id, c.event_id, c.event_type, c.event_time, c.reason, c.status, \
+    c.requested_at, c.decided_by, c.decided_at, c.decision_note, c.adjusted_event_id";
+
+type CorrectionRow = (
+    i32,
+    i32,
+    Option<u64>,
+    String,
+    NaiveDateTime,
+    String,
+    String,
+    NaiveDateTime,
+    Option<i32>,
+    Option<NaiveDateTime>,
+    Option<String>,
+    Option<u64>,
+);
+
+fn correction_from_row(
+    (
+        id,
+        user_id,
+        event_id,
+        event_type,
+        event_time,
+        reason,
+        status,
+        requested_at,
+        decided_by,
+        decided_at,
+        decision_note,
+        adjusted_event_id,
+    ): CorrectionRow,
+) -> AttendanceCorrection {
+    AttendanceCorrection {
+        id,
+        user_id,
+        kind: correction_kind(event_id),
+        event_id,
+        event_type: parse_event_type(&event_type),
+        event_time,
+        reason,
+        status: parse_correction_status(&status),
+        requested_at,
+        decided_by,
+        decided_at,
+        decision_note,
+        adjusted_event_id,
+    }
+}
+
+const ACCRUAL_COLUMNS: &str = "id, user_id, leave_type, days, note, created_by, created_at";
+
+type AccrualRow = (i32, i32, String, f64, Option<String>, i32, NaiveDateTime);
+
+fn accrual_from_row((id, user_id, leave_type, days, note, created_by, created_at): AccrualRow) -> LeaveAccrual {
+    LeaveAccrual {
+        id,
+        user_id,
+        leave_type: parse_leave_type(&leave_type),
+        days,
+        note,
+        created_by,
+        created_at,
+    }
+}
+
+// For queries that alias leave_requests as `l`
+const LEAVE_COLUMNS: &str = "l.id, l.user_id, l.leave_type, l.starts_on, l.ends_on, l.days, l.reason, l.status, \
+    l.requested_at, l.decided_by, l.decided_at, l.decision_note";
+
+type LeaveRow = (
+    i32,
+    i32,
+    String,
+    NaiveDate,
+    NaiveDate,
+    f64,
+    Option<String>,
+    String,
+    NaiveDateTime,
+    Option<i32>,
+    Option<NaiveDateTime>,
+    Option<String>,
+);
+
+fn leave_from_row(
+    (
+        id,
+        user_id,
+        leave_type,
+        starts_on,
+        ends_on,
+        days,
+        reason,
+        status,
+        requested_at,
+        decided_by,
+        decided_at,
+        decision_note,
+    ): LeaveRow,
+) -> Leave {
+    Leave {
+        id,
+        user_id,
+        leave_type: parse_leave_type(&leave_type),
+        starts_on,
+        ends_on,
+        days,
+        reason,
+        status: parse_leave_status(&status),
+        requested_at,
+        decided_by,
+        decided_at,
+        decision_note,
+    }
+}
+
+const PERIOD_LOCK_COLUMNS: &str =
+    "id, starts_on, ends_on, locked_by, locked_at, unlocked_by, unlocked_at, unlock_reason";
+
+type PeriodLockRow = (
+    i32,
+    NaiveDate,
+    NaiveDate,
+    i32,
+    NaiveDateTime,
+    Option<i32>,
+    Option<NaiveDateTime>,
+    Option<String>,
+);
+
+fn period_lock_from_row(
+    (id, starts_on, ends_on, locked_by, locked_at, unlocked_by, unlocked_at, unlock_reason): PeriodLockRow,
+) -> PeriodLock {
+    PeriodLock {
+        id,
+        starts_on,
+        ends_on,
+        locked_by,
+        locked_at,
+        unlocked_by,
+        unlocked_at,
+        unlock_reason,
+    }
+}
+
+// Run inside the transaction that stores a leave request (`leave_id` None)
+// or approves one, with the user's row locked, so concurrent requests
+// cannot overlap or overdraw
+fn check_leave(
+    tx: &mut Transaction,
+    user_id: i32,
+    leave_type: LeaveType,
+    starts_on: NaiveDate,
+    ends_on: NaiveDate,
+    days: f64,
+    leave_id: Option<i32>,
+) -> Result<(), LeaveError> {
+    let overlapping: Option<(NaiveDate, NaiveDate)> = tx
+        .exec_first(
+            "SELECT starts_on, ends_on FROM leave_requests
+            WHERE user_id = :user_id AND status <> 'rejected'
+            AND (:leave_id IS NULL OR id <> :leave_id)
+            AND starts_on <= :ends_on AND ends_on >= :starts_on
+            ORDER BY starts_on
+            LIMIT 1",
+            params! {
+                "user_id" => user_id,
+                "leave_id" => leave_id,
+                "starts_on" => starts_on,
+                "ends_on" => ends_on,
+            }
+        )?;
+
+    if let Some((starts_on, ends_on)) = overlapping {
+        return Err(LeaveError::Overlaps { starts_on, ends_on });
+    }
+
+    if !leave_type.is_accrued() {
+        return Ok(());
+    }
+
+    let accrued: Option<f64> = tx
+        .exec_first(
+            "SELECT COALESCE(SUM(days), 0) FROM leave_accruals
+            WHERE user_id = :user_id AND leave_type = :leave_type",
+            params! {
+                "user_id" => user_id,
+                "leave_type" => leave_type.as_str(),
+            }
+        )?;
+    // A new request also waits behind pending ones; an approval only
+    // behind approved leave
+    let taken: Option<f64> = tx
+        .exec_first(
+            "SELECT COALESCE(SUM(days), 0) FROM leave_requests
+            WHERE user_id = :user_id AND leave_type = :leave_type
+            AND (status = 'approved' OR (status = 'pending' AND :leave_id IS NULL))",
+            params! {
+                "user_id" => user_id,
+                "leave_type" => leave_type.as_str(),
+                "leave_id" => leave_id,
+            }
+        )?;
+
+    let available = accrued.unwrap_or_default() - taken.unwrap_or_default();
+    if available < days {
+        return Err(LeaveError::InsufficientBalance {
+            leave_type,
+            available,
+            requested: days,
+        });
+    }
+
+    Ok(())
+}
+
+// Run inside the approval's transaction, after the adjusted event is
+// written and the punch it replaces is hidden
+fn check_corrected_sequence(tx: &mut Transaction, correction_id: i32) -> Result<(), CorrectionError> {
+    let correction: Option<(i32, NaiveDateTime, Option<NaiveDateTime>)> = tx
+        .exec_first(
+            "SELECT c.user_id, c.event_time, o.event_time FROM attendance_corrections c
+            LEFT JOIN attendance_events o ON o.id = c.event_id
+            WHERE c.id = :correction_id",
+            params! {
+                "correction_id" => correction_id,
+            }
+        )?;
+    let (user_id, event_time, original_time) = correction.ok_or(CorrectionError::CorrectionNotFound)?;
+    let from = original_time.map_or(event_time, |original_time| original_time.min(event_time));
+
+    let last_event: Option<String> = tx
+        .exec_first(
+            format!(
+                "SELECT e.event_type FROM attendance_events e
+                WHERE e.user_id = :user_id AND e.event_time < :from AND {}
+                ORDER BY e.event_time DESC, e.id DESC
+                LIMIT 1",
+                CURRENT_EVENT_FILTER
+            ),
+            params! {
+                "user_id" => user_id,
+                "from" => from,
+            }
+        )?;
+
+    let events = tx
+        .exec_map(
+            format!(
+                "SELECT e.event_type FROM attendance_events e
+                WHERE e.user_id = :user_id AND e.event_time >= :from AND {}
+                ORDER BY e.event_time, e.id",
+                CURRENT_EVENT_FILTER
+            ),
+            params! {
+                "user_id" => user_id,
+                "from" => from,
+            },
+            |event_type: String| parse_event_type(&event_type),
+        )?;
+
+    check_attendance_sequence(last_event.as_deref().map(parse_event_type), events)
+        .map_err(CorrectionError::InvalidSequence)
+}
+
+pub struct MySqlStorage {
+    pool: Pool,
+}
+
+impl MySqlStorage {
+    pub fn connect(url: &str) -> Result<Self, StorageError> {
+        // Connections are opened lazily so an unreachable server does not
+        // stop the app from starting; punches are queued until it returns
+        let opts = OptsBuilder::from_opts(Opts::from_url(url).map_err(mysql::Error::from)?)
+            .pool_opts(PoolOpts::default().with_constraints(PoolConstraints::new_const::<0, 10>()))
+            .tcp_connect_timeout(Some(CONNECT_TIMEOUT));
+        let pool = Pool::new(opts)?;
+
+        Ok(MySqlStorage { pool })
+    }
+
+    fn apply_pending(conn: &mut PooledConn) -> Result<Vec<u32>, MigrationError> {
+        let applied: Vec<AppliedMigration> = conn.query_map(
+            "SELECT version, name, checksum FROM schema_migrations ORDER BY version",
+            |(version, name, checksum)| AppliedMigration { version, name, checksum },
+        )?;
+
+        let pending = migrations::pending(&applied, migrations::MYSQL)?;
+        if pending.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // MySQL commits DDL implicitly, so a transaction could not undo a
+        // failed migration. Each one is recorded as soon as it has run, so a
+        // failure stops at the migration that needs attention.
+        let mut versions = Vec::with_capacity(pending.len());
+
+        for migration in pending {
+            conn.query_drop(migration.sql).map_err(|e| migration.failed(e))?;
+            conn.exec_drop(
+                "INSERT INTO schema_migrations (version, name, checksum)
+                VALUES (:version, :name, :checksum)",
+                params! {
+                    "version" => migration.version,
+                    "name" => migration.name,
+                    "checksum" => migration.checksum(),
+                }
+            )?;
+            versions.push(migration.version);
+        }
+
+        Ok(versions)
+    }
+
+    // Runs while holding PERIOD_LOCK_NAME
+    fn insert_period_lock_in_turn(
+        conn: &mut PooledConn,
+        starts_on: NaiveDate,
+        ends_on: NaiveDate,
+        locked_by: i32,
+        now: NaiveDateTime,
+    ) -> Result<i32, PayPeriodError> {
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+
+        let overlapping: Option<(NaiveDate, NaiveDate)> = tx.exec_first(
+            "SELECT starts_on, ends_on FROM pay_period_locks
+            WHERE unlocked_at IS NULL AND starts_on <= :ends_on AND ends_on >= :starts_on
+            ORDER BY starts_on
+            LIMIT 1",
+            params! {
+                "starts_on" => starts_on,
+                "ends_on" => ends_on,
+            }
+        )?;
+
+        if let Some((starts_on, ends_on)) = overlapping {
+            return Err(PayPeriodError::AlreadyLocked { starts_on, ends_on });
+        }
+
+        tx.exec_drop(
+            "INSERT INTO pay_period_locks (starts_on, ends_on, locked_by, locked_at)
+            VALUES (:starts_on, :ends_on, :locked_by, :now)",
+            params! {
+                "starts_on" => starts_on,
+                "ends_on" => ends_on,
+                "locked_by" => locked_by,
+                "now" => now,
+            }
+        )?;
+
+        let lock_id = tx.last_insert_id().unwrap_or_default() as i32;
+        tx.commit()?;
+
+        Ok(lock_id)
+    }
+}
+
+impl Storage for MySqlStorage {
+    fn migrate(&self) -> Result<Vec<u32>, MigrationError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INT UNSIGNED PRIMARY KEY,
+                name VARCHAR(255) NOT NULL,
+                checksum CHAR(64) NOT NULL,
+                applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+            )"
+        )?;
+
+        // Several kiosks may start at once against the same server
+        let locked: Option<Option<i32>> = conn.exec_first(
+            "SELECT GET_LOCK(:name, :timeout)",
+            params! {
+                "name" => MIGRATION_LOCK_NAME,
+                "timeout" => MIGRATION_LOCK_TIMEOUT_SECS,
+            }
+        )?;
+        if locked.flatten() != Some(1) {
+            return Err(MigrationError::LockTimeout);
+        }
+
+        let result = Self::apply_pending(&mut conn);
+
+        conn.exec_drop(
+            "DO RELEASE_LOCK(:name)",
+            params! {
+                "name" => MIGRATION_LOCK_NAME,
+            }
+        )?;
+
+        result
+    }
+
+    fn name(&self) -> &'static str {
+        "mysql"
+    }
+
+    fn is_embedded(&self) -> bool {
+        false
+    }
+
+    fn count_users(&self) -> Result<u64, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let count: Option<u64> = conn.query_first("SELECT COUNT(*) FROM users")?;
+
+        Ok(count.unwrap_or(0))
+    }
+
+    fn insert_user(&self, user: &NewUser) -> Result<i32, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "INSERT INTO users
+            (username, password_hash, email, full_name, role, supervisor_id)
+            VALUES (:username, :password_hash, :email, :full_name, :role, :supervisor_id)",
+            params! {
+                "username" => &user.username,
+                "password_hash" => &user.password_hash,
+                "email" => &user.email,
+                "full_name" => &user.full_name,
+                "role" => user.role.as_str(),
+                "supervisor_id" => user.supervisor_id,
+            }
+        )?;
+
+        Ok(conn.last_insert_id() as i32)
+    }
+
+    fn list_users(&self) -> Result<Vec<User>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let users = conn
+            .query_map(
+                format!("SELECT {} FROM users u ORDER BY u.created_at DESC, u.id DESC", USER_COLUMNS),
+                user_from_row
+            )?;
+
+        Ok(users)
+    }
+
+    fn query_users(&self, query: &UserQuery) -> Result<(Vec<User>, u64), StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let total: Option<u64> = conn
+            .exec_first(
+                format!("SELECT COUNT(*) FROM users u WHERE {}", USER_SEARCH_FILTER),
+                params! {
+                    "pattern" => &query.search_pattern,
+                }
+            )?;
+
+        let users = conn
+            .exec_map(
+                format!(
+                    "SELECT {} FROM users u WHERE {} ORDER BY {} LIMIT :limit OFFSET :offset",
+                    USER_COLUMNS,
+                    USER_SEARCH_FILTER,
+                    user_order_by(query)
+                ),
+                params! {
+                    "pattern" => &query.search_pattern,
+                    "limit" => query.limit,
+                    "offset" => query.offset,
+                },
+                user_from_row
+            )?;
+
+        Ok((users, total.unwrap_or(0)))
+    }
+
+    fn find_user(&self, user_id: i32) -> Result<Option<User>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let row: Option<UserRow> = conn
+            .exec_first(
+                format!("SELECT {} FROM users u WHERE u.id = :user_id", USER_COLUMNS),
+                params! {
+                    "user_id" => user_id,
+                }
+            )?;
+
+        Ok(row.map(user_from_row))
+    }
+
+    fn update_user(&self, user_id: i32, update: &UpdateUserRequest) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        // Matched rather than changed rows, so an unchanged save still counts
+        let found: Option<i32> = conn
+            .exec_first(
+                "SELECT id FROM users WHERE id = :user_id",
+                params! {
+                    "user_id" => user_id,
+                }
+            )?;
+
+        conn.exec_drop(
+            "UPDATE users SET email = :email, full_name = :full_name, role = :role
+            WHERE id = :user_id",
+            params! {
+                "user_id" => user_id,
+                "email" => &update.email,
+                "full_name" => &update.full_name,
+                "role" => update.role.as_str(),
+            }
+        )?;
+
+        Ok(found.is_some())
+    }
+
+    fn set_user_status(&self, user_id: i32, status: UserStatus) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let found: Option<i32> = conn
+            .exec_first(
+                "SELECT id FROM users WHERE id = :user_id",
+                params! {
+                    "user_id" => user_id,
+                }
+            )?;
+
+        conn.exec_drop(
+            "UPDATE users SET status = :status WHERE id = :user_id",
+            params! {
+                "user_id" => user_id,
+                "status" => status.as_str(),
+            }
+        )?;
+
+        Ok(found.is_some())
+    }
+
+    fn delete_user(&self, user_id: i32) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+
+        tx.exec_drop(
+            "DELETE FROM sessions WHERE user_id = :user_id",
+            params! {
+                "user_id" => user_id,
+            }
+        )?;
+        tx.exec_drop(
+            "DELETE FROM qr_redemptions WHERE user_id = :user_id",
+            params! {
+                "user_id" => user_id,
+            }
+        )?;
+        tx.exec_drop(
+            "DELETE FROM schedule_assignments WHERE user_id = :user_id",
+            params! {
+                "user_id" => user_id,
+            }
+        )?;
+        tx.exec_drop(
+            "DELETE FROM attendance_exceptions WHERE user_id = :user_id AND reviewed_at IS NULL",
+            params! {
+                "user_id" => user_id,
+            }
+        )?;
+        tx.exec_drop(
+            "UPDATE users SET supervisor_id = NULL WHERE supervisor_id = :user_id",
+            params! {
+                "user_id" => user_id,
+            }
+        )?;
+        tx.exec_drop(
+            "DELETE FROM users WHERE id = :user_id",
+            params! {
+                "user_id" => user_id,
+            }
+        )?;
+        let deleted = tx.affected_rows();
+
+        tx.commit()?;
+
+        Ok(deleted > 0)
+    }
+
+    fn find_user_credentials(&self, username: &str) -> Result<Option<(User, String)>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let row = conn
+            .exec_first(
+                format!("SELECT {}, u.password_hash FROM users u WHERE u.username = :username", USER_COLUMNS),
+                params! {
+                    "username" => username,
+                }
+            )?;
+
+        Ok(row.map(split_user_row::<String>))
+    }
+
+    fn update_password_hash(&self, user_id: i32, password_hash: &str) -> Result<(), StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "UPDATE users SET password_hash = :password_hash WHERE id = :user_id",
+            params! {
+                "user_id" => user_id,
+                "password_hash" => password_hash,
+            }
+        )?;
+
+        Ok(())
+    }
+
+    fn set_password(&self, user_id: i32, password_hash: &str, must_change: bool) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        // As in update_user, a match counts even when nothing changes
+        let found: Option<i32> = conn
+            .exec_first(
+                "SELECT id FROM users WHERE id = :user_id",
+                params! {
+                    "user_id" => user_id,
+                }
+            )?;
+
+        conn.exec_drop(
+            "UPDATE users SET password_hash = :password_hash, must_change_password = :must_change
+            WHERE id = :user_id",
+            params! {
+                "user_id" => user_id,
+                "password_hash" => password_hash,
+                "must_change" => must_change,
+            }
+        )?;
+
+        Ok(found.is_some())
+    }
+
+    fn set_kiosk_credentials(
+        &self,
+        user_id: i32,
+        badge_hash: Option<&str>,
+        pin_hash: Option<&str>,
+    ) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        // As in update_user, a match counts even when nothing changes
+        let found: Option<i32> = conn
+            .exec_first(
+                "SELECT id FROM users WHERE id = :user_id",
+                params! {
+                    "user_id" => user_id,
+                }
+            )?;
+
+        conn.exec_drop(
+            "UPDATE users SET badge_hash = :badge_hash, pin_hash = :pin_hash WHERE id = :user_id",
+            params! {
+                "user_id" => user_id,
+                "badge_hash" => badge_hash,
+                "pin_hash" => pin_hash,
+            }
+        )?;
+
+        Ok(found.is_some())
+    }
+
+    fn find_kiosk_credentials(&self, badge_hash: &str) -> Result<Option<(User, Option<String>)>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let row = conn
+            .exec_first(
+                format!("SELECT {}, u.pin_hash FROM users u WHERE u.badge_hash = :badge_hash", USER_COLUMNS),
+                params! {
+                    "badge_hash" => badge_hash,
+                }
+            )?;
+
+        Ok(row.map(split_user_row::<Option<String>>))
+    }
+
+    fn list_offline_users(&self) -> Result<Vec<OfflineUser>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let users = conn.query_map(
+            format!(
+                "SELECT {}, (
+                    SELECT e.event_type FROM attendance_events e
+                    WHERE e.user_id = u.id AND {}
+                    ORDER BY e.event_time DESC, e.id DESC
+                    LIMIT 1
+                ), u.badge_hash, u.pin_hash
+                FROM users u
+                WHERE u.status = 'active'",
+                USER_COLUMNS, CURRENT_EVENT_FILTER
+            ),
+            |(id, username, email, full_name, role, status, must_change_password, created_at, last_event_type, badge_hash, pin_hash): OfflineUserRow| {
+                let row = (id, username, email, full_name, role, status, must_change_password, created_at);
+                OfflineUser {
+                    user: user_from_row(row),
+                    last_event_type: last_event_type.as_deref().map(parse_event_type),
+                    badge_hash,
+                    pin_hash,
+                }
+            },
+        )?;
+
+        Ok(users)
+    }
+
+    fn redeem_qr_code(
+        &self,
+        user_id: i32,
+        window: i64,
+        oldest_window: i64,
+        now: NaiveDateTime,
+    ) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "DELETE FROM qr_redemptions WHERE time_window < :oldest_window",
+            params! {
+                "oldest_window" => oldest_window,
+            }
+        )?;
+        let inserted = conn.exec_drop(
+            "INSERT INTO qr_redemptions (user_id, time_window, redeemed_at)
+            VALUES (:user_id, :window, :now)",
+            params! {
+                "user_id" => user_id,
+                "window" => window,
+                "now" => now,
+            }
+        );
+
+        match inserted.map_err(StorageError::from) {
+            Ok(()) => Ok(true),
+            Err(StorageError::Duplicate(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn is_supervisor_of(&self, supervisor_id: i32, user_id: i32) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let found: Option<i32> = conn
+            .exec_first(
+                "SELECT id FROM users WHERE id = :user_id AND supervisor_id = :supervisor_id",
+                params! {
+                    "user_id" => user_id,
+                    "supervisor_id" => supervisor_id,
+                }
+            )?;
+
+        Ok(found.is_some())
+    }
+
+    fn insert_session(
+        &self,
+        token_hash: &str,
+        user_id: i32,
+        created_at: NaiveDateTime,
+        expires_at: NaiveDateTime,
+    ) -> Result<(), StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "INSERT INTO sessions (token_hash, user_id, created_at, expires_at)
+            VALUES (:token_hash, :user_id, :created_at, :expires_at)",
+            params! {
+                "token_hash" => token_hash,
+                "user_id" => user_id,
+                "created_at" => created_at,
+                "expires_at" => expires_at,
+            }
+        )?;
+
+        Ok(())
+    }
+
+    fn find_session_user(
+        &self,
+        token_hash: &str,
+        now: NaiveDateTime,
+    ) -> Result<Option<(User, NaiveDateTime)>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let row = conn
+            .exec_first(
+                format!(
+                    "SELECT {}, s.expires_at FROM sessions s
+                    JOIN users u ON u.id = s.user_id
+                    WHERE s.token_hash = :token_hash
+                    AND s.revoked_at IS NULL
+                    AND s.expires_at > :now
+                    AND u.status = 'active'",
+                    USER_COLUMNS
+                ),
+                params! {
+                    "token_hash" => token_hash,
+                    "now" => now,
+                }
+            )?;
+
+        Ok(row.map(split_user_row::<NaiveDateTime>))
+    }
+
+    fn revoke_session(&self, token_hash: &str, now: NaiveDateTime) -> Result<(), StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "UPDATE sessions SET revoked_at = :now
+            WHERE token_hash = :token_hash AND revoked_at IS NULL",
+            params! {
+                "token_hash" => token_hash,
+                "now" => now,
+            }
+        )?;
+
+        Ok(())
+    }
+
+    fn revoke_user_sessions(&self, user_id: i32, now: NaiveDateTime) -> Result<(), StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "UPDATE sessions SET revoked_at = :now
+            WHERE user_id = :user_id AND revoked_at IS NULL",
+            params! {
+                "user_id" => user_id,
+                "now" => now,
+            }
+        )?;
+
+        Ok(())
+    }
+
+    fn record_login_failure(
+        &self,
+        scope: ThrottleScope,
+        subject: &str,
+        now: NaiveDateTime,
+        window_start: NaiveDateTime,
+    ) -> Result<u32, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+
+        tx.exec_drop(
+            "INSERT INTO login_throttles (scope, subject, failures, last_failure_at)
+            VALUES (:scope, :subject, 1, :now)
+            ON DUPLICATE KEY UPDATE
+                failures = IF(
+                    last_failure_at < :window_start
+                        AND (locked_until IS NULL OR locked_until < :window_start),
+                    1,
+                    failures + 1
+                ),
+                last_failure_at = :now",
+            params! {
+                "scope" => scope.as_str(),
+                "subject" => subject,
+                "now" => now,
+                "window_start" => window_start,
+            }
+        )?;
+
+        let failures: Option<u32> = tx
+            .exec_first(
+                "SELECT failures FROM login_throttles WHERE scope = :scope AND subject = :subject",
+                params! {
+                    "scope" => scope.as_str(),
+                    "subject" => subject,
+                }
+            )?;
+
+        tx.commit()?;
+
+        Ok(failures.unwrap_or(1))
+    }
+
+    fn lock_login(&self, scope: ThrottleScope, subject: &str, until: NaiveDateTime) -> Result<(), StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "UPDATE login_throttles SET locked_until = :until
+            WHERE scope = :scope AND subject = :subject",
+            params! {
+                "scope" => scope.as_str(),
+                "subject" => subject,
+                "until" => until,
+            }
+        )?;
+
+        Ok(())
+    }
+
+    fn login_locked_until(&self, scope: ThrottleScope, subject: &str) -> Result<Option<NaiveDateTime>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let until: Option<Option<NaiveDateTime>> = conn
+            .exec_first(
+                "SELECT locked_until FROM login_throttles WHERE scope = :scope AND subject = :subject",
+                params! {
+                    "scope" => scope.as_str(),
+                    "subject" => subject,
+                }
+            )?;
+
+        Ok(until.flatten())
+    }
+
+    fn clear_login_failures(&self, scope: ThrottleScope, subject: &str) -> Result<(), StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "DELETE FROM login_throttles WHERE scope = :scope AND subject = :subject",
+            params! {
+                "scope" => scope.as_str(),
+                "subject" => subject,
+            }
+        )?;
+
+        Ok(())
+    }
+
+    fn insert_shift(&self, shift: &ShiftRequest) -> Result<i32, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "INSERT INTO shifts (name, start_time, end_time, split_start_time, split_end_time)
+            VALUES (:name, :start_time, :end_time, :split_start_time, :split_end_time)",
+            params! {
+                "name" => &shift.name,
+                "start_time" => shift.start_time,
+                "end_time" => shift.end_time,
+                "split_start_time" => shift.split_start_time,
+                "split_end_time" => shift.split_end_time,
+            }
+        )?;
+
+        Ok(conn.last_insert_id() as i32)
+    }
+
+    fn list_shifts(&self) -> Result<Vec<Shift>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let shifts = conn
+            .query_map(
+                format!("SELECT {} FROM shifts ORDER BY name, id", SHIFT_COLUMNS),
+                shift_from_row
+            )?;
+
+        Ok(shifts)
+    }
+
+    fn find_shift(&self, shift_id: i32) -> Result<Option<Shift>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let row: Option<ShiftRow> = conn
+            .exec_first(
+                format!("SELECT {} FROM shifts WHERE id = :shift_id", SHIFT_COLUMNS),
+                params! {
+                    "shift_id" => shift_id,
+                }
+            )?;
+
+        Ok(row.map(shift_from_row))
+    }
+
+    fn update_shift(&self, shift_id: i32, shift: &ShiftRequest) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        // As in update_user, a match counts even when nothing changes
+        let found: Option<i32> = conn
+            .exec_first(
+                "SELECT id FROM shifts WHERE id = :shift_id",
+                params! {
+                    "shift_id" => shift_id,
+                }
+            )?;
+
+        conn.exec_drop(
+            "UPDATE shifts SET name = :name, start_time = :start_time, end_time = :end_time,
+                split_start_time = :split_start_time, split_end_time = :split_end_time
+            WHERE id = :shift_id",
+            params! {
+                "shift_id" => shift_id,
+                "name" => &shift.name,
+                "start_time" => shift.start_time,
+                "end_time" => shift.end_time,
+                "split_start_time" => shift.split_start_time,
+                "split_end_time" => shift.split_end_time,
+            }
+        )?;
+
+        Ok(found.is_some())
+    }
+
+    fn delete_shift(&self, shift_id: i32) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "DELETE FROM shifts WHERE id = :shift_id",
+            params! {
+                "shift_id" => shift_id,
+            }
+        )?;
+
+        Ok(conn.affected_rows() > 0)
+    }
+
+    fn insert_schedule_assignment(&self, assignment: &ScheduleAssignmentRequest) -> Result<i32, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+        let pattern = pattern_columns(&assignment.pattern);
+
+        conn.exec_drop(
+            "INSERT INTO schedule_assignments
+            (user_id, shift_id, pattern, weekdays, days_on, days_off, starts_on, ends_on)
+            VALUES (:user_id, :shift_id, :pattern, :weekdays, :days_on, :days_off, :starts_on, :ends_on)",
+            params! {
+                "user_id" => assignment.user_id,
+                "shift_id" => assignment.shift_id,
+                "pattern" => pattern.kind,
+                "weekdays" => pattern.weekdays,
+                "days_on" => pattern.days_on,
+                "days_off" => pattern.days_off,
+                "starts_on" => assignment.starts_on,
+                "ends_on" => assignment.ends_on,
+            }
+        )?;
+
+        Ok(conn.last_insert_id() as i32)
+    }
+
+    fn find_schedule_assignment(&self, assignment_id: i32) -> Result<Option<ScheduleAssignment>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let row: Option<AssignmentRow> = conn
+            .exec_first(
+                format!("SELECT {} FROM schedule_assignments WHERE id = :assignment_id", ASSIGNMENT_COLUMNS),
+                params! {
+                    "assignment_id" => assignment_id,
+                }
+            )?;
+
+        Ok(row.map(assignment_from_row))
+    }
+
+    fn list_schedule_assignments(
+        &self,
+        user_id: Option<i32>,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<Vec<ScheduleAssignment>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let assignments = conn
+            .exec_map(
+                format!(
+                    "SELECT {} FROM schedule_assignments
+                    WHERE (:user_id IS NULL OR user_id = :user_id)
+                        AND (:to IS NULL OR starts_on <= :to)
+                        AND (:from IS NULL OR ends_on IS NULL OR ends_on >= :from)
+                    ORDER BY user_id, starts_on, id",
+                    ASSIGNMENT_COLUMNS
+                ),
+                params! {
+                    "user_id" => user_id,
+                    "from" => from,
+                    "to" => to,
+                },
+                assignment_from_row
+            )?;
+
+        Ok(assignments)
+    }
+
+    fn update_schedule_assignment(
+        &self,
+        assignment_id: i32,
+        assignment: &ScheduleAssignmentRequest,
+    ) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+        let pattern = pattern_columns(&assignment.pattern);
+
+        // As in update_user, a match counts even when nothing changes
+        let found: Option<i32> = conn
+            .exec_first(
+                "SELECT id FROM schedule_assignments WHERE id = :assignment_id",
+                params! {
+                    "assignment_id" => assignment_id,
+                }
+            )?;
+
+        conn.exec_drop(
+            "UPDATE schedule_assignments SET user_id = :user_id, shift_id = :shift_id, pattern = :pattern,
+                weekdays = :weekdays, days_on = :days_on, days_off = :days_off,
+                starts_on = :starts_on, ends_on = :ends_on
+            WHERE id = :assignment_id",
+            params! {
+                "assignment_id" => assignment_id,
+                "user_id" => assignment.user_id,
+                "shift_id" => assignment.shift_id,
+                "pattern" => pattern.kind,
+                "weekdays" => pattern.weekdays,
+                "days_on" => pattern.days_on,
+                "days_off" => pattern.days_off,
+                "starts_on" => assignment.starts_on,
+                "ends_on" => assignment.ends_on,
+            }
+        )?;
+
+        Ok(found.is_some())
+    }
+
+    fn delete_schedule_assignment(&self, assignment_id: i32) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "DELETE FROM schedule_assignments WHERE id = :assignment_id",
+            params! {
+                "assignment_id" => assignment_id,
+            }
+        )?;
+
+        Ok(conn.affected_rows() > 0)
+    }
+
+    fn replace_attendance_exceptions(
+        &self,
+        user_id: i32,
+        from: NaiveDate,
+        to: NaiveDate,
+        exceptions: &[NewAttendanceException],
+        now: NaiveDateTime,
+    ) -> Result<(), StorageError> {
+        let mut conn = self.pool.get_conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+
+        tx.exec_drop(
+            "DELETE FROM attendance_exceptions
+            WHERE user_id = :user_id AND work_date BETWEEN :from AND :to AND reviewed_at IS NULL",
+            params! {
+                "user_id" => user_id,
+                "from" => from,
+                "to" => to,
+            }
+        )?;
+        tx.exec_batch(
+            "INSERT INTO attendance_exceptions (user_id, work_date, kind, minutes, detected_at)
+            VALUES (:user_id, :work_date, :kind, :minutes, :now)
+            ON DUPLICATE KEY UPDATE id = id",
+            exceptions.iter().map(|exception| params! {
+                "user_id" => user_id,
+                "work_date" => exception.work_date,
+                "kind" => exception.kind.as_str(),
+                "minutes" => exception.minutes,
+                "now" => now,
+            })
+        )?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn list_attendance_exceptions(
+        &self,
+        user_id: Option<i32>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<AttendanceException>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let exceptions = conn
+            .exec_map(
+                format!(
+                    "SELECT {} FROM attendance_exceptions
+                    WHERE (:user_id IS NULL OR user_id = :user_id) AND work_date BETWEEN :from AND :to
+                    ORDER BY work_date, user_id, id",
+                    EXCEPTION_COLUMNS
+                ),
+                params! {
+                    "user_id" => user_id,
+                    "from" => from,
+                    "to" => to,
+                },
+                exception_from_row
+            )?;
+
+        Ok(exceptions)
+    }
+
+    fn find_attendance_exception(&self, exception_id: i32) -> Result<Option<AttendanceException>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let row: Option<ExceptionRow> = conn
+            .exec_first(
+                format!("SELECT {} FROM attendance_exceptions WHERE id = :exception_id", EXCEPTION_COLUMNS),
+                params! {
+                    "exception_id" => exception_id,
+                }
+            )?;
+
+        Ok(row.map(exception_from_row))
+    }
+
+    fn review_attendance_exception(
+        &self,
+        exception_id: i32,
+        reviewer_id: i32,
+        note: Option<&str>,
+        now: NaiveDateTime,
+    ) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        // As in update_user, a match counts even when nothing changes
+        let found: Option<i32> = conn
+            .exec_first(
+                "SELECT id FROM attendance_exceptions WHERE id = :exception_id",
+                params! {
+                    "exception_id" => exception_id,
+                }
+            )?;
+
+        conn.exec_drop(
+            "UPDATE attendance_exceptions SET reviewed_by = :reviewer_id, reviewed_at = :now, review_note = :note
+            WHERE id = :exception_id",
+            params! {
+                "exception_id" => exception_id,
+                "reviewer_id" => reviewer_id,
+                "now" => now,
+                "note" => note,
+            }
+        )?;
+
+        Ok(found.is_some())
+    }
+
+    fn insert_period_lock(
+        &self,
+        starts_on: NaiveDate,
+        ends_on: NaiveDate,
+        locked_by: i32,
+        now: NaiveDateTime,
+    ) -> Result<i32, PayPeriodError> {
+        let mut conn = self.pool.get_conn()?;
+
+        // Overlapping ranges share no row that FOR UPDATE could lock, so lock
+        // attempts take turns on a named lock instead
+        let locked: Option<Option<i32>> = conn.exec_first(
+            "SELECT GET_LOCK(:name, :timeout)",
+            params! {
+                "name" => PERIOD_LOCK_NAME,
+                "timeout" => PERIOD_LOCK_TIMEOUT_SECS,
+            }
+        )?;
+        if locked.flatten() != Some(1) {
+            return Err(StorageError::Unavailable("Timed out waiting for another pay period lock".to_string()).into());
+        }
+
+        let result = Self::insert_period_lock_in_turn(&mut conn, starts_on, ends_on, locked_by, now);
+
+        conn.exec_drop(
+            "DO RELEASE_LOCK(:name)",
+            params! {
+                "name" => PERIOD_LOCK_NAME,
+            }
+        )?;
+
+        result
+    }
+
+    fn find_period_lock(&self, lock_id: i32) -> Result<Option<PeriodLock>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let row: Option<PeriodLockRow> = conn
+            .exec_first(
+                format!("SELECT {} FROM pay_period_locks WHERE id = :lock_id", PERIOD_LOCK_COLUMNS),
+                params! {
+                    "lock_id" => lock_id,
+                }
+            )?;
+
+        Ok(row.map(period_lock_from_row))
+    }
+
+    fn list_period_locks(&self, from: NaiveDate, to: NaiveDate) -> Result<Vec<PeriodLock>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let locks = conn
+            .exec_map(
+                format!(
+                    "SELECT {} FROM pay_period_locks
+                    WHERE starts_on <= :to AND ends_on >= :from
+                    ORDER BY starts_on, id",
+                    PERIOD_LOCK_COLUMNS
+                ),
+                params! {
+                    "from" => from,
+                    "to" => to,
+                },
+                period_lock_from_row
+            )?;
+
+        Ok(locks)
+    }
+
+    fn unlock_period(
+        &self,
+        lock_id: i32,
+        unlocked_by: i32,
+        reason: &str,
+        now: NaiveDateTime,
+    ) -> Result<bool, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        // Setting unlocked_at always changes the row, so affected rows are
+        // exactly the locks that were still in force
+        conn.exec_drop(
+            "UPDATE pay_period_locks SET unlocked_by = :unlocked_by, unlocked_at = :now, unlock_reason = :reason
+            WHERE id = :lock_id AND unlocked_at IS NULL",
+            params! {
+                "lock_id" => lock_id,
+                "unlocked_by" => unlocked_by,
+                "now" => now,
+                "reason" => reason,
+            }
+        )?;
+
+        Ok(conn.affected_rows() > 0)
+    }
+
+    fn insert_correction(
+        &self,
+        user_id: i32,
+        correction: &NewAttendanceCorrection,
+        now: NaiveDateTime,
+    ) -> Result<i32, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "INSERT INTO attendance_corrections (user_id, event_id, event_type, event_time, reason, requested_at)
+            VALUES (:user_id, :event_id, :event_type, :event_time, :reason, :now)",
+            params! {
+                "user_id" => user_id,
+                "event_id" => correction.event_id,
+                "event_type" => correction.event_type.as_str(),
+                "event_time" => correction.event_time,
+                "reason" => &correction.reason,
+                "now" => now,
+            }
+        )?;
+
+        Ok(conn.last_insert_id() as i32)
+    }
+
+    fn find_correction(&self, correction_id: i32) -> Result<Option<AttendanceCorrection>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let row: Option<CorrectionRow> = conn
+            .exec_first(
+                format!(
+                    "SELECT {} FROM attendance_corrections c WHERE c.id = :correction_id",
+                    CORRECTION_COLUMNS
+                ),
+                params! {
+                    "correction_id" => correction_id,
+                }
+            )?;
+
+        Ok(row.map(correction_from_row))
+    }
+
+    fn list_corrections(
+        &self,
+        user_id: Option<i32>,
+        supervisor_id: Option<i32>,
+        status: Option<CorrectionStatus>,
+    ) -> Result<Vec<AttendanceCorrection>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let corrections = conn
+            .exec_map(
+                format!(
+                    "SELECT {} FROM attendance_corrections c
+                    JOIN users u ON u.id = c.user_id
+                    WHERE (:user_id IS NULL OR c.user_id = :user_id)
+                    AND (:supervisor_id IS NULL OR u.supervisor_id = :supervisor_id)
+                    AND (:status IS NULL OR c.status = :status)
+                    ORDER BY c.requested_at, c.id",
+                    CORRECTION_COLUMNS
+                ),
+                params! {
+                    "user_id" => user_id,
+                    "supervisor_id" => supervisor_id,
+                    "status" => status.map(|status| status.as_str()),
+                },
+                correction_from_row
+            )?;
+
+        Ok(corrections)
+    }
+
+    fn decide_correction(
+        &self,
+        correction_id: i32,
+        status: CorrectionStatus,
+        decided_by: i32,
+        note: Option<&str>,
+        now: NaiveDateTime,
+    ) -> Result<bool, CorrectionError> {
+        let mut conn = self.pool.get_conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+
+        // The status always changes, so affected rows are exactly the
+        // corrections that were still pending
+        tx.exec_drop(
+            "UPDATE attendance_corrections
+            SET status = :status, decided_by = :decided_by, decided_at = :now, decision_note = :note
+            WHERE id = :correction_id AND status = 'pending'",
+            params! {
+                "correction_id" => correction_id,
+                "status" => status.as_str(),
+                "decided_by" => decided_by,
+                "now" => now,
+                "note" => note,
+            }
+        )?;
+
+        if tx.affected_rows() == 0 {
+            return Ok(false);
+        }
+
+        if status == CorrectionStatus::Approved {
+            // Lock the user row so punches recorded meanwhile cannot slip
+            // past the sequence check
+            tx.exec_drop(
+                "SELECT u.id FROM users u JOIN attendance_corrections c ON c.user_id = u.id
+                WHERE c.id = :correction_id FOR UPDATE",
+                params! {
+                    "correction_id" => correction_id,
+                }
+            )?;
+            tx.exec_drop(
+                "INSERT INTO attendance_events (user_id, event_type, event_time, corrects_event_id, correction_id)
+                SELECT user_id, event_type, event_time, event_id, id FROM attendance_corrections
+                WHERE id = :correction_id",
+                params! {
+                    "correction_id" => correction_id,
+                }
+            )?;
+            let event_id = tx.last_insert_id();
+            tx.exec_drop(
+                "UPDATE attendance_corrections SET adjusted_event_id = :event_id WHERE id = :correction_id",
+                params! {
+                    "correction_id" => correction_id,
+                    "event_id" => event_id,
+                }
+            )?;
+            check_corrected_sequence(&mut tx, correction_id)?;
+        }
+
+        tx.commit()?;
+
+        Ok(true)
+    }
+
+    fn insert_leave_accrual(
+        &self,
+        user_id: i32,
+        leave_type: LeaveType,
+        days: f64,
+        note: Option<&str>,
+        created_by: i32,
+        now: NaiveDateTime,
+    ) -> Result<i32, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        conn.exec_drop(
+            "INSERT INTO leave_accruals (user_id, leave_type, days, note, created_by, created_at)
+            VALUES (:user_id, :leave_type, :days, :note, :created_by, :now)",
+            params! {
+                "user_id" => user_id,
+                "leave_type" => leave_type.as_str(),
+                "days" => days,
+                "note" => note,
+                "created_by" => created_by,
+                "now" => now,
+            }
+        )?;
+
+        Ok(conn.last_insert_id() as i32)
+    }
+
+    fn list_leave_accruals(&self, user_id: i32) -> Result<Vec<LeaveAccrual>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let accruals = conn
+            .exec_map(
+                format!(
+                    "SELECT {} FROM leave_accruals WHERE user_id = :user_id ORDER BY created_at, id",
+                    ACCRUAL_COLUMNS
+                ),
+                params! {
+                    "user_id" => user_id,
+                },
+                accrual_from_row
+            )?;
+
+        Ok(accruals)
+    }
+
+    fn insert_leave(&self, user_id: i32, leave: &NewLeave, now: NaiveDateTime) -> Result<i32, LeaveError> {
+        let mut conn = self.pool.get_conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+
+        // Lock the user row so concurrent requests for the same user are serialized
+        tx.exec_drop(
+            "SELECT id FROM users WHERE id = :user_id FOR UPDATE",
+            params! {
+                "user_id" => user_id,
+            }
+        )?;
+        check_leave(&mut tx, user_id, leave.leave_type, leave.starts_on, leave.ends_on, leave.days, None)?;
+
+        tx.exec_drop(
+            "INSERT INTO leave_requests (user_id, leave_type, starts_on, ends_on, days, reason, requested_at)
+            VALUES (:user_id, :leave_type, :starts_on, :ends_on, :days, :reason, :now)",
+            params! {
+                "user_id" => user_id,
+                "leave_type" => leave.leave_type.as_str(),
+                "starts_on" => leave.starts_on,
+                "ends_on" => leave.ends_on,
+                "days" => leave.days,
+                "reason" => &leave.reason,
+                "now" => now,
+            }
+        )?;
+
+        let leave_id = tx.last_insert_id().unwrap_or_default() as i32;
+        tx.commit()?;
+
+        Ok(leave_id)
+    }
+
+    fn find_leave(&self, leave_id: i32) -> Result<Option<Leave>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let row: Option<LeaveRow> = conn
+            .exec_first(
+                format!("SELECT {} FROM leave_requests l WHERE l.id = :leave_id", LEAVE_COLUMNS),
+                params! {
+                    "leave_id" => leave_id,
+                }
+            )?;
+
+        Ok(row.map(leave_from_row))
+    }
+
+    fn list_leaves(
+        &self,
+        user_id: Option<i32>,
+        supervisor_id: Option<i32>,
+        status: Option<LeaveStatus>,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<Vec<Leave>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let leaves = conn
+            .exec_map(
+                format!(
+                    "SELECT {} FROM leave_requests l
+                    JOIN users u ON u.id = l.user_id
+                    WHERE (:user_id IS NULL OR l.user_id = :user_id)
+                    AND (:supervisor_id IS NULL OR u.supervisor_id = :supervisor_id)
+                    AND (:status IS NULL OR l.status = :status)
+                    AND (:from IS NULL OR l.ends_on >= :from)
+                    AND (:to IS NULL OR l.starts_on <= :to)
+                    ORDER BY l.starts_on, l.id",
+                    LEAVE_COLUMNS
+                ),
+                params! {
+                    "user_id" => user_id,
+                    "supervisor_id" => supervisor_id,
+                    "status" => status.map(|status| status.as_str()),
+                    "from" => from,
+                    "to" => to,
+                },
+                leave_from_row
+            )?;
+
+        Ok(leaves)
+    }
+
+    fn decide_leave(
+        &self,
+        leave_id: i32,
+        status: LeaveStatus,
+        decided_by: i32,
+        note: Option<&str>,
+        now: NaiveDateTime,
+    ) -> Result<bool, LeaveError> {
+        let mut conn = self.pool.get_conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+
+        if status == LeaveStatus::Approved {
+            let row: Option<LeaveRow> = tx
+                .exec_first(
+                    format!(
+                        "SELECT {} FROM leave_requests l WHERE l.id = :leave_id AND l.status = 'pending' FOR UPDATE",
+                        LEAVE_COLUMNS
+                    ),
+                    params! {
+                        "leave_id" => leave_id,
+                    }
+                )?;
+
+            let Some(leave) = row.map(leave_from_row) else {
+                return Ok(false);
+            };
+            tx.exec_drop(
+                "SELECT id FROM users WHERE id = :user_id FOR UPDATE",
+                params! {
+                    "user_id" => leave.user_id,
+                }
+            )?;
+            check_leave(
+                &mut tx,
+                leave.user_id,
+                leave.leave_type,
+                leave.starts_on,
+                leave.ends_on,
+                leave.days,
+                Some(leave_id),
+            )?;
+        }
+
+        // The status always changes, so affected rows are exactly the
+        // requests that were still pending
+        tx.exec_drop(
+            "UPDATE leave_requests
+            SET status = :status, decided_by = :decided_by, decided_at = :now, decision_note = :note
+            WHERE id = :leave_id AND status = 'pending'",
+            params! {
+                "leave_id" => leave_id,
+                "status" => status.as_str(),
+                "decided_by" => decided_by,
+                "now" => now,
+                "note" => note,
+            }
+        )?;
+
+        let changed = tx.affected_rows() > 0;
+        tx.commit()?;
+
+        Ok(changed)
+    }
+
+    fn record_attendance_event(&self, punch: &Punch) -> Result<AttendanceEvent, AttendanceError> {
+        let mut conn = self.pool.get_conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        let user_id = punch.user_id;
+
+        // Lock the user row so concurrent punches for the same user are serialized
+        let user: Option<i32> = tx
+            .exec_first(
+                "SELECT id FROM users WHERE id = :user_id FOR UPDATE",
+                params! {
+                    "user_id" => user_id,
+                }
+            )?;
+
+        if user.is_none() {
+            return Err(AttendanceError::UserNotFound);
+        }
+
+        // A replayed punch that already reached the server is not counted twice
+        let existing: Option<(u64, String, NaiveDateTime)> = tx
+            .exec_first(
+                "SELECT id, event_type, event_time FROM attendance_events
+                WHERE idempotency_key = :idempotency_key",
+                params! {
+                    "idempotency_key" => &punch.idempotency_key,
+                }
+            )?;
+
+        if let Some((id, event_type, event_time)) = existing {
+            return Ok(AttendanceEvent {
+                id,
+                user_id,
+                event_type: AttendanceEventType::parse(&event_type).unwrap_or(punch.event_type),
+                event_time,
+                idempotency_key: Some(punch.idempotency_key.clone()),
+                corrects_event_id: None,
+                correction_id: None,
+                queued: false,
+            });
+        }
+
+        let last_event: Option<String> = tx
+            .exec_first(
+                format!(
+                    "SELECT e.event_type FROM attendance_events e
+                    WHERE e.user_id = :user_id AND {}
+                    ORDER BY e.event_time DESC, e.id DESC
+                    LIMIT 1",
+                    CURRENT_EVENT_FILTER
+                ),
+                params! {
+                    "user_id" => user_id,
+                }
+            )?;
+
+        check_attendance_transition(
+            last_event.as_deref().and_then(AttendanceEventType::parse),
+            punch.event_type,
+        )?;
+
+        tx.exec_drop(
+            "INSERT INTO attendance_events (user_id, event_type, event_time, idempotency_key)
+            VALUES (:user_id, :event_type, :event_time, :idempotency_key)",
+            params! {
+                "user_id" => user_id,
+                "event_type" => punch.event_type.as_str(),
+                "event_time" => punch.event_time,
+                "idempotency_key" => &punch.idempotency_key,
+            }
+        )?;
+
+        let id = tx.last_insert_id().unwrap_or_default();
+        tx.commit()?;
+
+        Ok(AttendanceEvent {
+            id,
+            user_id,
+            event_type: punch.event_type,
+            event_time: punch.event_time,
+            idempotency_key: Some(punch.idempotency_key.clone()),
+            corrects_event_id: None,
+            correction_id: None,
+            queued: false,
+        })
+    }
+
+    fn last_attendance_event_type(&self, user_id: i32) -> Result<Option<AttendanceEventType>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let event_type: Option<String> = conn
+            .exec_first(
+                format!(
+                    "SELECT e.event_type FROM attendance_events e
+                    WHERE e.user_id = :user_id AND {}
+                    ORDER BY e.event_time DESC, e.id DESC
+                    LIMIT 1",
+                    CURRENT_EVENT_FILTER
+                ),
+                params! {
+                    "user_id" => user_id,
+                }
+            )?;
+
+        Ok(event_type.as_deref().and_then(AttendanceEventType::parse))
+    }
+
+    fn find_attendance_event(&self, event_id: u64) -> Result<Option<AttendanceEvent>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let row: Option<EventRow> = conn
+            .exec_first(
+                format!(
+                    "SELECT {} FROM attendance_events e WHERE e.id = :event_id AND {}",
+                    EVENT_COLUMNS, CURRENT_EVENT_FILTER
+                ),
+                params! {
+                    "event_id" => event_id,
+                }
+            )?;
+
+        Ok(row.map(event_from_row))
+    }
+
+    fn attendance_events(
+        &self,
+        user_id: i32,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+    ) -> Result<Vec<AttendanceEvent>, StorageError> {
+        let mut conn = self.pool.get_conn()?;
+
+        let events = conn
+            .exec_map(
+                format!(
+                    "SELECT {} FROM attendance_events e
+                    WHERE e.user_id = :user_id AND {}
+                    AND (:from IS NULL OR e.event_time >= :from)
+                    AND (:to IS NULL OR e.event_time < :to)
+                    ORDER BY e.event_time DESC, e.id DESC",
+                    EVENT_COLUMNS, CURRENT_EVENT_FILTER
+                ),
+                params! {
+                    "user_id" => user_id,
+                    "from" => from,
+                    "to" => to,
+                },
+                event_from_row
+            )?;
+
+        Ok(events)
+    }
+}
@@ -0,0 +1,1672 @@
+// src/storage/sqlite.rs
+
+use std::sync::{Mutex, MutexGuard};
+use chrono::{NaiveDate, NaiveDateTime};
+use rusqlite::{named_params, params, Connection, OptionalExtension, Row, TransactionBehavior};
+
+use crate::database::{
+    check_attendance_sequence, check_attendance_transition, AttendanceCorrection, AttendanceError, AttendanceEvent,
+    AttendanceEventType, AttendanceException, CorrectionError, CorrectionStatus, Leave, LeaveAccrual, LeaveError,
+    LeaveStatus, LeaveType, NewAttendanceCorrection, NewAttendanceException, NewLeave, NewUser, PayPeriodError, PeriodLock, Punch,
+    ScheduleAssignment, ScheduleAssignmentRequest, Shift, ShiftRequest, UpdateUserRequest, User, UserQuery,
+    UserStatus,
+};
+use crate::lockout::ThrottleScope;
+use crate::migrations::{self, AppliedMigration, MigrationError};
+use crate::offline_cache::OfflineUser;
+use super::{
+    correction_kind, parse_correction_status, parse_event_type, parse_exception_kind, parse_leave_status,
+    parse_leave_type, parse_pattern, parse_role, parse_status, pattern_columns, user_order_by, Storage, StorageError, CURRENT_EVENT_FILTER, USER_SEARCH_FILTER,
+};
+
+impl From<rusqlite::Error> for AttendanceError {
+    fn from(e: rusqlite::Error) -> Self {
+        AttendanceError::Database(e.into())
+    }
+}
+
+impl From<rusqlite::Error> for CorrectionError {
+    fn from(e: rusqlite::Error) -> Self {
+        CorrectionError::Database(e.into())
+    }
+}
+
+impl From<rusqlite::Error> for LeaveError {
+    fn from(e: rusqlite::Error) -> Self {
+        LeaveError::Database(e.into())
+    }
+}
+
+impl From<rusqlite::Error> for PayPeriodError {
+    fn from(e: rusqlite::Error) -> Self {
+        PayPeriodError::Database(e.into())
+    }
+}
+
+impl From<rusqlite::Error> for MigrationError {
+    fn from(e: rusqlite::Error) -> Self {
+        MigrationError::Database(e.into())
+    }
+}
+
+// Columns read by `read_user`, for queries that alias users as `u`
+const USER_COLUMNS: &str =
+    "u.id, u.username, u.email, u.full_name, u.role, u.status, u.must_change_password, u.created_at";
+// Index of the first column selected after USER_COLUMNS
+const AFTER_USER_COLUMNS: usize = 8;
+
+fn read_user(row: &Row) -> rusqlite::Result<User> {
+    Ok(User {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        email: row.get(2)?,
+        full_name: row.get(3)?,
+        role: parse_role(&row.get::<_, String>(4)?),
+        status: parse_status(&row.get::<_, String>(5)?),
+        must_change_password: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+const SHIFT_COLUMNS: &str = "id, name, start_time, end_time, split_start_time, split_end_time";
+
+fn read_shift(row: &Row) -> rusqlite::Result<Shift> {
+    Ok(Shift {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        start_time: row.get(2)?,
+        end_time: row.get(3)?,
+        split_start_time: row.get(4)?,
+        split_end_time: row.get(5)?,
+    })
+}
+
+const ASSIGNMENT_COLUMNS: &str =
+    "id, user_id, shift_id, pattern, weekdays, days_on, days_off, starts_on, ends_on";
+
+fn read_assignment(row: &Row) -> rusqlite::Result<ScheduleAssignment> {
+    Ok(ScheduleAssignment {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        shift_id: row.get(2)?,
+        pattern: parse_pattern(&row.get::<_, String>(3)?, row.get(4)?, row.get(5)?, row.get(6)?),
+        starts_on: row.get(7)?,
+        ends_on: row.get(8)?,
+    })
+}
+
+const EXCEPTION_COLUMNS: &str =
+    "id, user_id, work_date, kind, minutes, detected_at, reviewed_by, reviewed_at, review_note";
+
+fn read_exception(row: &Row) -> rusqlite::Result<AttendanceException> {
+    Ok(AttendanceException {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        work_date: row.get(2)?,
+        kind: parse_exception_kind(&row.get::<_, String>(3)?),
+        minutes: row.get(4)?,
+        detected_at: row.get(5)?,
+        reviewed_by: row.get(6)?,
+        reviewed_at: row.get(7)?,
+        review_note: row.get(8)?,
+    })
+}
+
+// For queries that alias attendance_events as `e`
+const EVENT_COLUMNS: &str =
+    "e.id, e.user_id, e.event_type, e.event_time, e.idempotency_key, e.corrects_event_id, e.correction_id";
+
+fn read_event(row: &Row) -> rusqlite::Result<AttendanceEvent> {
+    Ok(AttendanceEvent {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        event_type: parse_event_type(&row.get::<_, String>(2)?),
+        event_time: row.get(3)?,
+        idempotency_key: row.get(4)?,
+        corrects_event_id: row.get(5)?,
+        correction_id: row.get(6)?,
+        queued: false,
+    })
+}
+
+// For queries that alias attendance_corrections as `c`
+const CORRECTION_COLUMNS: &str = "c.id, c.user_id, c.event_id, c.event_type, c.event_time, c.reason, c.status, \
+    c.requested_at, c.decided_by, c.decided_at, c.decision_note, c.adjusted_event_id";
+
+fn read_correction(row: &Row) -> rusqlite::Result<AttendanceCorrection> {
+    let event_id = row.get(2)?;
+
+    Ok(AttendanceCorrection {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        kind: correction_kind(event_id),
+        event_id,
+        event_type: parse_event_type(&row.get::<_, String>(3)?),
+        event_time: row.get(4)?,
+        reason: row.get(5)?,
+        status: parse_correction_status(&row.get::<_, String>(6)?),
+        requested_at: row.get(7)?,
+        decided_by: row.get(8)?,
+        decided_at: row.get(9)?,
+        decision_note: row.get(10)?,
+        adjusted_event_id: row.get(11)?,
+    })
+}
+
+const ACCRUAL_COLUMNS: &str = "id, user_id, leave_type, days, note, created_by, created_at";
+
+fn read_accrual(row: &Row) -> rusqlite::Result<LeaveAccrual> {
+    Ok(LeaveAccrual {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        leave_type: parse_leave_type(&row.get::<_, String>(2)?),
+        days: row.get(3)?,
+        note: row.get(4)?,
+        created_by: row.get(5)?,
+        created_at: row.get(6)?,
+    })
+}
+
+// For queries that alias leave_requests as `l`
+const LEAVE_COLUMNS: &str = "l.id, l.user_id, l.leave_type, l.starts_on, l.ends_on, l.days, l.reason, l.status, \
+    l.requested_at, l.decided_by, l.decided_at, l.decision_note";
+
+fn read_leave(row: &Row) -> rusqlite::Result<Leave> {
+    Ok(Leave {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        leave_type: parse_leave_type(&row.get::<_, String>(2)?),
+        starts_on: row.get(3)?,
+        ends_on: row.get(4)?,
+        days: row.get(5)?,
+        reason: row.get(6)?,
+        status: parse_leave_status(&row.get::<_, String>(7)?),
+        requested_at: row.get(8)?,
+        decided_by: row.get(9)?,
+        decided_at: row.get(10)?,
+        decision_note: row.get(11)?,
+    })
+}
+
+const PERIOD_LOCK_COLUMNS: &str =
+    "id, starts_on, ends_on, locked_by, locked_at, unlocked_by, unlocked_at, unlock_reason";
+
+fn read_period_lock(row: &Row) -> rusqlite::Result<PeriodLock> {
+    Ok(PeriodLock {
+        id: row.get(0)?,
+        starts_on: row.get(1)?,
+        ends_on: row.get(2)?,
+        locked_by: row.get(3)?,
+        locked_at: row.get(4)?,
+        unlocked_by: row.get(5)?,
+        unlocked_at: row.get(6)?,
+        unlock_reason: row.get(7)?,
+    })
+}
+
+// Run inside the approval's transaction, after the adjusted event is
+// written and the punch it replaces is hidden
+fn check_corrected_sequence(conn: &Connection, correction_id: i32) -> Result<(), CorrectionError> {
+    let (user_id, event_time, original_time): (i32, NaiveDateTime, Option<NaiveDateTime>) = conn.query_row(
+        "SELECT c.user_id, c.event_time, o.event_time FROM attendance_corrections c
+        LEFT JOIN attendance_events o ON o.id = c.event_id
+        WHERE c.id = ?1",
+        params![correction_id],
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    let from = original_time.map_or(event_time, |original_time| original_time.min(event_time));
+
+    let last_event: Option<String> = conn
+        .query_row(
+            &format!(
+                "SELECT e.event_type FROM attendance_events e
+                WHERE e.user_id = :user_id AND e.event_time < :from AND {}
+                ORDER BY e.event_time DESC, e.id DESC
+                LIMIT 1",
+                CURRENT_EVENT_FILTER
+            ),
+            named_params! {
+                ":user_id": user_id,
+                ":from": from,
+            },
+            |row| row.get(0),
+        )
+        .optional()?;
+
+    let mut stmt = conn.prepare(&format!(
+        "SELECT e.event_type FROM attendance_events e
+        WHERE e.user_id = :user_id AND e.event_time >= :from AND {}
+        ORDER BY e.event_time, e.id",
+        CURRENT_EVENT_FILTER
+    ))?;
+    let events = stmt
+        .query_map(
+            named_params! {
+                ":user_id": user_id,
+                ":from": from,
+            },
+            |row| Ok(parse_event_type(&row.get::<_, String>(0)?)),
+        )?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    check_attendance_sequence(last_event.as_deref().map(parse_event_type), events)
+        .map_err(CorrectionError::InvalidSequence)
+}
+
+// Run inside the transaction that stores a leave request (`leave_id` None)
+// or approves one, so concurrent requests cannot overlap or overdraw
+fn check_leave(
+    conn: &Connection,
+    user_id: i32,
+    leave_type: LeaveType,
+    starts_on: NaiveDate,
+    ends_on: NaiveDate,
+    days: f64,
+    leave_id: Option<i32>,
+) -> Result<(), LeaveError> {
+    let overlapping: Option<(NaiveDate, NaiveDate)> = conn
+        .query_row(
+            "SELECT starts_on, ends_on FROM leave_requests
+            WHERE user_id = :user_id AND status <> 'rejected'
+            AND (:leave_id IS NULL OR id <> :leave_id)
+            AND starts_on <= :ends_on AND ends_on >= :starts_on
+            ORDER BY starts_on
+            LIMIT 1",
+            named_params! {
+                ":user_id": user_id,
+                ":leave_id": leave_id,
+                ":starts_on": starts_on,
+                ":ends_on": ends_on,
+            },
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()?;
+
+    if let Some((starts_on, ends_on)) = overlapping {
+        return Err(LeaveError::Overlaps { starts_on, ends_on });
+    }
+
+    if !leave_type.is_accrued() {
+        return Ok(());
+    }
+
+    let accrued: f64 = conn.query_row(
+        "SELECT COALESCE(SUM(days), 0.0) FROM leave_accruals
+        WHERE user_id = :user_id AND leave_type = :leave_type",
+        named_params! {
+            ":user_id": user_id,
+            ":leave_type": leave_type.as_str(),
+        },
+        |row| row.get(0),
+    )?;
+    // A new request also waits behind pending ones; an approval only
+    // behind approved leave
+    let taken: f64 = conn.query_row(
+        "SELECT COALESCE(SUM(days), 0.0) FROM leave_requests
+        WHERE user_id = :user_id AND leave_type = :leave_type
+        AND (status = 'approved' OR (status = 'pending' AND :leave_id IS NULL))",
+        named_params! {
+            ":user_id": user_id,
+            ":leave_type": leave_type.as_str(),
+            ":leave_id": leave_id,
+        },
+        |row| row.get(0),
+    )?;
+
+    let available = accrued - taken;
+    if available < days {
+        return Err(LeaveError::InsufficientBalance {
+            leave_type,
+            available,
+            requested: days,
+        });
+    }
+
+    Ok(())
+}
+
+// A single embedded connection shared by all commands. SQLite serializes
+// writers anyway, so a mutex costs nothing for a single kiosk.
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    pub fn open(path: &str) -> Result<Self, StorageError> {
+        let conn = Connection::open(path)?;
+
+        conn.execute_batch(
+            "PRAGMA foreign_keys = ON;
+            PRAGMA journal_mode = WAL;
+            PRAGMA busy_timeout = 5000;"
+        )?;
+
+        Ok(SqliteStorage { conn: Mutex::new(conn) })
+    }
+
+    fn conn(&self) -> MutexGuard<'_, Connection> {
+        // A panic while holding the lock leaves the connection itself usable
+        self.conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn migrate(&self) -> Result<Vec<u32>, MigrationError> {
+        let mut conn = self.conn();
+
+        conn.execute_batch(
+            r"CREATE TABLE IF NOT EXISTS schema_migrations (
+                version INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                checksum TEXT NOT NULL,
+                applied_at TEXT DEFAULT CURRENT_TIMESTAMP
+            )"
+        )?;
+
+        // SQLite DDL is transactional, so a failed migration leaves no trace
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let applied = tx
+            .prepare("SELECT version, name, checksum FROM schema_migrations ORDER BY version")?
+            .query_map([], |row| {
+                Ok(AppliedMigration {
+                    version: row.get(0)?,
+                    name: row.get(1)?,
+                    checksum: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let pending = migrations::pending(&applied, migrations::SQLITE)?;
+        let mut versions = Vec::with_capacity(pending.len());
+
+        for migration in pending {
+            tx.execute_batch(migration.sql).map_err(|e| migration.failed(e))?;
+            tx.execute(
+                "INSERT INTO schema_migrations (version, name, checksum)
+                VALUES (:version, :name, :checksum)",
+                named_params! {
+                    ":version": migration.version,
+                    ":name": migration.name,
+                    ":checksum": migration.checksum(),
+                },
+            )?;
+            versions.push(migration.version);
+        }
+
+        tx.commit()?;
+
+        Ok(versions)
+    }
+
+    fn name(&self) -> &'static str {
+        "sqlite"
+    }
+
+    fn is_embedded(&self) -> bool {
+        true
+    }
+
+    fn count_users(&self) -> Result<u64, StorageError> {
+        let count: u64 = self.conn()
+            .query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))?;
+
+        Ok(count)
+    }
+
+    fn insert_user(&self, user: &NewUser) -> Result<i32, StorageError> {
+        let conn = self.conn();
+
+        conn.execute(
+            "INSERT INTO users
+            (username, password_hash, email, full_name, role, supervisor_id)
+            VALUES (:username, :password_hash, :email, :full_name, :role, :supervisor_id)",
+            named_params! {
+                ":username": user.username,
+                ":password_hash": user.password_hash,
+                ":email": user.email,
+                ":full_name": user.full_name,
+                ":role": user.role.as_str(),
+                ":supervisor_id": user.supervisor_id,
+            },
+        )?;
+
+        Ok(conn.last_insert_rowid() as i32)
+    }
+
+    fn list_users(&self) -> Result<Vec<User>, StorageError> {
+        let conn = self.conn();
+
+        let users = conn
+            .prepare(&format!(
+                "SELECT {} FROM users u ORDER BY u.created_at DESC, u.id DESC",
+                USER_COLUMNS
+            ))?
+            .query_map([], read_user)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(users)
+    }
+
+    fn query_users(&self, query: &UserQuery) -> Result<(Vec<User>, u64), StorageError> {
+        let conn = self.conn();
+
+        let total: u64 = conn.query_row(
+            &format!("SELECT COUNT(*) FROM users u WHERE {}", USER_SEARCH_FILTER),
+            named_params! {
+                ":pattern": query.search_pattern,
+            },
+            |row| row.get(0),
+        )?;
+
+        let users = conn
+            .prepare(&format!(
+                "SELECT {} FROM users u WHERE {} ORDER BY {} LIMIT :limit OFFSET :offset",
+                USER_COLUMNS,
+                USER_SEARCH_FILTER,
+                user_order_by(query)
+            ))?
+            .query_map(
+                named_params! {
+                    ":pattern": query.search_pattern,
+                    ":limit": query.limit,
+                    ":offset": query.offset,
+                },
+                read_user,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok((users, total))
+    }
+
+    fn find_user(&self, user_id: i32) -> Result<Option<User>, StorageError> {
+        let user = self.conn()
+            .query_row(
+                &format!("SELECT {} FROM users u WHERE u.id = :user_id", USER_COLUMNS),
+                named_params! {
+                    ":user_id": user_id,
+                },
+                read_user,
+            )
+            .optional()?;
+
+        Ok(user)
+    }
+
+    fn update_user(&self, user_id: i32, update: &UpdateUserRequest) -> Result<bool, StorageError> {
+        let changed = self.conn().execute(
+            "UPDATE users SET email = :email, full_name = :full_name, role = :role
+            WHERE id = :user_id",
+            named_params! {
+                ":user_id": user_id,
+                ":email": update.email,
+                ":full_name": update.full_name,
+                ":role": update.role.as_str(),
+            },
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    fn set_user_status(&self, user_id: i32, status: UserStatus) -> Result<bool, StorageError> {
+        let changed = self.conn().execute(
+            "UPDATE users SET status = :status WHERE id = :user_id",
+            named_params! {
+                ":user_id": user_id,
+                ":status": status.as_str(),
+            },
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    fn delete_user(&self, user_id: i32) -> Result<bool, StorageError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        tx.execute("DELETE FROM sessions WHERE user_id = ?1", params![user_id])?;
+        tx.execute("DELETE FROM qr_redemptions WHERE user_id = ?1", params![user_id])?;
+        tx.execute("DELETE FROM schedule_assignments WHERE user_id = ?1", params![user_id])?;
+        tx.execute(
+            "DELETE FROM attendance_exceptions WHERE user_id = ?1 AND reviewed_at IS NULL",
+            params![user_id],
+        )?;
+        tx.execute("UPDATE users SET supervisor_id = NULL WHERE supervisor_id = ?1", params![user_id])?;
+        let deleted = tx.execute("DELETE FROM users WHERE id = ?1", params![user_id])?;
+
+        tx.commit()?;
+
+        Ok(deleted > 0)
+    }
+
+    fn find_user_credentials(&self, username: &str) -> Result<Option<(User, String)>, StorageError> {
+        let row = self.conn()
+            .query_row(
+                &format!(
+                    "SELECT {}, u.password_hash FROM users u WHERE u.username = :username",
+                    USER_COLUMNS
+                ),
+                named_params! {
+                    ":username": username,
+                },
+                |row| Ok((read_user(row)?, row.get(AFTER_USER_COLUMNS)?)),
+            )
+            .optional()?;
+
+        Ok(row)
+    }
+
+    fn update_password_hash(&self, user_id: i32, password_hash: &str) -> Result<(), StorageError> {
+        self.conn().execute(
+            "UPDATE users SET password_hash = :password_hash WHERE id = :user_id",
+            named_params! {
+                ":user_id": user_id,
+                ":password_hash": password_hash,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn set_password(&self, user_id: i32, password_hash: &str, must_change: bool) -> Result<bool, StorageError> {
+        let changed = self.conn().execute(
+            "UPDATE users SET password_hash = :password_hash, must_change_password = :must_change
+            WHERE id = :user_id",
+            named_params! {
+                ":user_id": user_id,
+                ":password_hash": password_hash,
+                ":must_change": must_change,
+            },
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    fn set_kiosk_credentials(
+        &self,
+        user_id: i32,
+        badge_hash: Option<&str>,
+        pin_hash: Option<&str>,
+    ) -> Result<bool, StorageError> {
+        let changed = self.conn().execute(
+            "UPDATE users SET badge_hash = :badge_hash, pin_hash = :pin_hash WHERE id = :user_id",
+            named_params! {
+                ":user_id": user_id,
+                ":badge_hash": badge_hash,
+                ":pin_hash": pin_hash,
+            },
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    fn find_kiosk_credentials(&self, badge_hash: &str) -> Result<Option<(User, Option<String>)>, StorageError> {
+        let row = self.conn()
+            .query_row(
+                &format!("SELECT {}, u.pin_hash FROM users u WHERE u.badge_hash = :badge_hash", USER_COLUMNS),
+                named_params! {
+                    ":badge_hash": badge_hash,
+                },
+                |row| Ok((read_user(row)?, row.get(AFTER_USER_COLUMNS)?)),
+            )
+            .optional()?;
+
+        Ok(row)
+    }
+
+    fn list_offline_users(&self) -> Result<Vec<OfflineUser>, StorageError> {
+        let conn = self.conn();
+
+        let users = conn
+            .prepare(&format!(
+                "SELECT {}, (
+                    SELECT e.event_type FROM attendance_events e
+                    WHERE e.user_id = u.id AND {}
+                    ORDER BY e.event_time DESC, e.id DESC
+                    LIMIT 1
+                ), u.badge_hash, u.pin_hash
+                FROM users u
+                WHERE u.status = 'active'",
+                USER_COLUMNS, CURRENT_EVENT_FILTER
+            ))?
+            .query_map([], |row| {
+                Ok(OfflineUser {
+                    user: read_user(row)?,
+                    last_event_type: row
+                        .get::<_, Option<String>>(AFTER_USER_COLUMNS)?
+                        .as_deref()
+                        .map(parse_event_type),
+                    badge_hash: row.get(AFTER_USER_COLUMNS + 1)?,
+                    pin_hash: row.get(AFTER_USER_COLUMNS + 2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(users)
+    }
+
+    fn redeem_qr_code(
+        &self,
+        user_id: i32,
+        window: i64,
+        oldest_window: i64,
+        now: NaiveDateTime,
+    ) -> Result<bool, StorageError> {
+        let conn = self.conn();
+
+        conn.execute(
+            "DELETE FROM qr_redemptions WHERE time_window < :oldest_window",
+            named_params! {
+                ":oldest_window": oldest_window,
+            },
+        )?;
+        let inserted = conn.execute(
+            "INSERT INTO qr_redemptions (user_id, time_window, redeemed_at)
+            VALUES (:user_id, :window, :now)",
+            named_params! {
+                ":user_id": user_id,
+                ":window": window,
+                ":now": now,
+            },
+        );
+
+        match inserted.map_err(StorageError::from) {
+            Ok(_) => Ok(true),
+            Err(StorageError::Duplicate(_)) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn is_supervisor_of(&self, supervisor_id: i32, user_id: i32) -> Result<bool, StorageError> {
+        let found: Option<i32> = self.conn()
+            .query_row(
+                "SELECT id FROM users WHERE id = :user_id AND supervisor_id = :supervisor_id",
+                named_params! {
+                    ":user_id": user_id,
+                    ":supervisor_id": supervisor_id,
+                },
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(found.is_some())
+    }
+
+    fn insert_session(
+        &self,
+        token_hash: &str,
+        user_id: i32,
+        created_at: NaiveDateTime,
+        expires_at: NaiveDateTime,
+    ) -> Result<(), StorageError> {
+        self.conn().execute(
+            "INSERT INTO sessions (token_hash, user_id, created_at, expires_at)
+            VALUES (:token_hash, :user_id, :created_at, :expires_at)",
+            named_params! {
+                ":token_hash": token_hash,
+                ":user_id": user_id,
+                ":created_at": created_at,
+                ":expires_at": expires_at,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn find_session_user(
+        &self,
+        token_hash: &str,
+        now: NaiveDateTime,
+    ) -> Result<Option<(User, NaiveDateTime)>, StorageError> {
+        let session = self.conn()
+            .query_row(
+                &format!(
+                    "SELECT {}, s.expires_at FROM sessions s
+                    JOIN users u ON u.id = s.user_id
+                    WHERE s.token_hash = :token_hash
+                    AND s.revoked_at IS NULL
+                    AND s.expires_at > :now
+                    AND u.status = 'active'",
+                    USER_COLUMNS
+                ),
+                named_params! {
+                    ":token_hash": token_hash,
+                    ":now": now,
+                },
+                |row| Ok((read_user(row)?, row.get(AFTER_USER_COLUMNS)?)),
+            )
+            .optional()?;
+
+        Ok(session)
+    }
+
+    fn revoke_session(&self, token_hash: &str, now: NaiveDateTime) -> Result<(), StorageError> {
+        self.conn().execute(
+            "UPDATE sessions SET revoked_at = :now
+            WHERE token_hash = :token_hash AND revoked_at IS NULL",
+            named_params! {
+                ":token_hash": token_hash,
+                ":now": now,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn revoke_user_sessions(&self, user_id: i32, now: NaiveDateTime) -> Result<(), StorageError> {
+        self.conn().execute(
+            "UPDATE sessions SET revoked_at = :now
+            WHERE user_id = :user_id AND revoked_at IS NULL",
+            named_params! {
+                ":user_id": user_id,
+                ":now": now,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn record_login_failure(
+        &self,
+        scope: ThrottleScope,
+        subject: &str,
+        now: NaiveDateTime,
+        window_start: NaiveDateTime,
+    ) -> Result<u32, StorageError> {
+        let failures = self.conn().query_row(
+            "INSERT INTO login_throttles (scope, subject, failures, last_failure_at)
+            VALUES (:scope, :subject, 1, :now)
+            ON CONFLICT (scope, subject) DO UPDATE SET
+                failures = CASE
+                    WHEN last_failure_at < :window_start
+                        AND (locked_until IS NULL OR locked_until < :window_start)
+                    THEN 1
+                    ELSE failures + 1
+                END,
+                last_failure_at = :now
+            RETURNING failures",
+            named_params! {
+                ":scope": scope.as_str(),
+                ":subject": subject,
+                ":now": now,
+                ":window_start": window_start,
+            },
+            |row| row.get(0),
+        )?;
+
+        Ok(failures)
+    }
+
+    fn lock_login(&self, scope: ThrottleScope, subject: &str, until: NaiveDateTime) -> Result<(), StorageError> {
+        self.conn().execute(
+            "UPDATE login_throttles SET locked_until = :until
+            WHERE scope = :scope AND subject = :subject",
+            named_params! {
+                ":scope": scope.as_str(),
+                ":subject": subject,
+                ":until": until,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn login_locked_until(&self, scope: ThrottleScope, subject: &str) -> Result<Option<NaiveDateTime>, StorageError> {
+        let until: Option<Option<NaiveDateTime>> = self.conn()
+            .query_row(
+                "SELECT locked_until FROM login_throttles WHERE scope = :scope AND subject = :subject",
+                named_params! {
+                    ":scope": scope.as_str(),
+                    ":subject": subject,
+                },
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(until.flatten())
+    }
+
+    fn clear_login_failures(&self, scope: ThrottleScope, subject: &str) -> Result<(), StorageError> {
+        self.conn().execute(
+            "DELETE FROM login_throttles WHERE scope = :scope AND subject = :subject",
+            named_params! {
+                ":scope": scope.as_str(),
+                ":subject": subject,
+            },
+        )?;
+
+        Ok(())
+    }
+
+    fn insert_shift(&self, shift: &ShiftRequest) -> Result<i32, StorageError> {
+        let conn = self.conn();
+
+        conn.execute(
+            "INSERT INTO shifts (name, start_time, end_time, split_start_time, split_end_time)
+            VALUES (:name, :start_time, :end_time, :split_start_time, :split_end_time)",
+            named_params! {
+                ":name": shift.name,
+                ":start_time": shift.start_time,
+                ":end_time": shift.end_time,
+                ":split_start_time": shift.split_start_time,
+                ":split_end_time": shift.split_end_time,
+            },
+        )?;
+
+        Ok(conn.last_insert_rowid() as i32)
+    }
+
+    fn list_shifts(&self) -> Result<Vec<Shift>, StorageError> {
+        let conn = self.conn();
+
+        let shifts = conn
+            .prepare(&format!("SELECT {} FROM shifts ORDER BY name, id", SHIFT_COLUMNS))?
+            .query_map([], read_shift)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(shifts)
+    }
+
+    fn find_shift(&self, shift_id: i32) -> Result<Option<Shift>, StorageError> {
+        let shift = self.conn()
+            .query_row(
+                &format!("SELECT {} FROM shifts WHERE id = :shift_id", SHIFT_COLUMNS),
+                named_params! {
+                    ":shift_id": shift_id,
+                },
+                read_shift,
+            )
+            .optional()?;
+
+        Ok(shift)
+    }
+
+    fn update_shift(&self, shift_id: i32, shift: &ShiftRequest) -> Result<bool, StorageError> {
+        let changed = self.conn().execute(
+            "UPDATE shifts SET name = :name, start_time = :start_time, end_time = :end_time,
+                split_start_time = :split_start_time, split_end_time = :split_end_time
+            WHERE id = :shift_id",
+            named_params! {
+                ":shift_id": shift_id,
+                ":name": shift.name,
+                ":start_time": shift.start_time,
+                ":end_time": shift.end_time,
+                ":split_start_time": shift.split_start_time,
+                ":split_end_time": shift.split_end_time,
+            },
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    fn delete_shift(&self, shift_id: i32) -> Result<bool, StorageError> {
+        let deleted = self.conn().execute("DELETE FROM shifts WHERE id = ?1", params![shift_id])?;
+
+        Ok(deleted > 0)
+    }
+
+    fn insert_schedule_assignment(&self, assignment: &ScheduleAssignmentRequest) -> Result<i32, StorageError> {
+        let conn = self.conn();
+        let pattern = pattern_columns(&assignment.pattern);
+
+        conn.execute(
+            "INSERT INTO schedule_assignments
+            (user_id, shift_id, pattern, weekdays, days_on, days_off, starts_on, ends_on)
+            VALUES (:user_id, :shift_id, :pattern, :weekdays, :days_on, :days_off, :starts_on, :ends_on)",
+            named_params! {
+                ":user_id": assignment.user_id,
+                ":shift_id": assignment.shift_id,
+                ":pattern": pattern.kind,
+                ":weekdays": pattern.weekdays,
+                ":days_on": pattern.days_on,
+                ":days_off": pattern.days_off,
+                ":starts_on": assignment.starts_on,
+                ":ends_on": assignment.ends_on,
+            },
+        )?;
+
+        Ok(conn.last_insert_rowid() as i32)
+    }
+
+    fn find_schedule_assignment(&self, assignment_id: i32) -> Result<Option<ScheduleAssignment>, StorageError> {
+        let assignment = self.conn()
+            .query_row(
+                &format!(
+                    "SELECT {} FROM schedule_assignments WHERE id = :assignment_id",
+                    ASSIGNMENT_COLUMNS
+                ),
+                named_params! {
+                    ":assignment_id": assignment_id,
+                },
+                read_assignment,
+            )
+            .optional()?;
+
+        Ok(assignment)
+    }
+
+    fn list_schedule_assignments(
+        &self,
+        user_id: Option<i32>,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<Vec<ScheduleAssignment>, StorageError> {
+        let conn = self.conn();
+
+        let assignments = conn
+            .prepare(&format!(
+                "SELECT {} FROM schedule_assignments
+                WHERE (:user_id IS NULL OR user_id = :user_id)
+                    AND (:to IS NULL OR starts_on <= :to)
+                    AND (:from IS NULL OR ends_on IS NULL OR ends_on >= :from)
+                ORDER BY user_id, starts_on, id",
+                ASSIGNMENT_COLUMNS
+            ))?
+            .query_map(
+                named_params! {
+                    ":user_id": user_id,
+                    ":from": from,
+                    ":to": to,
+                },
+                read_assignment,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(assignments)
+    }
+
+    fn update_schedule_assignment(
+        &self,
+        assignment_id: i32,
+        assignment: &ScheduleAssignmentRequest,
+    ) -> Result<bool, StorageError> {
+        let pattern = pattern_columns(&assignment.pattern);
+
+        let changed = self.conn().execute(
+            "UPDATE schedule_assignments SET user_id = :user_id, shift_id = :shift_id, pattern = :pattern,
+                weekdays = :weekdays, days_on = :days_on, days_off = :days_off,
+                starts_on = :starts_on, ends_on = :ends_on
+            WHERE id = :assignment_id",
+            named_params! {
+                ":assignment_id": assignment_id,
+                ":user_id": assignment.user_id,
+                ":shift_id": assignment.shift_id,
+                ":pattern": pattern.kind,
+                ":weekdays": pattern.weekdays,
+                ":days_on": pattern.days_on,
+                ":days_off": pattern.days_off,
+                ":starts_on": assignment.starts_on,
+                ":ends_on": assignment.ends_on,
+            },
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    fn delete_schedule_assignment(&self, assignment_id: i32) -> Result<bool, StorageError> {
+        let deleted = self.conn().execute(
+            "DELETE FROM schedule_assignments WHERE id = ?1",
+            params![assignment_id],
+        )?;
+
+        Ok(deleted > 0)
+    }
+
+    fn replace_attendance_exceptions(
+        &self,
+        user_id: i32,
+        from: NaiveDate,
+        to: NaiveDate,
+        exceptions: &[NewAttendanceException],
+        now: NaiveDateTime,
+    ) -> Result<(), StorageError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        tx.execute(
+            "DELETE FROM attendance_exceptions
+            WHERE user_id = :user_id AND work_date BETWEEN :from AND :to AND reviewed_at IS NULL",
+            named_params! {
+                ":user_id": user_id,
+                ":from": from,
+                ":to": to,
+            },
+        )?;
+        for exception in exceptions {
+            tx.execute(
+                "INSERT INTO attendance_exceptions (user_id, work_date, kind, minutes, detected_at)
+                VALUES (:user_id, :work_date, :kind, :minutes, :now)
+                ON CONFLICT (user_id, work_date, kind) DO NOTHING",
+                named_params! {
+                    ":user_id": user_id,
+                    ":work_date": exception.work_date,
+                    ":kind": exception.kind.as_str(),
+                    ":minutes": exception.minutes,
+                    ":now": now,
+                },
+            )?;
+        }
+
+        tx.commit()?;
+
+        Ok(())
+    }
+
+    fn list_attendance_exceptions(
+        &self,
+        user_id: Option<i32>,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<AttendanceException>, StorageError> {
+        let conn = self.conn();
+
+        let exceptions = conn
+            .prepare(&format!(
+                "SELECT {} FROM attendance_exceptions
+                WHERE (:user_id IS NULL OR user_id = :user_id) AND work_date BETWEEN :from AND :to
+                ORDER BY work_date, user_id, id",
+                EXCEPTION_COLUMNS
+            ))?
+            .query_map(
+                named_params! {
+                    ":user_id": user_id,
+                    ":from": from,
+                    ":to": to,
+                },
+                read_exception,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(exceptions)
+    }
+
+    fn find_attendance_exception(&self, exception_id: i32) -> Result<Option<AttendanceException>, StorageError> {
+        let exception = self.conn()
+            .query_row(
+                &format!("SELECT {} FROM attendance_exceptions WHERE id = :exception_id", EXCEPTION_COLUMNS),
+                named_params! {
+                    ":exception_id": exception_id,
+                },
+                read_exception,
+            )
+            .optional()?;
+
+        Ok(exception)
+    }
+
+    fn review_attendance_exception(
+        &self,
+        exception_id: i32,
+        reviewer_id: i32,
+        note: Option<&str>,
+        now: NaiveDateTime,
+    ) -> Result<bool, StorageError> {
+        let changed = self.conn().execute(
+            "UPDATE attendance_exceptions SET reviewed_by = :reviewer_id, reviewed_at = :now, review_note = :note
+            WHERE id = :exception_id",
+            named_params! {
+                ":exception_id": exception_id,
+                ":reviewer_id": reviewer_id,
+                ":now": now,
+                ":note": note,
+            },
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    fn insert_period_lock(
+        &self,
+        starts_on: NaiveDate,
+        ends_on: NaiveDate,
+        locked_by: i32,
+        now: NaiveDateTime,
+    ) -> Result<i32, PayPeriodError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let overlapping: Option<(NaiveDate, NaiveDate)> = tx
+            .query_row(
+                "SELECT starts_on, ends_on FROM pay_period_locks
+                WHERE unlocked_at IS NULL AND starts_on <= :ends_on AND ends_on >= :starts_on
+                ORDER BY starts_on
+                LIMIT 1",
+                named_params! {
+                    ":starts_on": starts_on,
+                    ":ends_on": ends_on,
+                },
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+
+        if let Some((starts_on, ends_on)) = overlapping {
+            return Err(PayPeriodError::AlreadyLocked { starts_on, ends_on });
+        }
+
+        tx.execute(
+            "INSERT INTO pay_period_locks (starts_on, ends_on, locked_by, locked_at)
+            VALUES (:starts_on, :ends_on, :locked_by, :now)",
+            named_params! {
+                ":starts_on": starts_on,
+                ":ends_on": ends_on,
+                ":locked_by": locked_by,
+                ":now": now,
+            },
+        )?;
+
+        let lock_id = tx.last_insert_rowid() as i32;
+        tx.commit()?;
+
+        Ok(lock_id)
+    }
+
+    fn find_period_lock(&self, lock_id: i32) -> Result<Option<PeriodLock>, StorageError> {
+        let lock = self.conn()
+            .query_row(
+                &format!("SELECT {} FROM pay_period_locks WHERE id = :lock_id", PERIOD_LOCK_COLUMNS),
+                named_params! {
+                    ":lock_id": lock_id,
+                },
+                read_period_lock,
+            )
+            .optional()?;
+
+        Ok(lock)
+    }
+
+    fn list_period_locks(&self, from: NaiveDate, to: NaiveDate) -> Result<Vec<PeriodLock>, StorageError> {
+        let conn = self.conn();
+
+        let locks = conn
+            .prepare(&format!(
+                "SELECT {} FROM pay_period_locks
+                WHERE starts_on <= :to AND ends_on >= :from
+                ORDER BY starts_on, id",
+                PERIOD_LOCK_COLUMNS
+            ))?
+            .query_map(
+                named_params! {
+                    ":from": from,
+                    ":to": to,
+                },
+                read_period_lock,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(locks)
+    }
+
+    fn unlock_period(
+        &self,
+        lock_id: i32,
+        unlocked_by: i32,
+        reason: &str,
+        now: NaiveDateTime,
+    ) -> Result<bool, StorageError> {
+        let changed = self.conn().execute(
+            "UPDATE pay_period_locks SET unlocked_by = :unlocked_by, unlocked_at = :now, unlock_reason = :reason
+            WHERE id = :lock_id AND unlocked_at IS NULL",
+            named_params! {
+                ":lock_id": lock_id,
+                ":unlocked_by": unlocked_by,
+                ":now": now,
+                ":reason": reason,
+            },
+        )?;
+
+        Ok(changed > 0)
+    }
+
+    fn insert_correction(
+        &self,
+        user_id: i32,
+        correction: &NewAttendanceCorrection,
+        now: NaiveDateTime,
+    ) -> Result<i32, StorageError> {
+        let conn = self.conn();
+
+        conn.execute(
+            "INSERT INTO attendance_corrections (user_id, event_id, event_type, event_time, reason, requested_at)
+            VALUES (:user_id, :event_id, :event_type, :event_time, :reason, :now)",
+            named_params! {
+                ":user_id": user_id,
+                ":event_id": correction.event_id,
+                ":event_type": correction.event_type.as_str(),
+                ":event_time": correction.event_time,
+                ":reason": correction.reason,
+                ":now": now,
+            },
+        )?;
+
+        Ok(conn.last_insert_rowid() as i32)
+    }
+
+    fn find_correction(&self, correction_id: i32) -> Result<Option<AttendanceCorrection>, StorageError> {
+        let correction = self.conn()
+            .query_row(
+                &format!(
+                    "SELECT {} FROM attendance_corrections c WHERE c.id = :correction_id",
+                    CORRECTION_COLUMNS
+                ),
+                named_params! {
+                    ":correction_id": correction_id,
+                },
+                read_correction,
+            )
+            .optional()?;
+
+        Ok(correction)
+    }
+
+    fn list_corrections(
+        &self,
+        user_id: Option<i32>,
+        supervisor_id: Option<i32>,
+        status: Option<CorrectionStatus>,
+    ) -> Result<Vec<AttendanceCorrection>, StorageError> {
+        let conn = self.conn();
+
+        let corrections = conn
+            .prepare(&format!(
+                "SELECT {} FROM attendance_corrections c
+                JOIN users u ON u.id = c.user_id
+                WHERE (:user_id IS NULL OR c.user_id = :user_id)
+                AND (:supervisor_id IS NULL OR u.supervisor_id = :supervisor_id)
+                AND (:status IS NULL OR c.status = :status)
+                ORDER BY c.requested_at, c.id",
+                CORRECTION_COLUMNS
+            ))?
+            .query_map(
+                named_params! {
+                    ":user_id": user_id,
+                    ":supervisor_id": supervisor_id,
+                    ":status": status.map(|status| status.as_str()),
+                },
+                read_correction,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(corrections)
+    }
+
+    fn decide_correction(
+        &self,
+        correction_id: i32,
+        status: CorrectionStatus,
+        decided_by: i32,
+        note: Option<&str>,
+        now: NaiveDateTime,
+    ) -> Result<bool, CorrectionError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let changed = tx.execute(
+            "UPDATE attendance_corrections
+            SET status = :status, decided_by = :decided_by, decided_at = :now, decision_note = :note
+            WHERE id = :correction_id AND status = 'pending'",
+            named_params! {
+                ":correction_id": correction_id,
+                ":status": status.as_str(),
+                ":decided_by": decided_by,
+                ":now": now,
+                ":note": note,
+            },
+        )?;
+
+        if changed == 0 {
+            return Ok(false);
+        }
+
+        if status == CorrectionStatus::Approved {
+            tx.execute(
+                "INSERT INTO attendance_events (user_id, event_type, event_time, corrects_event_id, correction_id)
+                SELECT user_id, event_type, event_time, event_id, id FROM attendance_corrections
+                WHERE id = :correction_id",
+                named_params! {
+                    ":correction_id": correction_id,
+                },
+            )?;
+            tx.execute(
+                "UPDATE attendance_corrections SET adjusted_event_id = :event_id WHERE id = :correction_id",
+                named_params! {
+                    ":correction_id": correction_id,
+                    ":event_id": tx.last_insert_rowid(),
+                },
+            )?;
+            check_corrected_sequence(&tx, correction_id)?;
+        }
+
+        tx.commit()?;
+
+        Ok(true)
+    }
+
+    fn insert_leave_accrual(
+        &self,
+        user_id: i32,
+        leave_type: LeaveType,
+        days: f64,
+        note: Option<&str>,
+        created_by: i32,
+        now: NaiveDateTime,
+    ) -> Result<i32, StorageError> {
+        let conn = self.conn();
+
+        conn.execute(
+            "INSERT INTO leave_accruals (user_id, leave_type, days, note, created_by, created_at)
+            VALUES (:user_id, :leave_type, :days, :note, :created_by, :now)",
+            named_params! {
+                ":user_id": user_id,
+                ":leave_type": leave_type.as_str(),
+                ":days": days,
+                ":note": note,
+                ":created_by": created_by,
+                ":now": now,
+            },
+        )?;
+
+        Ok(conn.last_insert_rowid() as i32)
+    }
+
+    fn list_leave_accruals(&self, user_id: i32) -> Result<Vec<LeaveAccrual>, StorageError> {
+        let conn = self.conn();
+
+        let accruals = conn
+            .prepare(&format!(
+                "SELECT {} FROM leave_accruals WHERE user_id = :user_id ORDER BY created_at, id",
+                ACCRUAL_COLUMNS
+            ))?
+            .query_map(
+                named_params! {
+                    ":user_id": user_id,
+                },
+                read_accrual,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(accruals)
+    }
+
+    fn insert_leave(&self, user_id: i32, leave: &NewLeave, now: NaiveDateTime) -> Result<i32, LeaveError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        check_leave(&tx, user_id, leave.leave_type, leave.starts_on, leave.ends_on, leave.days, None)?;
+
+        tx.execute(
+            "INSERT INTO leave_requests (user_id, leave_type, starts_on, ends_on, days, reason, requested_at)
+            VALUES (:user_id, :leave_type, :starts_on, :ends_on, :days, :reason, :now)",
+            named_params! {
+                ":user_id": user_id,
+                ":leave_type": leave.leave_type.as_str(),
+                ":starts_on": leave.starts_on,
+                ":ends_on": leave.ends_on,
+                ":days": leave.days,
+                ":reason": leave.reason,
+                ":now": now,
+            },
+        )?;
+
+        let leave_id = tx.last_insert_rowid() as i32;
+        tx.commit()?;
+
+        Ok(leave_id)
+    }
+
+    fn find_leave(&self, leave_id: i32) -> Result<Option<Leave>, StorageError> {
+        let leave = self.conn()
+            .query_row(
+                &format!("SELECT {} FROM leave_requests l WHERE l.id = :leave_id", LEAVE_COLUMNS),
+                named_params! {
+                    ":leave_id": leave_id,
+                },
+                read_leave,
+            )
+            .optional()?;
+
+        Ok(leave)
+    }
+
+    fn list_leaves(
+        &self,
+        user_id: Option<i32>,
+        supervisor_id: Option<i32>,
+        status: Option<LeaveStatus>,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<Vec<Leave>, StorageError> {
+        let conn = self.conn();
+
+        let leaves = conn
+            .prepare(&format!(
+                "SELECT {} FROM leave_requests l
+                JOIN users u ON u.id = l.user_id
+                WHERE (:user_id IS NULL OR l.user_id = :user_id)
+                AND (:supervisor_id IS NULL OR u.supervisor_id = :supervisor_id)
+                AND (:status IS NULL OR l.status = :status)
+                AND (:from IS NULL OR l.ends_on >= :from)
+                AND (:to IS NULL OR l.starts_on <= :to)
+                ORDER BY l.starts_on, l.id",
+                LEAVE_COLUMNS
+            ))?
+            .query_map(
+                named_params! {
+                    ":user_id": user_id,
+                    ":supervisor_id": supervisor_id,
+                    ":status": status.map(|status| status.as_str()),
+                    ":from": from,
+                    ":to": to,
+                },
+                read_leave,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(leaves)
+    }
+
+    fn decide_leave(
+        &self,
+        leave_id: i32,
+        status: LeaveStatus,
+        decided_by: i32,
+        note: Option<&str>,
+        now: NaiveDateTime,
+    ) -> Result<bool, LeaveError> {
+        let mut conn = self.conn();
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        if status == LeaveStatus::Approved {
+            let leave = tx
+                .query_row(
+                    &format!(
+                        "SELECT {} FROM leave_requests l WHERE l.id = :leave_id AND l.status = 'pending'",
+                        LEAVE_COLUMNS
+                    ),
+                    named_params! {
+                        ":leave_id": leave_id,
+                    },
+                    read_leave,
+                )
+                .optional()?;
+
+            let Some(leave) = leave else {
+                return Ok(false);
+            };
+            check_leave(
+                &tx,
+                leave.user_id,
+                leave.leave_type,
+                leave.starts_on,
+                leave.ends_on,
+                leave.days,
+                Some(leave_id),
+            )?;
+        }
+
+        let changed = tx.execute(
+            "UPDATE leave_requests
+            SET status = :status, decided_by = :decided_by, decided_at = :now, decision_note = :note
+            WHERE id = :leave_id AND status = 'pending'",
+            named_params! {
+                ":leave_id": leave_id,
+                ":status": status.as_str(),
+                ":decided_by": decided_by,
+                ":now": now,
+                ":note": note,
+            },
+        )?;
+
+        tx.commit()?;
+
+        Ok(changed > 0)
+    }
+
+    fn record_attendance_event(&self, punch: &Punch) -> Result<AttendanceEvent, AttendanceError> {
+        let user_id = punch.user_id;
+        let mut conn = self.conn();
+        // Take the write lock up front so the open-shift check cannot go stale
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+
+        let user: Option<i32> = tx
+            .query_row("SELECT id FROM users WHERE id = ?1", params![user_id], |row| row.get(0))
+            .optional()?;
+
+        if user.is_none() {
+            return Err(AttendanceError::UserNotFound);
+        }
+
+        // A replayed punch that was already stored is not counted twice
+        let existing: Option<(u64, String, NaiveDateTime)> = tx
+            .query_row(
+                "SELECT id, event_type, event_time FROM attendance_events
+                WHERE idempotency_key = ?1",
+                params![punch.idempotency_key],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+
+        if let Some((id, event_type, event_time)) = existing {
+            return Ok(AttendanceEvent {
+                id,
+                user_id,
+                event_type: AttendanceEventType::parse(&event_type).unwrap_or(punch.event_type),
+                event_time,
+                idempotency_key: Some(punch.idempotency_key.clone()),
+                corrects_event_id: None,
+                correction_id: None,
+                queued: false,
+            });
+        }
+
+        let last_event: Option<String> = tx
+            .query_row(
+                &format!(
+                    "SELECT e.event_type FROM attendance_events e
+                    WHERE e.user_id = ?1 AND {}
+                    ORDER BY e.event_time DESC, e.id DESC
+                    LIMIT 1",
+                    CURRENT_EVENT_FILTER
+                ),
+                params![user_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        check_attendance_transition(
+            last_event.as_deref().and_then(AttendanceEventType::parse),
+            punch.event_type,
+        )?;
+
+        tx.execute(
+            "INSERT INTO attendance_events (user_id, event_type, event_time, idempotency_key)
+            VALUES (:user_id, :event_type, :event_time, :idempotency_key)",
+            named_params! {
+                ":user_id": user_id,
+                ":event_type": punch.event_type.as_str(),
+                ":event_time": punch.event_time,
+                ":idempotency_key": punch.idempotency_key,
+            },
+        )?;
+
+        let id = tx.last_insert_rowid() as u64;
+        tx.commit()?;
+
+        Ok(AttendanceEvent {
+            id,
+            user_id,
+            event_type: punch.event_type,
+            event_time: punch.event_time,
+            idempotency_key: Some(punch.idempotency_key.clone()),
+            corrects_event_id: None,
+            correction_id: None,
+            queued: false,
+        })
+    }
+
+    fn last_attendance_event_type(&self, user_id: i32) -> Result<Option<AttendanceEventType>, StorageError> {
+        let event_type: Option<String> = self.conn()
+            .query_row(
+                &format!(
+                    "SELECT e.event_type FROM attendance_events e
+                    WHERE e.user_id = :user_id AND {}
+                    ORDER BY e.event_time DESC, e.id DESC
+                    LIMIT 1",
+                    CURRENT_EVENT_FILTER
+                ),
+                named_params! {
+                    ":user_id": user_id,
+                },
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(event_type.as_deref().and_then(AttendanceEventType::parse))
+    }
+
+    fn find_attendance_event(&self, event_id: u64) -> Result<Option<AttendanceEvent>, StorageError> {
+        let event = self.conn()
+            .query_row(
+                &format!(
+                    "SELECT {} FROM attendance_events e WHERE e.id = :event_id AND {}",
+                    EVENT_COLUMNS, CURRENT_EVENT_FILTER
+                ),
+                named_params! {
+                    ":event_id": event_id,
+                },
+                read_event,
+            )
+            .optional()?;
+
+        Ok(event)
+    }
+
+    fn attendance_events(
+        &self,
+        user_id: i32,
+        from: Option<NaiveDateTime>,
+        to: Option<NaiveDateTime>,
+    ) -> Result<Vec<AttendanceEvent>, StorageError> {
+        let conn = self.conn();
+
+        let events = conn
+            .prepare(&format!(
+                "SELECT {} FROM attendance_events e
+                WHERE e.user_id = :user_id AND {}
+                AND (:from IS NULL OR e.event_time >= :from)
+                AND (:to IS NULL OR e.event_time < :to)
+                ORDER BY e.event_time DESC, e.id DESC",
+                EVENT_COLUMNS, CURRENT_EVENT_FILTER
+            ))?
+            .query_map(
+                named_params! {
+                    ":user_id": user_id,
+                    ":from": from,
+                    ":to": to,
+                },
+                read_event,
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(events)
+    }
+}
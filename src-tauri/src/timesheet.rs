@@ -0,0 +1,1065 @@
+// src/timesheet.rs
+
+use std::collections::BTreeMap;
+use std::env;
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Serialize, Deserialize};
+
+use crate::database::AttendanceEventType;
+use crate::pay_period::{PayPeriod, PayPeriodConfig};
+
+const DEFAULT_AUTO_BREAK_MINUTES: i64 = 30;
+const DEFAULT_DAILY_OVERTIME_MINUTES: i64 = 8 * 60;
+const DEFAULT_WEEKLY_OVERTIME_MINUTES: i64 = 40 * 60;
+const DEFAULT_NIGHT_HOURS: &str = "22:00-06:00";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    // Half an interval or more rounds up
+    Nearest,
+    Up,
+    Down,
+}
+
+impl RoundingMode {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "nearest" => Some(RoundingMode::Nearest),
+            "up" => Some(RoundingMode::Up),
+            "down" => Some(RoundingMode::Down),
+            _ => None,
+        }
+    }
+}
+
+// A punch in local time, which is what days, weeks and night hours are
+// counted in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimesheetEvent {
+    pub event_type: AttendanceEventType,
+    pub at: NaiveDateTime,
+}
+
+// Hours for one local day. Work is counted on the day it started, so an
+// overnight shift stays in one piece.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailyHours {
+    pub date: NaiveDate,
+    // Paid time: from clock-in to clock-out, less unpaid breaks
+    pub worked_minutes: i64,
+    pub regular_minutes: i64,
+    pub overtime_minutes: i64,
+    // Breaks short enough to stay paid, included in `worked_minutes`
+    pub paid_break_minutes: i64,
+    // Deducted breaks, including any automatic deduction
+    pub unpaid_break_minutes: i64,
+    // Worked time inside the night differential hours
+    pub night_minutes: i64,
+}
+
+// Hours for one pay period. Weekly overtime lands on the day it is earned,
+// so a week that spans two periods counts its earlier days toward the
+// threshold and its overtime in whichever period the later days fall.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PayPeriodHours {
+    pub starts_on: NaiveDate,
+    pub ends_on: NaiveDate,
+    pub worked_minutes: i64,
+    pub regular_minutes: i64,
+    pub overtime_minutes: i64,
+    pub paid_break_minutes: i64,
+    pub unpaid_break_minutes: i64,
+    pub night_minutes: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Timesheet {
+    pub user_id: i32,
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    // Every day from `from` through `to`, worked or not
+    pub days: Vec<DailyHours>,
+    pub worked_minutes: i64,
+    pub regular_minutes: i64,
+    pub overtime_minutes: i64,
+    pub paid_break_minutes: i64,
+    pub unpaid_break_minutes: i64,
+    pub night_minutes: i64,
+    // Every pay period with a day in the range. Periods cut off by `from`
+    // or `to` only total the days inside it.
+    pub pay_periods: Vec<PayPeriodHours>,
+    // Punches that do not pair up, such as a clock-out without a clock-in.
+    // Their time is not counted until they are corrected.
+    pub unmatched: Vec<TimesheetEvent>,
+    // Clock-in of a shift that has not been clocked out yet
+    pub open_since: Option<NaiveDateTime>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TimesheetRules {
+    // Clock-in and clock-out times are rounded to this interval; zero
+    // keeps them exact. Break punches are never rounded.
+    pub rounding: Duration,
+    pub rounding_mode: RoundingMode,
+    // Breaks up to this long are paid; longer ones are deducted in full
+    pub paid_break: Duration,
+    // Shifts at least this long have at least `auto_break` deducted, for
+    // staff who do not punch their breaks
+    pub auto_break_after: Option<Duration>,
+    pub auto_break: Duration,
+    pub daily_overtime_after: Option<Duration>,
+    // Applies to time that is not already daily overtime
+    pub weekly_overtime_after: Option<Duration>,
+    pub week_starts_on: Weekday,
+    pub pay_periods: PayPeriodConfig,
+    // Local start and end of the night differential hours; the end may be
+    // past midnight
+    pub night: Option<(NaiveTime, NaiveTime)>,
+}
+
+impl Default for TimesheetRules {
+    fn default() -> Self {
+        TimesheetRules {
+            rounding: Duration::zero(),
+            rounding_mode: RoundingMode::Nearest,
+            paid_break: Duration::zero(),
+            auto_break_after: None,
+            auto_break: Duration::minutes(DEFAULT_AUTO_BREAK_MINUTES),
+            daily_overtime_after: Some(Duration::minutes(DEFAULT_DAILY_OVERTIME_MINUTES)),
+            weekly_overtime_after: Some(Duration::minutes(DEFAULT_WEEKLY_OVERTIME_MINUTES)),
+            week_starts_on: Weekday::Mon,
+            pay_periods: PayPeriodConfig::default(),
+            night: parse_night_hours(DEFAULT_NIGHT_HOURS),
+        }
+    }
+}
+
+impl TimesheetRules {
+    pub fn from_env() -> Self {
+        let defaults = TimesheetRules::default();
+        let var = |name: &str| env::var(name).ok().map(|value| value.trim().to_lowercase());
+        let minutes = |name: &str| {
+            var(name)
+                .and_then(|value| value.parse::<i64>().ok())
+                .filter(|minutes| *minutes >= 0)
+                .map(Duration::minutes)
+        };
+        // Zero turns a threshold off
+        let threshold = |name: &str, default: Option<Duration>| match minutes(name) {
+            Some(minutes) if minutes.is_zero() => None,
+            Some(minutes) => Some(minutes),
+            None => default,
+        };
+
+        TimesheetRules {
+            rounding: minutes("TIMESHEET_ROUNDING_MINUTES").unwrap_or(defaults.rounding),
+            rounding_mode: var("TIMESHEET_ROUNDING_MODE")
+                .and_then(|value| RoundingMode::parse(&value))
+                .unwrap_or(defaults.rounding_mode),
+            paid_break: minutes("PAID_BREAK_MINUTES").unwrap_or(defaults.paid_break),
+            auto_break_after: threshold("AUTO_BREAK_AFTER_MINUTES", defaults.auto_break_after),
+            auto_break: minutes("AUTO_BREAK_MINUTES").unwrap_or(defaults.auto_break),
+            daily_overtime_after: threshold("DAILY_OVERTIME_MINUTES", defaults.daily_overtime_after),
+            weekly_overtime_after: threshold("WEEKLY_OVERTIME_MINUTES", defaults.weekly_overtime_after),
+            week_starts_on: var("WEEK_STARTS_ON")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(defaults.week_starts_on),
+            pay_periods: PayPeriodConfig::from_env(),
+            night: match var("NIGHT_DIFFERENTIAL_HOURS").as_deref() {
+                Some("off") => None,
+                Some(value) => parse_night_hours(value).or(defaults.night),
+                None => defaults.night,
+            },
+        }
+    }
+
+    // First day of the overtime week `date` falls in
+    pub fn week_start(&self, date: NaiveDate) -> NaiveDate {
+        let offset = (date.weekday().num_days_from_monday() + 7
+            - self.week_starts_on.num_days_from_monday())
+            % 7;
+        date - Duration::days(i64::from(offset))
+    }
+
+    // Hours for `from` through `to` from one user's `events`. Weekly
+    // overtime counts from the start of the week, so events should reach
+    // back to `week_start(from)`. Events are sorted by time, keeping the
+    // given order for punches at the same time.
+    pub fn compute(
+        &self,
+        user_id: i32,
+        from: NaiveDate,
+        to: NaiveDate,
+        events: &[TimesheetEvent],
+    ) -> Timesheet {
+        let (sessions, unmatched, open) = pair_events(events);
+
+        let mut totals: BTreeMap<NaiveDate, SessionHours> = BTreeMap::new();
+        for session in &sessions {
+            let hours = self.session_hours(session);
+            let day = totals.entry(hours.date).or_insert(SessionHours { date: hours.date, ..SessionHours::default() });
+            day.worked += hours.worked;
+            day.paid_break += hours.paid_break;
+            day.unpaid_break += hours.unpaid_break;
+            day.night += hours.night;
+        }
+
+        let mut worked_days: BTreeMap<NaiveDate, DailyHours> = BTreeMap::new();
+        let mut week: Option<NaiveDate> = None;
+        let mut week_regular = Duration::zero();
+
+        for (date, day) in totals {
+            let daily_overtime = self
+                .daily_overtime_after
+                .map_or(Duration::zero(), |after| (day.worked - after).max(Duration::zero()));
+            let mut regular = day.worked - daily_overtime;
+
+            let week_start = self.week_start(date);
+            if week != Some(week_start) {
+                week = Some(week_start);
+                week_regular = Duration::zero();
+            }
+
+            let weekly_overtime = self.weekly_overtime_after.map_or(Duration::zero(), |after| {
+                (week_regular + regular - after).max(Duration::zero()).min(regular)
+            });
+            regular -= weekly_overtime;
+            week_regular += regular;
+
+            let worked_minutes = day.worked.num_minutes();
+            let overtime_minutes = (daily_overtime + weekly_overtime).num_minutes();
+
+            worked_days.insert(date, DailyHours {
+                date,
+                worked_minutes,
+                regular_minutes: worked_minutes - overtime_minutes,
+                overtime_minutes,
+                paid_break_minutes: day.paid_break.num_minutes(),
+                unpaid_break_minutes: day.unpaid_break.num_minutes(),
+                night_minutes: day.night.num_minutes(),
+            });
+        }
+
+        let in_range = |time: NaiveDateTime| time.date() >= from && time.date() <= to;
+
+        let days: Vec<DailyHours> = from
+            .iter_days()
+            .take_while(|date| *date <= to)
+            .map(|date| {
+                worked_days
+                    .remove(&date)
+                    .unwrap_or(DailyHours { date, ..DailyHours::default() })
+            })
+            .collect();
+
+        Timesheet {
+            user_id,
+            from,
+            to,
+            worked_minutes: days.iter().map(|day| day.worked_minutes).sum(),
+            regular_minutes: days.iter().map(|day| day.regular_minutes).sum(),
+            overtime_minutes: days.iter().map(|day| day.overtime_minutes).sum(),
+            paid_break_minutes: days.iter().map(|day| day.paid_break_minutes).sum(),
+            unpaid_break_minutes: days.iter().map(|day| day.unpaid_break_minutes).sum(),
+            night_minutes: days.iter().map(|day| day.night_minutes).sum(),
+            pay_periods: self
+                .pay_periods
+                .periods(from, to)
+                .into_iter()
+                .map(|period| period_hours(period, &days))
+                .collect(),
+            days,
+            unmatched: unmatched.into_iter().filter(|event| in_range(event.at)).collect(),
+            open_since: open.filter(|start| in_range(*start)),
+        }
+    }
+
+    fn session_hours(&self, session: &WorkSession) -> SessionHours {
+        let start = self.round(session.start);
+        let end = self.round(session.end).max(start);
+        let span = end - start;
+
+        let mut paid_break = Duration::zero();
+        let mut unpaid_break = Duration::zero();
+        let mut unpaid_night = Duration::zero();
+
+        for &(break_start, break_end) in &session.breaks {
+            // Rounding can move the shift edges past a break
+            let break_start = break_start.clamp(start, end);
+            let break_end = break_end.clamp(break_start, end);
+            let length = break_end - break_start;
+
+            if length > self.paid_break {
+                unpaid_break += length;
+                unpaid_night += self.night_overlap(break_start, break_end);
+            } else {
+                paid_break += length;
+            }
+        }
+
+        if let Some(after) = self.auto_break_after {
+            if span >= after && unpaid_break < self.auto_break {
+                unpaid_break = self.auto_break.min(span);
+            }
+        }
+
+        let worked = span - unpaid_break;
+        let night = (self.night_overlap(start, end) - unpaid_night).clamp(Duration::zero(), worked);
+
+        SessionHours {
+            date: start.date(),
+            worked,
+            paid_break,
+            unpaid_break,
+            night,
+        }
+    }
+
+    fn round(&self, time: NaiveDateTime) -> NaiveDateTime {
+        let step = self.rounding.num_seconds();
+        if step <= 0 {
+            return time;
+        }
+
+        // Intervals are counted from midnight, so 15 minutes lands on the
+        // quarter hours
+        let seconds = time.and_utc().timestamp();
+        let past = seconds.rem_euclid(step);
+        let rounded = match self.rounding_mode {
+            RoundingMode::Down => seconds - past,
+            RoundingMode::Up if past == 0 => seconds,
+            RoundingMode::Up => seconds - past + step,
+            RoundingMode::Nearest if past * 2 >= step => seconds - past + step,
+            RoundingMode::Nearest => seconds - past,
+        };
+
+        time + Duration::seconds(rounded - seconds)
+    }
+
+    fn night_overlap(&self, start: NaiveDateTime, end: NaiveDateTime) -> Duration {
+        let Some((night_start, night_end)) = self.night else {
+            return Duration::zero();
+        };
+
+        // The night that began the day before can reach into `start`
+        let first = start.date() - Duration::days(1);
+        first
+            .iter_days()
+            .take_while(|date| *date <= end.date())
+            .map(|date| {
+                let window_start = date.and_time(night_start);
+                let mut window_end = date.and_time(night_end);
+                if window_end <= window_start {
+                    window_end += Duration::days(1);
+                }
+                (end.min(window_end) - start.max(window_start)).max(Duration::zero())
+            })
+            .sum()
+    }
+}
+
+fn period_hours(period: PayPeriod, days: &[DailyHours]) -> PayPeriodHours {
+    let days: Vec<&DailyHours> = days
+        .iter()
+        .filter(|day| day.date >= period.starts_on && day.date <= period.ends_on)
+        .collect();
+
+    PayPeriodHours {
+        starts_on: period.starts_on,
+        ends_on: period.ends_on,
+        worked_minutes: days.iter().map(|day| day.worked_minutes).sum(),
+        regular_minutes: days.iter().map(|day| day.regular_minutes).sum(),
+        overtime_minutes: days.iter().map(|day| day.overtime_minutes).sum(),
+        paid_break_minutes: days.iter().map(|day| day.paid_break_minutes).sum(),
+        unpaid_break_minutes: days.iter().map(|day| day.unpaid_break_minutes).sum(),
+        night_minutes: days.iter().map(|day| day.night_minutes).sum(),
+    }
+}
+
+// "HH:MM-HH:MM"
+fn parse_night_hours(value: &str) -> Option<(NaiveTime, NaiveTime)> {
+    let (start, end) = value.split_once('-')?;
+    let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+    let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+    Some((start, end))
+}
+
+// One clock-in to clock-out, with the breaks taken in between
+struct WorkSession {
+    start: NaiveDateTime,
+    end: NaiveDateTime,
+    breaks: Vec<(NaiveDateTime, NaiveDateTime)>,
+}
+
+#[derive(Default)]
+struct SessionHours {
+    date: NaiveDate,
+    worked: Duration,
+    paid_break: Duration,
+    unpaid_break: Duration,
+    night: Duration,
+}
+
+struct OpenSession {
+    events: Vec<TimesheetEvent>,
+    breaks: Vec<(NaiveDateTime, NaiveDateTime)>,
+    break_start: Option<NaiveDateTime>,
+}
+
+// Splits the events into complete sessions, punches that fit none, and the
+// start of a session still open at the end. A clock-in that is followed
+// by another clock-in leaves its whole session unmatched.
+fn pair_events(events: &[TimesheetEvent]) -> (Vec<WorkSession>, Vec<TimesheetEvent>, Option<NaiveDateTime>) {
+    let mut events = events.to_vec();
+    events.sort_by_key(|event| event.at);
+
+    let mut sessions = Vec::new();
+    let mut unmatched = Vec::new();
+    let mut open: Option<OpenSession> = None;
+
+    for event in events {
+        match (event.event_type, open.as_mut()) {
+            (AttendanceEventType::In, current) => {
+                if let Some(abandoned) = current.map(|session| std::mem::take(&mut session.events)) {
+                    unmatched.extend(abandoned);
+                }
+                open = Some(OpenSession {
+                    events: vec![event],
+                    breaks: Vec::new(),
+                    break_start: None,
+                });
+            }
+            (AttendanceEventType::Out, Some(session)) => {
+                let mut breaks = std::mem::take(&mut session.breaks);
+                // A break still running at clock-out ends with the shift
+                if let Some(break_start) = session.break_start {
+                    breaks.push((break_start, event.at));
+                }
+                sessions.push(WorkSession {
+                    start: session.events[0].at,
+                    end: event.at,
+                    breaks,
+                });
+                open = None;
+            }
+            (AttendanceEventType::BreakStart, Some(session)) if session.break_start.is_none() => {
+                session.break_start = Some(event.at);
+                session.events.push(event);
+            }
+            (AttendanceEventType::BreakEnd, Some(session)) if session.break_start.is_some() => {
+                if let Some(break_start) = session.break_start.take() {
+                    session.breaks.push((break_start, event.at));
+                }
+                session.events.push(event);
+            }
+            _ => unmatched.push(event),
+        }
+    }
+
+    let open_since = open.and_then(|session| session.events.first().map(|event| event.at));
+    unmatched.sort_by_key(|event| event.at);
+
+    (sessions, unmatched, open_since)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pay_period::PayFrequency;
+
+    fn at(date: NaiveDate, hour: u32, minute: u32) -> NaiveDateTime {
+        date.and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    fn day(d: u32) -> NaiveDate {
+        // 2024-06-03 is a Monday
+        NaiveDate::from_ymd_opt(2024, 6, d).unwrap()
+    }
+
+    fn event(event_type: AttendanceEventType, time: NaiveDateTime) -> TimesheetEvent {
+        TimesheetEvent { event_type, at: time }
+    }
+
+    fn shift(date: NaiveDate, start: (u32, u32), end: (u32, u32)) -> Vec<TimesheetEvent> {
+        let start = at(date, start.0, start.1);
+        let mut end = at(date, end.0, end.1);
+        if end <= start {
+            end += Duration::days(1);
+        }
+        vec![event(AttendanceEventType::In, start), event(AttendanceEventType::Out, end)]
+    }
+
+    // No rounding, breaks, overtime or night hours, so each test turns on
+    // only what it is about
+    fn plain() -> TimesheetRules {
+        TimesheetRules {
+            rounding: Duration::zero(),
+            rounding_mode: RoundingMode::Nearest,
+            paid_break: Duration::zero(),
+            auto_break_after: None,
+            auto_break: Duration::zero(),
+            daily_overtime_after: None,
+            weekly_overtime_after: None,
+            week_starts_on: Weekday::Mon,
+            pay_periods: PayPeriodConfig::default(),
+            night: None,
+        }
+    }
+
+    fn one_day(rules: &TimesheetRules, date: NaiveDate, events: &[TimesheetEvent]) -> DailyHours {
+        rules.compute(1, date, date, events).days.remove(0)
+    }
+
+    #[test]
+    fn counts_a_simple_shift() {
+        let sheet = plain().compute(1, day(3), day(3), &shift(day(3), (9, 0), (17, 0)));
+
+        assert_eq!(sheet.days.len(), 1);
+        assert_eq!(sheet.days[0].worked_minutes, 480);
+        assert_eq!(sheet.days[0].regular_minutes, 480);
+        assert_eq!(sheet.worked_minutes, 480);
+        assert!(sheet.unmatched.is_empty());
+        assert_eq!(sheet.open_since, None);
+    }
+
+    #[test]
+    fn empty_events_give_zero_days_for_the_whole_range() {
+        let sheet = plain().compute(1, day(3), day(9), &[]);
+
+        assert_eq!(sheet.days.len(), 7);
+        assert!(sheet.days.iter().all(|d| d.worked_minutes == 0));
+        assert_eq!(sheet.days[6].date, day(9));
+        assert_eq!(sheet.worked_minutes, 0);
+    }
+
+    #[test]
+    fn event_order_does_not_matter() {
+        let mut events = shift(day(3), (9, 0), (12, 0));
+        events.extend(shift(day(3), (13, 0), (17, 0)));
+        events.reverse();
+
+        let hours = one_day(&plain(), day(3), &events);
+
+        assert_eq!(hours.worked_minutes, 420);
+    }
+
+    #[test]
+    fn rounds_to_the_nearest_interval() {
+        let rules = TimesheetRules { rounding: Duration::minutes(15), ..plain() };
+
+        // 08:52 -> 08:45 and 17:08 -> 17:15
+        assert_eq!(one_day(&rules, day(3), &shift(day(3), (8, 52), (17, 8))).worked_minutes, 510);
+        // 08:53 -> 09:00 and 17:07 -> 17:00
+        assert_eq!(one_day(&rules, day(3), &shift(day(3), (8, 53), (17, 7))).worked_minutes, 480);
+    }
+
+    #[test]
+    fn nearest_rounding_goes_up_at_exactly_half_an_interval() {
+        let rules = TimesheetRules { rounding: Duration::minutes(15), ..plain() };
+        let events = vec![
+            event(AttendanceEventType::In, at(day(3), 9, 0)),
+            event(AttendanceEventType::Out, day(3).and_hms_opt(17, 7, 30).unwrap()),
+        ];
+
+        assert_eq!(one_day(&rules, day(3), &events).worked_minutes, 495);
+    }
+
+    #[test]
+    fn rounds_up_and_down() {
+        let up = TimesheetRules { rounding: Duration::minutes(15), rounding_mode: RoundingMode::Up, ..plain() };
+        let down = TimesheetRules { rounding_mode: RoundingMode::Down, ..up };
+        let events = shift(day(3), (8, 50), (17, 5));
+
+        // 09:00 to 17:15
+        assert_eq!(one_day(&up, day(3), &events).worked_minutes, 495);
+        // 08:45 to 17:00
+        assert_eq!(one_day(&down, day(3), &events).worked_minutes, 495);
+        // Punches already on the interval stay put
+        assert_eq!(one_day(&up, day(3), &shift(day(3), (9, 0), (17, 0))).worked_minutes, 480);
+        assert_eq!(one_day(&down, day(3), &shift(day(3), (9, 0), (17, 0))).worked_minutes, 480);
+    }
+
+    #[test]
+    fn rounding_never_makes_time_negative() {
+        let rules = TimesheetRules { rounding: Duration::minutes(15), rounding_mode: RoundingMode::Up, ..plain() };
+        let events = vec![
+            event(AttendanceEventType::In, at(day(3), 9, 1)),
+            event(AttendanceEventType::Out, at(day(3), 9, 2)),
+        ];
+
+        assert_eq!(one_day(&rules, day(3), &events).worked_minutes, 0);
+    }
+
+    #[test]
+    fn rounding_can_move_a_shift_to_the_next_day() {
+        let rules = TimesheetRules { rounding: Duration::minutes(15), ..plain() };
+        let events = shift(day(3), (23, 55), (8, 0));
+
+        let sheet = rules.compute(1, day(3), day(4), &events);
+
+        assert_eq!(sheet.days[0].worked_minutes, 0);
+        assert_eq!(sheet.days[1].worked_minutes, 480);
+    }
+
+    #[test]
+    fn deducts_breaks_longer_than_the_paid_allowance() {
+        let rules = TimesheetRules { paid_break: Duration::minutes(15), ..plain() };
+        let mut events = shift(day(3), (9, 0), (17, 0));
+        events.push(event(AttendanceEventType::BreakStart, at(day(3), 10, 0)));
+        events.push(event(AttendanceEventType::BreakEnd, at(day(3), 10, 15)));
+        events.push(event(AttendanceEventType::BreakStart, at(day(3), 12, 0)));
+        events.push(event(AttendanceEventType::BreakEnd, at(day(3), 12, 45)));
+
+        let hours = one_day(&rules, day(3), &events);
+
+        assert_eq!(hours.paid_break_minutes, 15);
+        assert_eq!(hours.unpaid_break_minutes, 45);
+        assert_eq!(hours.worked_minutes, 435);
+    }
+
+    #[test]
+    fn a_break_longer_than_the_allowance_is_deducted_in_full() {
+        let rules = TimesheetRules { paid_break: Duration::minutes(15), ..plain() };
+        let mut events = shift(day(3), (9, 0), (17, 0));
+        events.push(event(AttendanceEventType::BreakStart, at(day(3), 12, 0)));
+        events.push(event(AttendanceEventType::BreakEnd, at(day(3), 12, 16)));
+
+        let hours = one_day(&rules, day(3), &events);
+
+        assert_eq!(hours.unpaid_break_minutes, 16);
+        assert_eq!(hours.worked_minutes, 464);
+    }
+
+    #[test]
+    fn break_punches_are_not_rounded() {
+        let rules = TimesheetRules { rounding: Duration::minutes(15), ..plain() };
+        let mut events = shift(day(3), (9, 0), (17, 0));
+        events.push(event(AttendanceEventType::BreakStart, at(day(3), 12, 2)));
+        events.push(event(AttendanceEventType::BreakEnd, at(day(3), 12, 34)));
+
+        assert_eq!(one_day(&rules, day(3), &events).unpaid_break_minutes, 32);
+    }
+
+    #[test]
+    fn a_break_open_at_clock_out_ends_with_the_shift() {
+        let mut events = shift(day(3), (9, 0), (17, 0));
+        events.push(event(AttendanceEventType::BreakStart, at(day(3), 16, 30)));
+
+        let sheet = plain().compute(1, day(3), day(3), &events);
+
+        assert_eq!(sheet.days[0].unpaid_break_minutes, 30);
+        assert_eq!(sheet.days[0].worked_minutes, 450);
+        assert!(sheet.unmatched.is_empty());
+    }
+
+    #[test]
+    fn auto_deducts_a_break_on_long_shifts() {
+        let rules = TimesheetRules {
+            auto_break_after: Some(Duration::hours(6)),
+            auto_break: Duration::minutes(30),
+            ..plain()
+        };
+
+        // Long enough
+        let hours = one_day(&rules, day(3), &shift(day(3), (9, 0), (17, 0)));
+        assert_eq!(hours.unpaid_break_minutes, 30);
+        assert_eq!(hours.worked_minutes, 450);
+
+        // Exactly at the threshold
+        assert_eq!(one_day(&rules, day(3), &shift(day(3), (9, 0), (15, 0))).worked_minutes, 330);
+
+        // Too short
+        assert_eq!(one_day(&rules, day(3), &shift(day(3), (9, 0), (14, 59))).worked_minutes, 359);
+    }
+
+    #[test]
+    fn auto_deduction_tops_up_a_short_recorded_break() {
+        let rules = TimesheetRules {
+            auto_break_after: Some(Duration::hours(6)),
+            auto_break: Duration::minutes(30),
+            ..plain()
+        };
+        let mut events = shift(day(3), (9, 0), (17, 0));
+        events.push(event(AttendanceEventType::BreakStart, at(day(3), 12, 0)));
+        events.push(event(AttendanceEventType::BreakEnd, at(day(3), 12, 20)));
+
+        assert_eq!(one_day(&rules, day(3), &events).unpaid_break_minutes, 30);
+
+        // A longer recorded break is not added to
+        let mut events = shift(day(3), (9, 0), (17, 0));
+        events.push(event(AttendanceEventType::BreakStart, at(day(3), 12, 0)));
+        events.push(event(AttendanceEventType::BreakEnd, at(day(3), 13, 0)));
+
+        assert_eq!(one_day(&rules, day(3), &events).unpaid_break_minutes, 60);
+    }
+
+    #[test]
+    fn a_paid_break_does_not_count_against_the_auto_deduction() {
+        let rules = TimesheetRules {
+            paid_break: Duration::minutes(15),
+            auto_break_after: Some(Duration::hours(6)),
+            auto_break: Duration::minutes(30),
+            ..plain()
+        };
+        let mut events = shift(day(3), (9, 0), (17, 0));
+        events.push(event(AttendanceEventType::BreakStart, at(day(3), 10, 0)));
+        events.push(event(AttendanceEventType::BreakEnd, at(day(3), 10, 10)));
+
+        let hours = one_day(&rules, day(3), &events);
+
+        assert_eq!(hours.paid_break_minutes, 10);
+        assert_eq!(hours.unpaid_break_minutes, 30);
+        assert_eq!(hours.worked_minutes, 450);
+    }
+
+    #[test]
+    fn daily_overtime_starts_after_the_threshold() {
+        let rules = TimesheetRules { daily_overtime_after: Some(Duration::hours(8)), ..plain() };
+
+        let hours = one_day(&rules, day(3), &shift(day(3), (8, 0), (18, 30)));
+        assert_eq!(hours.regular_minutes, 480);
+        assert_eq!(hours.overtime_minutes, 150);
+
+        let hours = one_day(&rules, day(3), &shift(day(3), (9, 0), (17, 0)));
+        assert_eq!(hours.regular_minutes, 480);
+        assert_eq!(hours.overtime_minutes, 0);
+    }
+
+    #[test]
+    fn daily_overtime_adds_up_split_shifts() {
+        let rules = TimesheetRules { daily_overtime_after: Some(Duration::hours(8)), ..plain() };
+        let mut events = shift(day(3), (6, 0), (11, 0));
+        events.extend(shift(day(3), (14, 0), (19, 0)));
+
+        let hours = one_day(&rules, day(3), &events);
+
+        assert_eq!(hours.worked_minutes, 600);
+        assert_eq!(hours.overtime_minutes, 120);
+    }
+
+    #[test]
+    fn weekly_overtime_applies_once_the_week_is_full() {
+        let rules = TimesheetRules { weekly_overtime_after: Some(Duration::hours(40)), ..plain() };
+        // Six 8-hour days, Monday to Saturday
+        let events: Vec<_> = (3..=8).flat_map(|d| shift(day(d), (9, 0), (17, 0))).collect();
+
+        let sheet = rules.compute(1, day(3), day(8), &events);
+
+        assert_eq!(sheet.days[4].overtime_minutes, 0);
+        assert_eq!(sheet.days[5].regular_minutes, 0);
+        assert_eq!(sheet.days[5].overtime_minutes, 480);
+        assert_eq!(sheet.regular_minutes, 2400);
+        assert_eq!(sheet.overtime_minutes, 480);
+    }
+
+    #[test]
+    fn weekly_overtime_splits_the_day_that_crosses_the_threshold() {
+        let rules = TimesheetRules { weekly_overtime_after: Some(Duration::hours(40)), ..plain() };
+        // Four 9-hour days then 6 hours on Friday
+        let mut events: Vec<_> = (3..=6).flat_map(|d| shift(day(d), (8, 0), (17, 0))).collect();
+        events.extend(shift(day(7), (8, 0), (14, 0)));
+
+        let sheet = rules.compute(1, day(3), day(7), &events);
+
+        assert_eq!(sheet.days[4].regular_minutes, 240);
+        assert_eq!(sheet.days[4].overtime_minutes, 120);
+    }
+
+    #[test]
+    fn weekly_overtime_does_not_count_daily_overtime_twice() {
+        let rules = TimesheetRules {
+            daily_overtime_after: Some(Duration::hours(8)),
+            weekly_overtime_after: Some(Duration::hours(40)),
+            ..plain()
+        };
+        // Five 10-hour days: 2 hours of daily overtime each, and the 40
+        // regular hours left do not pass the weekly threshold
+        let events: Vec<_> = (3..=7).flat_map(|d| shift(day(d), (8, 0), (18, 0))).collect();
+
+        let sheet = rules.compute(1, day(3), day(7), &events);
+
+        assert_eq!(sheet.regular_minutes, 2400);
+        assert_eq!(sheet.overtime_minutes, 600);
+
+        // A sixth day is all weekly overtime
+        let events: Vec<_> = (3..=8).flat_map(|d| shift(day(d), (8, 0), (18, 0))).collect();
+        let sheet = rules.compute(1, day(3), day(8), &events);
+
+        assert_eq!(sheet.days[5].overtime_minutes, 600);
+        assert_eq!(sheet.regular_minutes, 2400);
+    }
+
+    #[test]
+    fn weekly_overtime_resets_at_the_start_of_the_week() {
+        let rules = TimesheetRules { weekly_overtime_after: Some(Duration::hours(40)), ..plain() };
+        // Monday the 3rd through Monday the 10th, 8 hours each
+        let events: Vec<_> = (3..=10).flat_map(|d| shift(day(d), (9, 0), (17, 0))).collect();
+
+        let sheet = rules.compute(1, day(3), day(10), &events);
+
+        assert_eq!(sheet.days[6].overtime_minutes, 480);
+        assert_eq!(sheet.days[7].overtime_minutes, 0);
+        assert_eq!(sheet.days[7].regular_minutes, 480);
+    }
+
+    #[test]
+    fn weekly_overtime_follows_the_configured_week_start() {
+        let rules = TimesheetRules {
+            weekly_overtime_after: Some(Duration::hours(40)),
+            week_starts_on: Weekday::Sun,
+            ..plain()
+        };
+        // Saturday the 8th ends the week; Sunday the 9th starts a new one
+        let events: Vec<_> = (3..=9).flat_map(|d| shift(day(d), (9, 0), (17, 0))).collect();
+
+        let sheet = rules.compute(1, day(3), day(9), &events);
+
+        assert_eq!(sheet.days[5].overtime_minutes, 480);
+        assert_eq!(sheet.days[6].overtime_minutes, 0);
+    }
+
+    #[test]
+    fn weekly_overtime_counts_days_before_the_range() {
+        let rules = TimesheetRules { weekly_overtime_after: Some(Duration::hours(40)), ..plain() };
+        let events: Vec<_> = (3..=8).flat_map(|d| shift(day(d), (9, 0), (17, 0))).collect();
+
+        // Only Saturday is asked for, but Monday to Friday already filled the week
+        let sheet = rules.compute(1, day(8), day(8), &events);
+
+        assert_eq!(sheet.days.len(), 1);
+        assert_eq!(sheet.overtime_minutes, 480);
+        assert_eq!(sheet.worked_minutes, 480);
+    }
+
+    #[test]
+    fn week_start_finds_the_configured_day() {
+        let monday = plain();
+        let sunday = TimesheetRules { week_starts_on: Weekday::Sun, ..plain() };
+
+        assert_eq!(monday.week_start(day(3)), day(3));
+        assert_eq!(monday.week_start(day(9)), day(3));
+        assert_eq!(sunday.week_start(day(8)), day(2));
+        assert_eq!(sunday.week_start(day(9)), day(9));
+    }
+
+    #[test]
+    fn counts_night_hours_across_midnight() {
+        let rules = TimesheetRules { night: parse_night_hours("22:00-06:00"), ..plain() };
+
+        let hours = one_day(&rules, day(3), &shift(day(3), (20, 0), (4, 0)));
+
+        assert_eq!(hours.worked_minutes, 480);
+        assert_eq!(hours.night_minutes, 360);
+    }
+
+    #[test]
+    fn counts_the_early_morning_end_of_the_previous_night() {
+        let rules = TimesheetRules { night: parse_night_hours("22:00-06:00"), ..plain() };
+
+        let hours = one_day(&rules, day(3), &shift(day(3), (4, 0), (12, 0)));
+
+        assert_eq!(hours.night_minutes, 120);
+    }
+
+    #[test]
+    fn counts_both_ends_of_a_long_night_shift() {
+        let rules = TimesheetRules { night: parse_night_hours("22:00-06:00"), ..plain() };
+        // 05:00 on the 3rd to 23:00 on the 3rd
+        let hours = one_day(&rules, day(3), &shift(day(3), (5, 0), (23, 0)));
+
+        assert_eq!(hours.night_minutes, 120);
+    }
+
+    #[test]
+    fn counts_a_night_window_within_one_day() {
+        let rules = TimesheetRules { night: parse_night_hours("18:00-23:00"), ..plain() };
+
+        let hours = one_day(&rules, day(3), &shift(day(3), (15, 0), (23, 30)));
+
+        assert_eq!(hours.night_minutes, 300);
+    }
+
+    #[test]
+    fn unpaid_breaks_are_not_night_hours() {
+        let rules = TimesheetRules { night: parse_night_hours("22:00-06:00"), ..plain() };
+        let mut events = shift(day(3), (22, 0), (6, 0));
+        events.push(event(AttendanceEventType::BreakStart, at(day(4), 2, 0)));
+        events.push(event(AttendanceEventType::BreakEnd, at(day(4), 2, 30)));
+
+        let hours = one_day(&rules, day(3), &events);
+
+        assert_eq!(hours.worked_minutes, 450);
+        assert_eq!(hours.night_minutes, 450);
+    }
+
+    #[test]
+    fn no_night_hours_when_turned_off() {
+        let hours = one_day(&plain(), day(3), &shift(day(3), (22, 0), (6, 0)));
+
+        assert_eq!(hours.night_minutes, 0);
+    }
+
+    #[test]
+    fn overnight_shifts_count_on_the_day_they_start() {
+        let rules = TimesheetRules { daily_overtime_after: Some(Duration::hours(8)), ..plain() };
+
+        let sheet = rules.compute(1, day(3), day(4), &shift(day(3), (18, 0), (6, 0)));
+
+        assert_eq!(sheet.days[0].worked_minutes, 720);
+        assert_eq!(sheet.days[0].overtime_minutes, 240);
+        assert_eq!(sheet.days[1].worked_minutes, 0);
+    }
+
+    #[test]
+    fn reports_a_clock_out_without_a_clock_in() {
+        let mut events = vec![event(AttendanceEventType::Out, at(day(3), 8, 0))];
+        events.extend(shift(day(3), (9, 0), (17, 0)));
+
+        let sheet = plain().compute(1, day(3), day(3), &events);
+
+        assert_eq!(sheet.worked_minutes, 480);
+        assert_eq!(sheet.unmatched, vec![event(AttendanceEventType::Out, at(day(3), 8, 0))]);
+    }
+
+    #[test]
+    fn a_clock_in_followed_by_another_leaves_the_first_session_unmatched() {
+        let events = vec![
+            event(AttendanceEventType::In, at(day(3), 9, 0)),
+            event(AttendanceEventType::BreakStart, at(day(3), 12, 0)),
+            event(AttendanceEventType::BreakEnd, at(day(3), 12, 30)),
+            event(AttendanceEventType::In, at(day(3), 13, 0)),
+            event(AttendanceEventType::Out, at(day(3), 17, 0)),
+        ];
+
+        let sheet = plain().compute(1, day(3), day(3), &events);
+
+        assert_eq!(sheet.worked_minutes, 240);
+        assert_eq!(sheet.unmatched.len(), 3);
+        assert_eq!(sheet.unmatched[0].at, at(day(3), 9, 0));
+    }
+
+    #[test]
+    fn reports_break_punches_that_do_not_pair() {
+        let mut events = shift(day(3), (9, 0), (17, 0));
+        // Outside any shift
+        events.push(event(AttendanceEventType::BreakStart, at(day(3), 18, 0)));
+        // An end with no start, and a second start while on a break
+        events.push(event(AttendanceEventType::BreakEnd, at(day(3), 10, 0)));
+        events.push(event(AttendanceEventType::BreakStart, at(day(3), 12, 0)));
+        events.push(event(AttendanceEventType::BreakStart, at(day(3), 12, 10)));
+        events.push(event(AttendanceEventType::BreakEnd, at(day(3), 12, 30)));
+
+        let sheet = plain().compute(1, day(3), day(3), &events);
+
+        let unmatched: Vec<_> = sheet.unmatched.iter().map(|e| e.at).collect();
+        assert_eq!(unmatched, vec![at(day(3), 10, 0), at(day(3), 12, 10), at(day(3), 18, 0)]);
+        assert_eq!(sheet.days[0].unpaid_break_minutes, 30);
+    }
+
+    #[test]
+    fn an_open_shift_is_reported_but_not_counted() {
+        let mut events = shift(day(3), (9, 0), (17, 0));
+        events.push(event(AttendanceEventType::In, at(day(4), 9, 0)));
+
+        let sheet = plain().compute(1, day(3), day(4), &events);
+
+        assert_eq!(sheet.worked_minutes, 480);
+        assert_eq!(sheet.open_since, Some(at(day(4), 9, 0)));
+        assert!(sheet.unmatched.is_empty());
+    }
+
+    #[test]
+    fn leaves_out_punches_and_open_shifts_outside_the_range() {
+        let events = vec![
+            event(AttendanceEventType::Out, at(day(2), 8, 0)),
+            event(AttendanceEventType::In, at(day(5), 9, 0)),
+        ];
+
+        let sheet = plain().compute(1, day(3), day(4), &events);
+
+        assert!(sheet.unmatched.is_empty());
+        assert_eq!(sheet.open_since, None);
+    }
+
+    #[test]
+    fn totals_add_up_the_days() {
+        let rules = TimesheetRules {
+            paid_break: Duration::minutes(10),
+            daily_overtime_after: Some(Duration::hours(8)),
+            night: parse_night_hours("22:00-06:00"),
+            ..plain()
+        };
+        let mut events = shift(day(3), (9, 0), (19, 0));
+        events.push(event(AttendanceEventType::BreakStart, at(day(3), 12, 0)));
+        events.push(event(AttendanceEventType::BreakEnd, at(day(3), 12, 10)));
+        events.extend(shift(day(4), (21, 0), (5, 0)));
+        events.push(event(AttendanceEventType::BreakStart, at(day(5), 1, 0)));
+        events.push(event(AttendanceEventType::BreakEnd, at(day(5), 1, 30)));
+
+        let sheet = rules.compute(1, day(3), day(4), &events);
+
+        assert_eq!(sheet.worked_minutes, 600 + 450);
+        assert_eq!(sheet.overtime_minutes, 120);
+        assert_eq!(sheet.regular_minutes, 480 + 450);
+        assert_eq!(sheet.paid_break_minutes, 10);
+        assert_eq!(sheet.unpaid_break_minutes, 30);
+        assert_eq!(sheet.night_minutes, 390);
+    }
+
+    #[test]
+    fn pay_periods_carry_weekly_overtime_across_the_boundary() {
+        let rules = TimesheetRules {
+            weekly_overtime_after: Some(Duration::hours(40)),
+            pay_periods: PayPeriodConfig { frequency: PayFrequency::SemiMonthly, ..PayPeriodConfig::default() },
+            ..plain()
+        };
+        // Monday the 10th through Monday the 17th; the week ends on Sunday
+        // the 16th, the day after the first period closes
+        let events: Vec<_> = (10..=17).flat_map(|d| shift(day(d), (9, 0), (17, 0))).collect();
+
+        let sheet = rules.compute(1, day(10), day(17), &events);
+
+        assert_eq!(sheet.pay_periods.len(), 2);
+        let first = &sheet.pay_periods[0];
+        assert_eq!((first.starts_on, first.ends_on), (day(1), day(15)));
+        assert_eq!((first.worked_minutes, first.regular_minutes, first.overtime_minutes), (2880, 2400, 480));
+        let second = &sheet.pay_periods[1];
+        assert_eq!((second.starts_on, second.ends_on), (day(16), day(30)));
+        assert_eq!((second.worked_minutes, second.regular_minutes, second.overtime_minutes), (960, 480, 480));
+        assert_eq!(
+            sheet.pay_periods.iter().map(|period| period.overtime_minutes).sum::<i64>(),
+            sheet.overtime_minutes
+        );
+
+        // The earlier days still count toward the week when only the second
+        // period is asked for
+        let sheet = rules.compute(1, day(16), day(30), &events);
+
+        assert_eq!(sheet.pay_periods.len(), 1);
+        assert_eq!(sheet.pay_periods[0].overtime_minutes, 480);
+        assert_eq!(sheet.pay_periods[0].worked_minutes, 960);
+    }
+
+    #[test]
+    fn pay_periods_cover_the_whole_range() {
+        // Bi-weekly from Monday 2024-01-01, so the 3rd starts a period
+        let sheet = plain().compute(1, day(10), day(20), &shift(day(18), (9, 0), (17, 0)));
+
+        let ranges: Vec<_> = sheet.pay_periods.iter().map(|period| (period.starts_on, period.ends_on)).collect();
+        assert_eq!(ranges, vec![(day(3), day(16)), (day(17), day(30))]);
+        assert_eq!(sheet.pay_periods[0].worked_minutes, 0);
+        assert_eq!(sheet.pay_periods[1].worked_minutes, 480);
+    }
+
+    #[test]
+    fn parses_night_hours() {
+        assert_eq!(
+            parse_night_hours("22:00-06:00"),
+            Some((NaiveTime::from_hms_opt(22, 0, 0).unwrap(), NaiveTime::from_hms_opt(6, 0, 0).unwrap()))
+        );
+        assert_eq!(parse_night_hours(" 21:30 - 05:15 ").map(|(start, _)| start), NaiveTime::from_hms_opt(21, 30, 0));
+        assert_eq!(parse_night_hours("22:00"), None);
+        assert_eq!(parse_night_hours("late-early"), None);
+    }
+}
@@ -0,0 +1,434 @@
+// src/validation.rs
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use serde::Serialize;
+
+use crate::database::{CorrectionKind, CorrectionRequest, LeaveType, SchedulePattern};
+
+const USERNAME_MIN_LEN: usize = 3;
+const USERNAME_MAX_LEN: usize = 32;
+const PASSWORD_MIN_LEN: usize = 8;
+// bcrypt (still selectable via PASSWORD_HASH) ignores everything past 72 bytes
+const PASSWORD_MAX_BYTES: usize = 72;
+const TEXT_MAX_LEN: usize = 255;
+const PIN_MIN_LEN: usize = 4;
+const PIN_MAX_LEN: usize = 8;
+const BADGE_MAX_LEN: usize = 64;
+const SHIFT_NAME_MAX_LEN: usize = 100;
+const ROTATION_MAX_DAYS: u32 = 366;
+const REPORT_MAX_DAYS: i64 = 366;
+const LEAVE_MAX_DAYS: i64 = 366;
+const ACCRUAL_MAX_DAYS: f64 = 366.0;
+
+// Bundled at compile time so kiosks can check passwords offline
+const COMMON_PASSWORDS: &str = include_str!("../data/common_passwords.txt");
+
+// A problem with one input, keyed by the request field name so the
+// frontend can show it next to the right control
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: &'static str, message: impl Into<String>) -> Self {
+        FieldError { field, message: message.into() }
+    }
+}
+
+// Collects every problem instead of stopping at the first one
+#[derive(Debug, Default)]
+pub struct Validator {
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    pub fn username(&mut self, username: &str) -> &mut Self {
+        let len = username.chars().count();
+        
+        if !(USERNAME_MIN_LEN..=USERNAME_MAX_LEN).contains(&len) {
+            self.push("username", format!(
+                "Username must be {} to {} characters long",
+                USERNAME_MIN_LEN, USERNAME_MAX_LEN
+            ));
+        } else if !username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')) {
+            self.push("username", "Username may only contain letters, digits, '.', '_' and '-'");
+        } else if !username.starts_with(|c: char| c.is_ascii_alphanumeric()) {
+            self.push("username", "Username must start with a letter or digit");
+        }
+        
+        self
+    }
+
+    pub fn email(&mut self, email: Option<&str>) -> &mut Self {
+        if let Some(email) = email {
+            if email.len() > TEXT_MAX_LEN || !is_valid_email(email) {
+                self.push("email", "Enter a valid email address");
+            }
+        }
+        
+        self
+    }
+
+    pub fn full_name(&mut self, full_name: Option<&str>) -> &mut Self {
+        if full_name.is_some_and(|name| name.chars().count() > TEXT_MAX_LEN) {
+            self.push("full_name", format!("Full name must be at most {} characters", TEXT_MAX_LEN));
+        }
+        
+        self
+    }
+
+    pub fn password(&mut self, field: &'static str, password: &str, username: &str) -> &mut Self {
+        if password.chars().count() < PASSWORD_MIN_LEN {
+            self.push(field, format!("Password must be at least {} characters long", PASSWORD_MIN_LEN));
+        } else if password.len() > PASSWORD_MAX_BYTES {
+            self.push(field, format!("Password must be at most {} bytes long", PASSWORD_MAX_BYTES));
+        } else if password.eq_ignore_ascii_case(username) {
+            self.push(field, "Password must not match the username");
+        } else if is_common_password(password) {
+            self.push(field, "This password appears in lists of breached passwords; choose another");
+        }
+        
+        self
+    }
+
+    // PINs only guard clock-in/out, so short numeric codes are acceptable
+    pub fn pin(&mut self, pin: Option<&str>) -> &mut Self {
+        if let Some(pin) = pin {
+            if !(PIN_MIN_LEN..=PIN_MAX_LEN).contains(&pin.len()) || !pin.chars().all(|c| c.is_ascii_digit()) {
+                self.push("pin", format!("PIN must be {} to {} digits", PIN_MIN_LEN, PIN_MAX_LEN));
+            }
+        }
+        
+        self
+    }
+
+    pub fn badge_number(&mut self, badge_number: Option<&str>) -> &mut Self {
+        if let Some(badge_number) = badge_number {
+            if badge_number.is_empty()
+                || badge_number.len() > BADGE_MAX_LEN
+                || !badge_number.chars().all(|c| c.is_ascii_alphanumeric())
+            {
+                self.push("badge_number", format!(
+                    "Badge number must be 1 to {} letters or digits",
+                    BADGE_MAX_LEN
+                ));
+            }
+        }
+        
+        self
+    }
+
+    pub fn shift_name(&mut self, name: &str) -> &mut Self {
+        let len = name.chars().count();
+        
+        if !(1..=SHIFT_NAME_MAX_LEN).contains(&len) {
+            self.push("name", format!("Shift name must be 1 to {} characters long", SHIFT_NAME_MAX_LEN));
+        }
+        
+        self
+    }
+
+    // `segments` as laid out for one working day by `Shift::segments_on`
+    pub fn shift_segments(&mut self, segments: &[(NaiveDateTime, NaiveDateTime)]) -> &mut Self {
+        let day = Duration::hours(24);
+        
+        if let Some((starts_at, ends_at)) = segments.first() {
+            if *ends_at - *starts_at >= day {
+                self.push("end_time", "Shift must end at a different time than it starts");
+            }
+        }
+        
+        if let [(first_start, _), (split_start, split_end)] = segments {
+            if *split_end - *split_start >= day {
+                self.push("split_end_time", "Second segment must end at a different time than it starts");
+            } else if *split_end - *first_start > day {
+                self.push("split_start_time", "Second segment must start after the first ends and finish within 24 hours of the shift start");
+            }
+        }
+        
+        self
+    }
+
+    pub fn schedule_pattern(&mut self, pattern: &SchedulePattern) -> &mut Self {
+        match pattern {
+            SchedulePattern::Weekly { weekdays } if weekdays.is_empty() => {
+                self.push("pattern", "Choose at least one weekday");
+            }
+            SchedulePattern::Rotating { days_on, days_off } => {
+                if *days_on == 0 {
+                    self.push("pattern", "A rotation needs at least one working day");
+                } else if days_on.saturating_add(*days_off) > ROTATION_MAX_DAYS {
+                    self.push("pattern", format!("A rotation may last at most {} days", ROTATION_MAX_DAYS));
+                }
+            }
+            SchedulePattern::Weekly { .. } => {}
+        }
+        
+        self
+    }
+
+    pub fn date_range(&mut self, starts_on: NaiveDate, ends_on: Option<NaiveDate>) -> &mut Self {
+        if ends_on.is_some_and(|ends_on| ends_on < starts_on) {
+            self.push("ends_on", "End date must not be before the start date");
+        }
+        
+        self
+    }
+
+    pub fn report_range(&mut self, from: NaiveDate, to: NaiveDate) -> &mut Self {
+        if to < from {
+            self.push("to", "End date must not be before the start date");
+        } else if (to - from).num_days() >= REPORT_MAX_DAYS {
+            self.push("to", format!("A report may cover at most {} days", REPORT_MAX_DAYS));
+        }
+        
+        self
+    }
+
+    pub fn note(&mut self, note: Option<&str>) -> &mut Self {
+        if note.is_some_and(|note| note.chars().count() > TEXT_MAX_LEN) {
+            self.push("note", format!("Note must be at most {} characters", TEXT_MAX_LEN));
+        }
+        
+        self
+    }
+
+    pub fn correction(&mut self, request: &CorrectionRequest, now: NaiveDateTime) -> &mut Self {
+        match request.kind {
+            CorrectionKind::MissingPunch if request.event_type.is_none() => {
+                self.push("event_type", "Choose which punch is missing");
+            }
+            CorrectionKind::MissingPunch if request.event_id.is_some() => {
+                self.push("event_id", "A missing punch does not replace another punch");
+            }
+            CorrectionKind::WrongTime if request.event_id.is_none() => {
+                self.push("event_id", "Choose the punch to correct");
+            }
+            _ => {}
+        }
+        
+        if request.event_time > now {
+            self.push("event_time", "Time must not be in the future");
+        }
+        
+        self
+    }
+
+    pub fn reason(&mut self, reason: &str) -> &mut Self {
+        if reason.trim().is_empty() {
+            self.push("reason", "A reason is required");
+        } else if reason.chars().count() > TEXT_MAX_LEN {
+            self.push("reason", format!("Reason must be at most {} characters", TEXT_MAX_LEN));
+        }
+        
+        self
+    }
+
+    pub fn leave(&mut self, starts_on: NaiveDate, ends_on: NaiveDate, reason: Option<&str>) -> &mut Self {
+        if ends_on < starts_on {
+            self.push("ends_on", "End date must not be before the start date");
+        } else if (ends_on - starts_on).num_days() >= LEAVE_MAX_DAYS {
+            self.push("ends_on", format!("Leave may cover at most {} days", LEAVE_MAX_DAYS));
+        }
+        
+        if reason.is_some_and(|reason| reason.chars().count() > TEXT_MAX_LEN) {
+            self.push("reason", format!("Reason must be at most {} characters", TEXT_MAX_LEN));
+        }
+        
+        self
+    }
+
+    pub fn leave_accrual(&mut self, leave_type: LeaveType, days: f64) -> &mut Self {
+        if !leave_type.is_accrued() {
+            self.push("leave_type", "Unpaid leave has no balance");
+        }
+        
+        if !days.is_finite() || days == 0.0 || days.abs() > ACCRUAL_MAX_DAYS {
+            self.push("days", format!("Days must be non-zero and at most {} either way", ACCRUAL_MAX_DAYS));
+        }
+        
+        self
+    }
+
+    pub fn push(&mut self, field: &'static str, message: impl Into<String>) {
+        self.errors.push(FieldError::new(field, message));
+    }
+
+    pub fn finish(&mut self) -> Result<(), Vec<FieldError>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+}
+
+// Deliberately loose: one '@', a non-empty local part and a dotted domain.
+// Deliverability can only be proven by sending mail.
+fn is_valid_email(email: &str) -> bool {
+    let Some((local, domain)) = email.split_once('@') else {
+        return false;
+    };
+    
+    !local.is_empty()
+        && !domain.contains('@')
+        && !email.chars().any(char::is_whitespace)
+        && domain.split('.').count() >= 2
+        && domain.split('.').all(|label| !label.is_empty())
+}
+
+fn is_common_password(password: &str) -> bool {
+    COMMON_PASSWORDS
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .any(|common| common.eq_ignore_ascii_case(password))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveTime;
+
+    fn errors(validator: &mut Validator) -> Vec<FieldError> {
+        validator.finish().err().unwrap_or_default()
+    }
+
+    fn username_ok(username: &str) -> bool {
+        Validator::default().username(username).finish().is_ok()
+    }
+
+    fn password_errors(password: &str, username: &str) -> Vec<FieldError> {
+        errors(Validator::default().password("password", password, username))
+    }
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, day)
+            .unwrap()
+            .and_time(NaiveTime::from_hms_opt(hour, minute, 0).unwrap())
+    }
+
+    fn segment_fields(segments: &[(NaiveDateTime, NaiveDateTime)]) -> Vec<&'static str> {
+        errors(Validator::default().shift_segments(segments))
+            .into_iter()
+            .map(|e| e.field)
+            .collect()
+    }
+
+    #[test]
+    fn username_length_boundaries() {
+        assert!(!username_ok("ab"));
+        assert!(username_ok("abc"));
+        assert!(username_ok(&"a".repeat(32)));
+        assert!(!username_ok(&"a".repeat(33)));
+    }
+
+    #[test]
+    fn username_charset() {
+        assert!(username_ok("jane.doe_2-b"));
+        assert!(!username_ok("jane doe"));
+        assert!(!username_ok("jane@doe"));
+        // Length is counted in characters, but only ASCII is allowed
+        assert!(!username_ok("jöhn"));
+    }
+
+    #[test]
+    fn username_must_start_with_a_letter_or_digit() {
+        assert!(username_ok("7eleven"));
+        assert!(!username_ok(".jane"));
+        assert!(!username_ok("_jane"));
+        assert!(!username_ok("-jane"));
+    }
+
+    #[test]
+    fn password_length_is_capped_in_bytes() {
+        // 36 two-byte characters fill the 72 bytes exactly
+        assert!(password_errors(&"é".repeat(36), "jane").is_empty());
+        assert_eq!(password_errors(&"é".repeat(37), "jane").len(), 1);
+        assert_eq!(password_errors(&"€".repeat(25), "jane").len(), 1);
+        assert!(password_errors(&"x7".repeat(36), "jane").is_empty());
+    }
+
+    #[test]
+    fn password_minimum_counts_characters() {
+        // Eight characters but sixteen bytes
+        assert!(password_errors(&"é".repeat(8), "jane").is_empty());
+        assert_eq!(password_errors(&"é".repeat(7), "jane").len(), 1);
+    }
+
+    #[test]
+    fn password_must_not_match_the_username() {
+        let errors = password_errors("JaneDoe2024", "janedoe2024");
+        
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].message, "Password must not match the username");
+        assert!(password_errors("JaneDoe2024!", "janedoe2024").is_empty());
+    }
+
+    #[test]
+    fn common_passwords_are_rejected_in_any_case() {
+        for password in ["password1", "PASSWORD1", "Password12"] {
+            let errors = password_errors(password, "jane");
+            assert_eq!(errors.len(), 1, "{}", password);
+            assert!(errors[0].message.contains("breached"), "{}", password);
+        }
+        
+        // Comment lines in the list are not passwords
+        assert!(!is_common_password("# Frequently breached passwords, one per line, compared case-insensitively."));
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let errors = errors(
+            Validator::default()
+                .username("x")
+                .email(Some("not-an-email"))
+                .password("password", "short", "x")
+                .pin(Some("12ab")),
+        );
+        let fields: Vec<_> = errors.iter().map(|e| e.field).collect();
+        
+        assert_eq!(fields, ["username", "email", "password", "pin"]);
+    }
+
+    #[test]
+    fn finish_starts_over() {
+        let mut validator = Validator::default();
+        validator.username("x");
+        
+        assert!(validator.finish().is_err());
+        assert!(validator.finish().is_ok());
+    }
+
+    #[test]
+    fn day_and_overnight_shifts_are_valid() {
+        assert!(segment_fields(&[(at(4, 9, 0), at(4, 17, 0))]).is_empty());
+        assert!(segment_fields(&[(at(4, 22, 0), at(5, 6, 0))]).is_empty());
+    }
+
+    #[test]
+    fn shift_must_not_last_a_full_day() {
+        assert_eq!(segment_fields(&[(at(4, 9, 0), at(5, 9, 0))]), ["end_time"]);
+    }
+
+    #[test]
+    fn split_shifts_are_valid() {
+        let segments = [(at(4, 7, 0), at(4, 11, 0)), (at(4, 16, 0), at(4, 20, 0))];
+        assert!(segment_fields(&segments).is_empty());
+        
+        // The second half may run past midnight
+        let segments = [(at(4, 14, 0), at(4, 18, 0)), (at(4, 22, 0), at(5, 2, 0))];
+        assert!(segment_fields(&segments).is_empty());
+    }
+
+    #[test]
+    fn split_shift_must_finish_within_a_day() {
+        let segments = [(at(4, 9, 0), at(4, 13, 0)), (at(5, 8, 0), at(5, 10, 0))];
+        assert_eq!(segment_fields(&segments), ["split_start_time"]);
+        
+        let segments = [(at(4, 9, 0), at(4, 13, 0)), (at(4, 14, 0), at(5, 14, 0))];
+        assert_eq!(segment_fields(&segments), ["split_end_time"]);
+    }
+}
@@ -0,0 +1,40 @@
+// src/lib/scanner.ts
+
+// Badge readers that act as keyboards type a whole badge within a few
+// milliseconds per key; people rarely manage less than ~80ms between keys.
+// Must match MAX_KEY_INTERVAL_MS in src-tauri/src/scanner.rs
+const MAX_KEY_INTERVAL_MS = 35
+
+// Tells which keys belong to a reader's burst so the keydown handler can
+// keep them from the page in the same tick. Detection itself happens in the
+// backend's `scanner_key`, which sees the same keys and timestamps; this
+// only mirrors its timing rule, since an awaited answer would come too late
+// to cancel the key.
+export class BurstTracker {
+  private lastKeyAt: number | null = null
+
+  // `key` uses KeyboardEvent.key names, e.g. "7", "A" or "Enter". Returns
+  // whether it follows the previous key fast enough to be part of a scan.
+  key(key: string, atMs: number): boolean {
+    // Readers press Shift for capitals; it says nothing about timing
+    if (key === 'Shift' || key === 'CapsLock') {
+      return this.lastKeyAt !== null
+    }
+
+    const inBurst = this.lastKeyAt !== null && atMs - this.lastKeyAt <= MAX_KEY_INTERVAL_MS
+    this.lastKeyAt = key === 'Enter' ? null : atMs
+    return inBurst
+  }
+}
+
+// Keys typed into a form belong to it, and password keystrokes are never
+// looked at
+export function isEditing(target: EventTarget | null): boolean {
+  if (!(target instanceof HTMLElement)) {
+    return false
+  }
+  return target instanceof HTMLInputElement
+    || target instanceof HTMLTextAreaElement
+    || target instanceof HTMLSelectElement
+    || target.isContentEditable
+}
@@ -0,0 +1,14 @@
+// src/lib/session.ts
+
+import { ref, watch } from 'vue'
+
+// Session token from `login_user`, kept for the lifetime of the window
+export const sessionToken = ref<string | null>(sessionStorage.getItem('session_token'))
+
+watch(sessionToken, (token) => {
+  if (token) {
+    sessionStorage.setItem('session_token', token)
+  } else {
+    sessionStorage.removeItem('session_token')
+  }
+})